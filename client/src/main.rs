@@ -42,7 +42,10 @@ use my_node_runtime::{
     substratee_registry::{Enclave, Request},
     AccountId, BalancesCall, BlockNumber, Call, Event, Hash, Header, Moment, Signature,
 };
-use sp_core::{crypto::Ss58Codec, hashing::blake2_256, sr25519 as sr25519_core, Pair, H256};
+use sp_core::{
+    crypto::Ss58Codec, ed25519 as ed25519_core, hashing::blake2_256, sr25519 as sr25519_core, Pair,
+    H256,
+};
 use sp_runtime::{
     traits::{IdentifyAccount, Verify},
     MultiSignature,
@@ -60,11 +63,16 @@ use substrate_api_client::{
     Api, XtStatus,
 };
 
+use encointer_balances::BalanceEntry;
 use encointer_currencies::{CurrencyIdentifier, Degree, Location};
 use encointer_scheduler::{CeremonyIndexType, CeremonyPhaseType};
 
 use substratee_stf::{
-    cli::get_identifiers, Getter, ShardIdentifier, TrustedCallSigned, TrustedOperation,
+    balance_to_native,
+    cli::{apply_demurrage, get_demurrage_per_block, get_identifiers},
+    keys::proof_keys,
+    BalanceType, CallConfirmation, Getter, Proxy, ShardIdentifier, StateProof, StfEvent,
+    TrustedCallSigned, TrustedGetter, TrustedGetterSigned, TrustedOperation,
 };
 use substratee_worker_api::Api as WorkerApi;
 
@@ -291,6 +299,89 @@ fn main() {
                     Ok(())
                 }),
         )
+        .add_cmd(
+            Command::new("shield-funds")
+                .description("move funds from an on-chain account to an incognito account")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("from")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("on-chain sender's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("incognito recipient's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("amount")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("U128")
+                                .help("amount of community currency to be shielded"),
+                        )
+                        .arg(
+                            Arg::with_name("shard")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("STRING")
+                                .help(
+                                    "shard (= currency identifier) to shield into, base58 encoded",
+                                ),
+                        )
+                })
+                .runner(|_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_from = matches.value_of("from").unwrap();
+                    let arg_to = matches.value_of("to").unwrap();
+                    let amount = u128::from_str_radix(matches.value_of("amount").unwrap(), 10)
+                        .expect("amount can be converted to u128");
+                    let from = get_pair_from_str(arg_from);
+                    let to = get_accountid_from_str(arg_to);
+                    let shard = ShardIdentifier::decode(
+                        &mut &matches
+                            .value_of("shard")
+                            .unwrap()
+                            .from_base58()
+                            .expect("shard argument must be base58 encoded")[..],
+                    )
+                    .expect("shard must be 32 bytes");
+
+                    let shielding_pubkey = get_worker_api(matches).get_rsa_pubkey().unwrap();
+                    let mut to_encrypted: Vec<u8> = Vec::new();
+                    shielding_pubkey
+                        .encrypt_buffer(&to.encode(), &mut to_encrypted)
+                        .unwrap();
+
+                    // the registry locks native units, the shard credits the same amount of community currency
+                    let native_amount =
+                        match BalanceType::checked_from_num(amount).and_then(balance_to_native) {
+                            Some(native_amount) => native_amount,
+                            None => {
+                                error!("amount {} is out of range", amount);
+                                return Ok(());
+                            }
+                        };
+                    let api = get_chain_api(matches).set_signer(sr25519_core::Pair::from(from));
+                    let xt: UncheckedExtrinsicV4<_> = compose_extrinsic!(
+                        api,
+                        "SubstrateeRegistry",
+                        "shield_funds",
+                        to_encrypted,
+                        native_amount,
+                        shard
+                    );
+                    let tx_hash = api
+                        .send_extrinsic(xt.hex_encode(), XtStatus::InBlock)
+                        .unwrap();
+                    println!("[+] Transaction got included. Hash: {:?}\n", tx_hash);
+                    Ok(())
+                }),
+        )
         .add_cmd(
             Command::new("list-workers")
                 .description("query enclave registry and list all workers")
@@ -342,6 +433,74 @@ fn main() {
                     Ok(())
                 }),
         )
+        .add_cmd(
+            Command::new("sidechain-block")
+                .description("get a sidechain block of a shard from the worker")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("mrenclave")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("STRING")
+                                .help("MRENCLAVE of the signing workers, base58 encoded"),
+                        )
+                        .arg(
+                            Arg::with_name("shard")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("STRING")
+                                .help("shard (= currency identifier), base58 encoded"),
+                        )
+                        .arg(
+                            Arg::with_name("number")
+                                .long("number")
+                                .takes_value(true)
+                                .value_name("U64")
+                                .help("block number, defaults to the latest block"),
+                        )
+                })
+                .runner(|_args: &str, matches: &ArgMatches<'_>| {
+                    let mut mrenclave = [0u8; 32];
+                    mrenclave.copy_from_slice(
+                        &matches
+                            .value_of("mrenclave")
+                            .unwrap()
+                            .from_base58()
+                            .expect("mrenclave argument must be base58 encoded"),
+                    );
+                    let shard = ShardIdentifier::decode(
+                        &mut &matches
+                            .value_of("shard")
+                            .unwrap()
+                            .from_base58()
+                            .expect("shard argument must be base58 encoded")[..],
+                    )
+                    .unwrap();
+                    let number = matches
+                        .value_of("number")
+                        .map(|n| n.parse::<u64>().expect("number must be a u64"));
+                    let enclave_signers = get_enclave_signers(&get_chain_api(matches), &mrenclave);
+                    let block = get_worker_api(matches)
+                        .get_sidechain_block(&shard, number, &enclave_signers)
+                        .expect("getting sidechain block from worker API failed");
+                    match block {
+                        Some(signed) => {
+                            let b = signed.block;
+                            println!("block {} ({:?})", b.block_number, b.hash());
+                            println!("   parent: {:?}", b.parent_hash);
+                            println!("   parentchain block: {}", b.parentchain_block_number);
+                            println!("   state hash: {:?}", b.state_hash);
+                            println!("   signer: {}", b.signer.to_ss58check());
+                            for call_hash in b.call_hashes.iter() {
+                                println!("   call: {:?}", call_hash);
+                            }
+                        }
+                        None => println!("no sidechain block found"),
+                    }
+                    Ok(())
+                }),
+        )
         // start encointer stuff
         .add_cmd(
             Command::new("new-currency")
@@ -513,18 +672,96 @@ fn perform_trusted_operation(matches: &ArgMatches<'_>, top: &TrustedOperation) -
 
 fn get_state(matches: &ArgMatches<'_>, getter: Getter) -> Option<Vec<u8>> {
     let worker_api = get_worker_api(matches);
-    let (_mrenclave, shard) = get_identifiers(matches);
+    let (mrenclave, shard) = get_identifiers(matches);
+    let enclave_signers = get_enclave_signers(&get_chain_api(matches), &mrenclave);
     debug!("calling workerapi to get state value, {:?}", getter);
+    if let Getter::trusted(TrustedGetterSigned {
+        getter: TrustedGetter::with_proof(inner),
+        ..
+    }) = &getter
+    {
+        let inner = inner.as_ref().clone();
+        let (block_number, proof) = worker_api
+            .get_proven_stf_state(getter, &shard, &enclave_signers)
+            .expect("getting proven value from worker API failed");
+        let chain_api = get_chain_api(matches);
+        match get_latest_confirmation(&chain_api, &shard) {
+            Some(confirmation) if confirmation.state_hash == proof.state_root => {
+                println!("state root {:?} is confirmed on chain", proof.state_root);
+            }
+            _ => {
+                error!(
+                    "state root {:?} is not the latest one committed on chain. Retry once it is",
+                    proof.state_root
+                );
+                return None;
+            }
+        }
+        for (key, value, _) in proof.entries.iter() {
+            println!(
+                "   proven 0x{} = 0x{}",
+                hex::encode(key),
+                hex::encode(value)
+            );
+        }
+        for (key, _) in proof.absent.iter() {
+            println!("   proven 0x{} to be absent", hex::encode(key));
+        }
+        return proven_value(&chain_api, &inner, block_number, &proof);
+    }
     let ret = worker_api
-        .get_stf_state(getter, &shard)
+        .get_stf_state(getter, &shard, &enclave_signers)
         .expect("getting value from worker API failed");
-    // strip whitespace padding through decoding
-    if let Ok(vd) = Decode::decode(&mut ret.as_slice()) {
-        debug!("decoded return value: {:?} ", vd);
-        vd
-    } else {
-        debug!("decoding failed");
-        None
+    debug!("verified return value: {:?} ", ret);
+    ret
+}
+
+/// derives the value of `getter` from the proven storage entries. The proof has to cover
+/// exactly the keys of `proof_keys`, each with its value or as absent
+fn proven_value(
+    api: &Api<sr25519::Pair>,
+    getter: &TrustedGetter,
+    block_number: BlockNumber,
+    proof: &StateProof,
+) -> Option<Vec<u8>> {
+    let keys = proof_keys(getter);
+    if proof.entries.len() + proof.absent.len() != keys.len() {
+        error!("state proof doesn't cover the keys of the getter");
+        return None;
+    }
+    let values = keys
+        .iter()
+        .map(|key| proof.value(key))
+        .collect::<Option<Vec<_>>>()?;
+    match getter {
+        TrustedGetter::balance(_, cid) => match values.first()? {
+            Some(value) => {
+                let entry = BalanceEntry::<BlockNumber>::decode(&mut value.as_slice()).ok()?;
+                // the enclave applies demurrage when the balance is read, the proof covers the raw entry
+                let demurrage = get_demurrage_per_block(api, *cid);
+                Some(apply_demurrage(entry, block_number, demurrage).encode())
+            }
+            None => Some(BalanceType::from_num(0).encode()),
+        },
+        TrustedGetter::nonce(_) => match values.first()? {
+            // the nonce is the first field of the `AccountInfo`
+            Some(value) => u32::decode(&mut value.as_slice()).ok().map(|n| n.encode()),
+            None => Some(0u32.encode()),
+        },
+        TrustedGetter::proxies(_, _) => match values.first()? {
+            Some(value) => Some(value.to_vec()),
+            None => Some(Vec::<Proxy>::new().encode()),
+        },
+        TrustedGetter::events(_, _, _, _) => {
+            // events which are absent have been dropped or not been deposited yet
+            let events = values
+                .iter()
+                .flatten()
+                .map(|value| StfEvent::decode(&mut value.as_slice()).ok())
+                .collect::<Option<Vec<_>>>()?;
+            Some(events.encode())
+        }
+        _ => None,
     }
 }
 
@@ -534,11 +771,24 @@ fn send_request(matches: &ArgMatches<'_>, call: TrustedCallSigned) -> Option<Vec
     let shielding_pubkey = worker_api.get_rsa_pubkey().unwrap();
 
     let call_encoded = call.encode();
+    // the key of the receipt the enclave records for the call
+    let call_hash = H256::from(blake2_256(&call_encoded));
+    println!("call hash: {:?}", call_hash);
     let mut call_encrypted: Vec<u8> = Vec::new();
     shielding_pubkey
         .encrypt_buffer(&call_encoded, &mut call_encrypted)
         .unwrap();
 
+    if matches.is_present("direct") {
+        // no extrinsic, so no public account is linked to the call
+        let (_mrenclave, shard) = get_identifiers(matches);
+        worker_api
+            .submit_trusted_call(&shard, call_encrypted)
+            .expect("worker rejected the call");
+        println!("call pending. It is executed in nonce order in one of the next sidechain blocks");
+        return Some(call_hash.encode());
+    }
+
     let arg_signer = matches.value_of("xt-signer").unwrap();
     let signer = get_pair_from_str(arg_signer);
     let _chain_api = chain_api.set_signer(sr25519_core::Pair::from(signer));
@@ -593,11 +843,10 @@ fn send_request(matches: &ArgMatches<'_>, call: TrustedCallSigned) -> Option<Vec
             )
             .unwrap()
             .unwrap();
-        let expected = H256::from(blake2_256(&call_encoded));
         info!("callConfirmed event received");
-        debug!("Expected stf call Hash: {:?}", expected);
+        debug!("Expected stf call Hash: {:?}", call_hash);
         debug!("Confirmed stf call Hash: {:?}", ret.payload);
-        if ret.payload == expected {
+        if ret.payload == call_hash {
             return Some(ret.payload.encode());
         }
     }
@@ -808,6 +1057,29 @@ fn get_enclave(api: &Api<sr25519::Pair>, eindex: u64) -> Option<Enclave<AccountI
     api.get_storage_map("SubstrateeRegistry", "EnclaveRegistry", eindex, None)
 }
 
+/// the latest confirmation the enclave has sent for `shard`, either of a call or of a sidechain
+/// block. The registry keeps its payload in `LatestIPFSHash`
+fn get_latest_confirmation(
+    api: &Api<sr25519::Pair>,
+    shard: &ShardIdentifier,
+) -> Option<CallConfirmation> {
+    let payload: Vec<u8> =
+        api.get_storage_map("SubstrateeRegistry", "LatestIPFSHash", shard, None)?;
+    CallConfirmation::decode(&mut payload.as_slice()).ok()
+}
+
+// signing keys of all registered enclaves with the given MRENCLAVE
+fn get_enclave_signers(
+    api: &Api<sr25519::Pair>,
+    mrenclave: &[u8; 32],
+) -> Vec<ed25519_core::Public> {
+    (1..=get_enclave_count(api))
+        .filter_map(|eindex| get_enclave(api, eindex))
+        .filter(|enclave| &enclave.mr_enclave == mrenclave)
+        .map(|enclave| ed25519_core::Public::from_raw(enclave.pubkey.into()))
+        .collect()
+}
+
 // encointer stuff
 
 fn get_currency_identifiers(api: &Api<sr25519::Pair>) -> Option<Vec<CurrencyIdentifier>> {
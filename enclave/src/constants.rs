@@ -18,9 +18,28 @@
 pub const RSA3072_SEALED_KEY_FILE: &str = "rsa3072_key_sealed.bin";
 pub const SEALED_SIGNER_SEED_FILE: &str = "ed25519_key_sealed.bin";
 pub const ENCRYPTED_STATE_FILE: &str = "state.bin";
+/// state of the latest sidechain block of a shard
+pub const BLOCK_STATE_FILE: &str = "block_state.bin";
+/// state of a shard the latest confirmation on the parentchain is about, either of a call or of
+/// a sidechain block. Getters are proven against it
+pub const COMMITTED_STATE_FILE: &str = "committed_state.bin";
 pub const SHARDS_PATH: &str = "./shards";
 pub const AES_KEY_FILE_AND_INIT_V: &str = "aes_key_sealed.bin";
 pub const CHAIN_RELAY_DB: &str = "chain_relay_db.bin";
+pub const TOP_POOL_DB: &str = "top_pool_sealed.bin";
+/// max number of calls waiting in the trusted operation pool
+pub const TOP_POOL_SIZE: usize = 256;
+pub const MAX_PENDING_CALLS_PER_ACCOUNT: usize = 16;
+/// number of block production rounds a call may wait in the trusted operation pool for its
+/// nonce before it is evicted, about an hour at the worker's sidechain block interval. Rounds
+/// only pass while the worker is running, so a restart doesn't expire the calls it imported
+pub const TOP_POOL_TTL: u64 = 12_000;
+pub const SIDECHAIN_DB: &str = "sidechain_sealed.bin";
+/// every n-th sidechain block of a shard is committed on the parentchain
+pub const SIDECHAIN_COMMITMENT_INTERVAL: u64 = 10;
+/// a shard with uncommitted sidechain blocks is committed at least every n parentchain blocks
+pub const SIDECHAIN_COMMITMENT_PERIOD: u32 = 10;
+pub const OUTBOX_DB: &str = "outbox_sealed.bin";
 
 pub const RA_DUMP_CERT_DER_FILE: &str = "ra_dump_cert.der";
 
@@ -40,7 +59,14 @@ pub static SUBSRATEE_REGISTRY_MODULE: u8 = 8u8;
 pub static REGISTER_ENCLAVE: u8 = 0u8;
 //pub static UNREGISTER_ENCLAVE: u8 = 1u8;
 pub static CALL_WORKER: u8 = 2u8;
+// confirm_call(shard: ShardIdentifier, call_hash: H256, confirmation: Vec<u8>) with the
+// encoded `substratee_stf::CallConfirmation`. The registry keeps the latest payload of each shard
+// in `LatestIPFSHash`, which is what clients check getter proofs against. Failed calls are
+// confirmed as well, and sidechain blocks are committed with their block hash as call hash.
+// Calls sent to the worker directly are not confirmed one by one, they are committed with the
+// sidechain block executing them
 pub static CALL_CONFIRMED: u8 = 3u8;
+pub static SHIELD_FUNDS: u8 = 4u8;
 
 // bump this to be consistent with SubstraTEE-node runtime
 pub static RUNTIME_SPEC_VERSION: u32 = 6;
@@ -36,8 +36,12 @@ use sgx_tunittest::*;
 use sgx_types::{sgx_epid_group_id_t, sgx_status_t, sgx_target_info_t, size_t, SgxResult};
 
 use substrate_api_client::{compose_extrinsic_offline, utils::storage_key};
-use substratee_node_primitives::CallWorkerFn;
-use substratee_stf::{Getter, ShardIdentifier, Stf, TrustedCallSigned};
+use substratee_node_primitives::{funds_shielded, CallWorkerFn, ShieldFundsFn};
+use substratee_stf::{
+    AccountId, BlockNumber, CallConfirmation, CallReceipt, Getter, ShardIdentifier,
+    SignedSidechainBlock, State as StfState, Stf, StfError, TrustedCallSigned, TrustedGetter,
+    TrustedGetterSigned, TrustedReturnValue,
+};
 
 use codec::{Decode, Encode};
 use sp_core::{crypto::Pair, hashing::blake2_256};
@@ -51,12 +55,13 @@ use std::string::String;
 use std::vec::Vec;
 
 use ipfs::IpfsContent;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::File;
 use std::io::Read;
 use utils::write_slice_and_whitespace_pad;
 
-use crate::constants::CALL_WORKER;
+use crate::constants::{CALL_WORKER, MAX_PENDING_CALLS_PER_ACCOUNT, SHIELD_FUNDS};
+use crate::top_pool::{PendingCall, TrustedOperationPool};
 use crate::utils::UnwrapOrSgxErrorUnexpected;
 use chain_relay::{
     storage_proof::{StorageProof, StorageProofChecker},
@@ -65,7 +70,11 @@ use chain_relay::{
 use sp_runtime::OpaqueExtrinsic;
 use sp_runtime::{generic::SignedBlock, traits::Header as HeaderT};
 use substrate_api_client::extrinsic::xt_primitives::UncheckedExtrinsicV4;
-use substratee_stf::sgx::{shards_key_hash, storage_hashes_to_update_per_shard, OpaqueCall};
+use substratee_stf::keys::proof_keys;
+use substratee_stf::sgx::{
+    prove_state, shards_key_hash, storage_hashes_to_update_per_shard, OpaqueCall, StateTransaction,
+};
+use substratee_stf::sgx_tests;
 
 mod aes;
 mod attestation;
@@ -73,8 +82,11 @@ mod constants;
 mod ed25519;
 mod io;
 mod ipfs;
+mod outbox;
 mod rsa3072;
+mod sidechain;
 mod state;
+mod top_pool;
 mod utils;
 
 pub mod cert;
@@ -169,9 +181,10 @@ pub unsafe extern "C" fn get_ecc_signing_pubkey(pubkey: *mut u8, pubkey_size: u3
     sgx_status_t::SGX_SUCCESS
 }
 
+/// composes and signs extrinsics for the calls waiting in the outbox, as many as fit into
+/// `extrinsics_slice`. The others stay in the outbox for the next round
 fn stf_post_actions(
     mut validator: LightValidation,
-    calls_buffer: Vec<OpaqueCall>,
     extrinsics_slice: &mut [u8],
     mut nonce: u32,
 ) -> SgxResult<()> {
@@ -179,24 +192,38 @@ fn stf_post_actions(
     let signer = ed25519::unseal_pair()?;
     debug!("Restored ECC pubkey: {:?}", signer.public());
 
-    let extrinsics_buffer: Vec<Vec<u8>> = calls_buffer
-        .into_iter()
-        .map(|call| {
-            let xt = compose_extrinsic_offline!(
-                signer.clone(),
-                call,
-                nonce,
-                Era::Immortal,
-                validator.genesis_hash(validator.num_relays).unwrap(),
-                validator.genesis_hash(validator.num_relays).unwrap(),
-                RUNTIME_SPEC_VERSION,
-                RUNTIME_TRANSACTION_VERSION
-            )
-            .encode();
-            nonce += 1;
-            xt
-        })
-        .collect();
+    let mut pending = outbox::load()?.into_iter();
+    let mut remaining = Vec::new();
+    let mut extrinsics_buffer: Vec<Vec<u8>> = Vec::new();
+    // the length prefix of the encoded extrinsics takes at most 5 bytes
+    let mut size = 5;
+    while let Some(call) = pending.next() {
+        let xt = compose_extrinsic_offline!(
+            signer.clone(),
+            call.clone(),
+            nonce,
+            Era::Immortal,
+            validator.genesis_hash(validator.num_relays).unwrap(),
+            validator.genesis_hash(validator.num_relays).unwrap(),
+            RUNTIME_SPEC_VERSION,
+            RUNTIME_TRANSACTION_VERSION
+        )
+        .encode();
+        let xt_size = xt.encode().len();
+        if size + xt_size > extrinsics_slice.len() {
+            if extrinsics_buffer.is_empty() {
+                // it would never fit
+                error!("dropping extrinsic of {} bytes", xt_size);
+                continue;
+            }
+            remaining.push(call);
+            remaining.extend(pending.by_ref());
+            break;
+        }
+        size += xt_size;
+        nonce += 1;
+        extrinsics_buffer.push(xt);
+    }
 
     for xt in extrinsics_buffer.iter() {
         validator
@@ -209,6 +236,7 @@ fn stf_post_actions(
 
     write_slice_and_whitespace_pad(extrinsics_slice, extrinsics_buffer.encode());
 
+    outbox::seal(&remaining)?;
     io::light_validation::seal(validator)?;
 
     Ok(())
@@ -228,12 +256,42 @@ pub unsafe extern "C" fn get_state(
     let value_slice = slice::from_raw_parts_mut(value, value_size as usize);
     let getter = Getter::decode(&mut trusted_op_slice).unwrap();
 
+    let validator = match io::light_validation::unseal() {
+        Ok(val) => val,
+        Err(e) => return e,
+    };
+
+    let latest_header = validator.latest_header(validator.num_relays).unwrap();
+
+    if let Getter::public(public_getter) = &getter {
+        if public_getter.currency() != &shard {
+            error!(
+                "public getter queries another currency than shard {:?}",
+                shard
+            );
+            return sgx_status_t::SGX_ERROR_UNEXPECTED;
+        }
+    }
+
     if let Getter::trusted(trusted_getter_signed) = getter.clone() {
         debug!("verifying signature of TrustedGetterSigned");
-        if let false = trusted_getter_signed.verify_signature() {
+        let mrenclave = match attestation::get_mrenclave_of_self() {
+            Ok(m) => m,
+            Err(e) => return e,
+        };
+        if let false = trusted_getter_signed.verify_signature(&mrenclave.m, &shard) {
             error!("bad signature");
             return sgx_status_t::SGX_ERROR_UNEXPECTED;
         }
+        if !trusted_getter_signed.is_valid_at(latest_header.number) {
+            error!(
+                "getter is only valid for blocks {}..={}, latest block is {}",
+                trusted_getter_signed.valid_from,
+                trusted_getter_signed.valid_until,
+                latest_header.number
+            );
+            return sgx_status_t::SGX_ERROR_UNEXPECTED;
+        }
     }
 
     if !state::exists(&shard) {
@@ -243,49 +301,157 @@ pub unsafe extern "C" fn get_state(
         }
     }
 
-    let mut state = match state::load(&shard) {
-        Ok(s) => s,
-        Err(status) => return status,
-    };
-
-    let validator = match io::light_validation::unseal() {
-        Ok(val) => val,
-        Err(e) => return e,
-    };
-
-    let latest_header = validator.latest_header(validator.num_relays).unwrap();
-
-    // FIXME: not sure we will ever need this as we are querying trusted state, not onchain state
-    // i.e. demurrage could be correctly applied with this, but the client could do that too.
-    debug!("Update STF storage!");
-    let requests: Vec<WorkerRequest> = Stf::get_storage_hashes_to_update_for_getter(&getter)
-        .into_iter()
-        .map(|key| WorkerRequest::ChainStorage(key, Some(latest_header.hash())))
-        .collect();
-
-    if !requests.is_empty() {
-        let responses: Vec<WorkerResponse<Vec<u8>>> = match worker_request(requests) {
-            Ok(resp) => resp,
-            Err(e) => return e,
+    // proofs are against the state of the latest confirmation of the shard, whose state hash
+    // clients find on chain. They derive the value from the proven entries, so it isn't
+    // returned a second time
+    let (value_opt, proof) = if let Getter::trusted(TrustedGetterSigned {
+        getter: TrustedGetter::with_proof(inner),
+        ..
+    }) = &getter
+    {
+        let state = match state::load_committed(&shard) {
+            Ok(Some(s)) => s,
+            Ok(None) => {
+                error!("shard {:?} has no committed state yet", shard);
+                return sgx_status_t::SGX_ERROR_UNEXPECTED;
+            }
+            Err(status) => return status,
         };
-
-        let update_map = match verify_worker_responses(responses, latest_header) {
-            Ok(map) => map,
-            Err(e) => return e,
+        (None, Some(prove_state(&state, &proof_keys(inner))))
+    } else {
+        let mut state = match state::load(&shard) {
+            Ok(s) => s,
+            Err(status) => return status,
         };
 
-        Stf::update_storage(&mut state, &update_map);
-    }
+        // FIXME: not sure we will ever need this as we are querying trusted state, not onchain state
+        // i.e. demurrage could be correctly applied with this, but the client could do that too.
+        debug!("Update STF storage!");
+        let requests: Vec<WorkerRequest> = Stf::get_storage_hashes_to_update_for_getter(&getter)
+            .into_iter()
+            .map(|key| WorkerRequest::ChainStorage(key, Some(latest_header.hash())))
+            .collect();
+
+        if !requests.is_empty() {
+            let responses: Vec<WorkerResponse<Vec<u8>>> = match worker_request(requests) {
+                Ok(resp) => resp,
+                Err(e) => return e,
+            };
+
+            let update_map = match verify_worker_responses(responses, latest_header.clone()) {
+                Ok(map) => map,
+                Err(e) => return e,
+            };
+
+            Stf::update_storage(&mut state, &update_map);
+        }
+        // getters are evaluated at the latest verified block, i.e. balances include demurrage up to it
+        Stf::update_block_number(&mut state, latest_header.number);
+
+        debug!("calling into STF to get state");
+        let value = match &getter {
+            Getter::trusted(TrustedGetterSigned {
+                getter: TrustedGetter::pending_calls(who, _),
+                ..
+            }) => {
+                // pending calls live in the enclave, not in the shard state
+                let pool = match top_pool::load() {
+                    Ok(pool) => pool,
+                    Err(status) => return status,
+                };
+                let pending: Vec<TrustedCallSigned> = pool
+                    .pending_of(&shard, who)
+                    .into_iter()
+                    .map(|p| p.call)
+                    .collect();
+                Some(pending.encode())
+            }
+            _ => Stf::get_state(&mut state, getter.clone()),
+        };
+        (value, None)
+    };
 
-    debug!("calling into STF to get state");
-    let value_opt = Stf::get_state(&mut state, getter);
+    debug!("signing getter result");
+    let signer = match ed25519::unseal_pair() {
+        Ok(pair) => pair,
+        Err(status) => return status,
+    };
+    let return_value = TrustedReturnValue::sign(
+        &signer,
+        value_opt,
+        shard,
+        latest_header.number,
+        latest_header.hash(),
+        &getter,
+        proof,
+    );
 
+    let return_value = return_value.encode();
+    if return_value.len() > value_slice.len() {
+        error!(
+            "getter result of {}B does not fit into buffer of {}B",
+            return_value.len(),
+            value_slice.len()
+        );
+        return sgx_status_t::SGX_ERROR_UNEXPECTED;
+    }
     debug!("returning getter result");
-    write_slice_and_whitespace_pad(value_slice, value_opt.encode());
+    write_slice_and_whitespace_pad(value_slice, return_value);
 
     sgx_status_t::SGX_SUCCESS
 }
 
+/// adds a trusted call which has been sent to the worker directly instead of through a
+/// `call_worker` extrinsic to the operation pool. It is executed in a sidechain block once the
+/// nonce of its signer reaches it
+#[no_mangle]
+pub unsafe extern "C" fn submit_trusted_call(
+    cyphertext: *const u8,
+    cyphertext_size: u32,
+    shard: *const u8,
+    shard_size: u32,
+) -> sgx_status_t {
+    let shard = ShardIdentifier::from_slice(slice::from_raw_parts(shard, shard_size as usize));
+    let cyphertext = slice::from_raw_parts(cyphertext, cyphertext_size as usize);
+
+    let rsa_keypair = match rsa3072::unseal_pair() {
+        Ok(pair) => pair,
+        Err(status) => return status,
+    };
+    let request_vec = match rsa3072::decrypt(cyphertext, &rsa_keypair) {
+        Ok(r) => r,
+        Err(status) => return status,
+    };
+    let validator = match io::light_validation::unseal() {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let latest_header = match validator.latest_header(validator.num_relays) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("chain relay has no header yet. Error: {:?}", e);
+            return sgx_status_t::SGX_ERROR_UNEXPECTED;
+        }
+    };
+    let mut calls = Vec::new();
+    let status = match add_to_pool(
+        &mut calls,
+        &shard,
+        &request_vec,
+        false,
+        latest_header.number,
+    ) {
+        Ok(true) => sgx_status_t::SGX_SUCCESS,
+        Ok(false) => sgx_status_t::SGX_ERROR_INVALID_PARAMETER,
+        Err(status) => status,
+    };
+    // the confirmation of a call from an extrinsic the submitted call has pushed out
+    if let Err(e) = outbox::push(calls) {
+        return e;
+    }
+    status
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn init_chain_relay(
     genesis_header: *const u8,
@@ -335,17 +501,103 @@ pub unsafe extern "C" fn init_chain_relay(
     sgx_status_t::SGX_SUCCESS
 }
 
+/// executes the directly submitted pending calls in sidechain blocks on top of the latest
+/// imported parentchain block. Calls from extrinsics are executed upon block import instead. Writes the signed blocks to `blocks`, which are handed out again until the worker
+/// confirms to have stored them. The calls for the parentchain are added to the outbox, the
+/// number of calls waiting in it is written to `pending_extrinsics`
 #[no_mangle]
-pub unsafe extern "C" fn sync_chain_relay(
-    blocks: *const u8,
+pub unsafe extern "C" fn produce_sidechain_blocks(
+    blocks: *mut u8,
     blocks_size: usize,
+    pending_extrinsics: *mut u32,
+) -> sgx_status_t {
+    let blocks_slice = slice::from_raw_parts_mut(blocks, blocks_size);
+
+    let validator = match io::light_validation::unseal() {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let latest_header = match validator.latest_header(validator.num_relays) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("chain relay has no header yet. Error: {:?}", e);
+            return sgx_status_t::SGX_ERROR_UNEXPECTED;
+        }
+    };
+
+    let mut calls = Vec::new();
+    let blocks = match produce_blocks(&mut calls, &latest_header) {
+        Ok(b) => b.encode(),
+        Err(e) => return e,
+    };
+    if blocks.len() > blocks_slice.len() {
+        error!(
+            "sidechain blocks of {} bytes exceed the buffer",
+            blocks.len()
+        );
+        return sgx_status_t::SGX_ERROR_UNEXPECTED;
+    }
+    write_slice_and_whitespace_pad(blocks_slice, blocks);
+    match outbox::push(calls) {
+        Ok(len) => *pending_extrinsics = len as u32,
+        Err(e) => return e,
+    }
+
+    sgx_status_t::SGX_SUCCESS
+}
+
+/// the worker has stored the sidechain blocks `(shard, block number)` in `confirmed`
+#[no_mangle]
+pub unsafe extern "C" fn confirm_sidechain_blocks(
+    confirmed: *const u8,
+    confirmed_size: usize,
+) -> sgx_status_t {
+    let mut confirmed_slice = slice::from_raw_parts(confirmed, confirmed_size);
+    let confirmed: Vec<(ShardIdentifier, u64)> = match Decode::decode(&mut confirmed_slice) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Decoding confirmed sidechain blocks failed. Error: {:?}", e);
+            return sgx_status_t::SGX_ERROR_UNEXPECTED;
+        }
+    };
+    let mut heads = match sidechain::load() {
+        Ok(h) => h,
+        Err(e) => return e,
+    };
+    for (shard, number) in confirmed.iter() {
+        heads.confirm(shard, *number);
+    }
+    match sidechain::seal(&heads) {
+        Ok(()) => sgx_status_t::SGX_SUCCESS,
+        Err(e) => e,
+    }
+}
+
+/// composes extrinsics for the calls waiting in the outbox, as many as fit into
+/// `unchecked_extrinsic`
+#[no_mangle]
+pub unsafe extern "C" fn compose_extrinsics(
     nonce: *const u32,
     unchecked_extrinsic: *mut u8,
     unchecked_extrinsic_size: usize,
 ) -> sgx_status_t {
+    let xt_slice = slice::from_raw_parts_mut(unchecked_extrinsic, unchecked_extrinsic_size);
+
+    let validator = match io::light_validation::unseal() {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    if let Err(_e) = stf_post_actions(validator, xt_slice, *nonce) {
+        return sgx_status_t::SGX_ERROR_UNEXPECTED;
+    }
+
+    sgx_status_t::SGX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sync_chain_relay(blocks: *const u8, blocks_size: usize) -> sgx_status_t {
     debug!("Syncing chain relay!");
     let mut blocks_slice = slice::from_raw_parts(blocks, blocks_size);
-    let xt_slice = slice::from_raw_parts_mut(unchecked_extrinsic, unchecked_extrinsic_size);
 
     let blocks: Vec<SignedBlock<Block>> = match Decode::decode(&mut blocks_slice) {
         Ok(b) => b,
@@ -383,10 +635,18 @@ pub unsafe extern "C" fn sync_chain_relay(
             Ok(c) => calls.extend(c.into_iter()),
             Err(_) => error!("Error executing relevant extrinsics"),
         };
+
+        if let Err(e) = execute_extrinsic_calls(&mut calls, &signed_block.block.header) {
+            error!("Error executing pending calls. Error: {:?}", e);
+        }
     }
 
-    if let Err(_e) = stf_post_actions(validator, calls, xt_slice, *nonce) {
-        return sgx_status_t::SGX_ERROR_UNEXPECTED;
+    // the calls are sent once the worker asks for the outbox
+    if let Err(e) = outbox::push(calls) {
+        return e;
+    }
+    if let Err(e) = io::light_validation::seal(validator) {
+        return e;
     }
 
     sgx_status_t::SGX_SUCCESS
@@ -434,8 +694,14 @@ pub fn update_states(header: Header) -> SgxResult<()> {
 
                     // block number is purged from the substrate state so it can't be read like other storage values
                     Stf::update_block_number(&mut state, header.number);
+                    let transferred = Stf::execute_scheduled_transfers(&mut state, header.number);
+                    let pruned = Stf::prune_call_receipts(&mut state, header.number);
 
                     state::write(state, &s)?;
+                    if transferred || pruned {
+                        // committed with the shard's next sidechain block
+                        sidechain::mark_changed(s)?;
+                    }
                 }
             }
             None => info!("No shards are on the chain yet"),
@@ -448,7 +714,10 @@ pub fn update_states(header: Header) -> SgxResult<()> {
 pub fn scan_block_for_relevant_xt(block: &Block) -> SgxResult<Vec<OpaqueCall>> {
     debug!("Scanning block {} for relevant xt", block.header.number());
     let mut calls = Vec::<OpaqueCall>::new();
-    for xt_opaque in block.extrinsics.iter() {
+    // fetched with the first ShieldFunds extrinsic
+    let mut events = None;
+    let echoed = echoed_event_data(block);
+    for (index, xt_opaque) in block.extrinsics.iter().enumerate() {
         if let Ok(xt) =
             UncheckedExtrinsicV4::<CallWorkerFn>::decode(&mut xt_opaque.encode().as_slice())
         {
@@ -458,10 +727,130 @@ pub fn scan_block_for_relevant_xt(block: &Block) -> SgxResult<Vec<OpaqueCall>> {
                 }
             }
         }
+        if let Ok(xt) =
+            UncheckedExtrinsicV4::<ShieldFundsFn>::decode(&mut xt_opaque.encode().as_slice())
+        {
+            if xt.function.0 == [SUBSRATEE_REGISTRY_MODULE, SHIELD_FUNDS] {
+                if let Err(e) = handle_shield_funds_xt(
+                    &mut calls,
+                    xt,
+                    index as u32,
+                    &block.header,
+                    &mut events,
+                    &echoed,
+                ) {
+                    error!("Error performing shield funds. Error: {:?}", e);
+                }
+            }
+        }
     }
     Ok(calls)
 }
 
+/// (extrinsic index, encoded event data) of the registry events which echo the arguments of the
+/// block's `ShieldFunds` and `CallWorker` extrinsics, should they have succeeded
+fn echoed_event_data(block: &Block) -> Vec<(u32, Vec<u8>)> {
+    let mut echoed = Vec::new();
+    for (index, xt_opaque) in block.extrinsics.iter().enumerate() {
+        let xt_vec = xt_opaque.encode();
+        if let Ok(xt) = UncheckedExtrinsicV4::<CallWorkerFn>::decode(&mut xt_vec.as_slice()) {
+            if xt.function.0 == [SUBSRATEE_REGISTRY_MODULE, CALL_WORKER] {
+                // Forwarded(request)
+                echoed.push((index as u32, xt.function.1.encode()));
+            }
+        }
+        if let Ok(xt) = UncheckedExtrinsicV4::<ShieldFundsFn>::decode(&mut xt_vec.as_slice()) {
+            if xt.function.0 == [SUBSRATEE_REGISTRY_MODULE, SHIELD_FUNDS] {
+                // ShieldFunds(account_encrypted)
+                echoed.push((index as u32, xt.function.1.encode()));
+            }
+        }
+    }
+    echoed
+}
+
+fn handle_shield_funds_xt(
+    calls: &mut Vec<OpaqueCall>,
+    xt: UncheckedExtrinsicV4<ShieldFundsFn>,
+    index: u32,
+    header: &Header,
+    events: &mut Option<Vec<u8>>,
+    echoed: &[(u32, Vec<u8>)],
+) -> SgxResult<()> {
+    if xt.signature.is_none() {
+        // only signed extrinsics have actually moved funds to the registry
+        error!("ShieldFunds extrinsic is not signed");
+        return Ok(());
+    }
+    let call_hash = blake2_256(&xt.encode());
+    let (call, account_encrypted, amount, shard) = xt.function;
+    debug!(
+        "Found ShieldFunds extrinsic in block: \nCall: {:?} \nAmount: {}\nshard: {}",
+        call,
+        amount,
+        shard.encode().to_base58(),
+    );
+
+    if !state::exists(&shard) {
+        // shards are created for the currencies on chain, not by whoever shields funds
+        error!("ShieldFunds extrinsic for unknown shard");
+        return Ok(());
+    }
+
+    if events.is_none() {
+        *events = Some(node_events(header)?);
+    }
+    // failed extrinsics are included in the block too, but haven't locked any funds
+    if !funds_shielded(events.as_ref().unwrap(), index, &account_encrypted, echoed) {
+        error!("ShieldFunds extrinsic failed on chain");
+        return Ok(());
+    }
+
+    debug!("decrypt the incognito account");
+    let rsa_keypair = rsa3072::unseal_pair()?;
+    let account_vec = rsa3072::decrypt(&account_encrypted, &rsa_keypair)?;
+    let account = if let Ok(a) = AccountId::decode(&mut account_vec.as_slice()) {
+        a
+    } else {
+        error!("could not decode incognito account");
+        return Ok(());
+    };
+
+    let mut state = state::load(&shard)?;
+
+    // for encointer we assume that every currency has its own shard. so shard == cid
+    if let Err(e) = Stf::shield_funds(&mut state, account, shard, amount) {
+        error!("Error shielding funds. Error: {:?}", e);
+        return Ok(());
+    }
+
+    let state_hash = state::write(state, &shard)?;
+
+    let confirmation = CallConfirmation {
+        state_hash,
+        success: true,
+        batch_results: None,
+    };
+    confirm_call(calls, shard, call_hash, confirmation)
+}
+
+/// the encoded events the node has deposited in the block of `header`, verified against its state
+/// root
+fn node_events(header: &Header) -> SgxResult<Vec<u8>> {
+    let key = storage_key("System", "Events").0;
+    let responses: Vec<WorkerResponse<Vec<u8>>> =
+        worker_request(vec![WorkerRequest::ChainStorage(
+            key.clone(),
+            Some(header.hash()),
+        )])?;
+    let update_map = verify_worker_responses(responses, header.clone())?;
+    // decoding them takes the node's runtime, see `funds_shielded`
+    match update_map.get(&key) {
+        Some(Some(events)) => Ok(events.clone()),
+        _ => Ok(Vec::new()),
+    }
+}
+
 fn handle_call_worker_xt(
     calls: &mut Vec<OpaqueCall>,
     xt: UncheckedExtrinsicV4<CallWorkerFn>,
@@ -478,30 +867,381 @@ fn handle_call_worker_xt(
     debug!("decrypt the call");
     let rsa_keypair = rsa3072::unseal_pair()?;
     let request_vec = rsa3072::decrypt(&cyphertext, &rsa_keypair)?;
-    let stf_call_signed = if let Ok(call) = TrustedCallSigned::decode(&mut request_vec.as_slice()) {
+
+    if add_to_pool(calls, &shard, &request_vec, true, header.number)? {
+        // confirmed once it has been executed
+        return Ok(());
+    }
+    // invalid calls fail right away and record the reason in their receipt
+    if let Some(confirmation) = execute_trusted_call(calls, &shard, &request_vec, header, true)? {
+        confirm_call(calls, shard, blake2_256(&request_vec), confirmation)?;
+    }
+    Ok(())
+}
+
+/// adds a decrypted call to the trusted operation pool if it can eventually be executed. Its
+/// nonce must be within the signer's share of the pool. Directly submitted calls haven't paid
+/// any fee, so their signer must have used the shard before or hold funds in it
+fn add_to_pool(
+    calls: &mut Vec<OpaqueCall>,
+    shard: &ShardIdentifier,
+    request_vec: &[u8],
+    via_extrinsic: bool,
+    block_number: BlockNumber,
+) -> SgxResult<bool> {
+    let call = match TrustedCallSigned::decode(&mut &request_vec[..]) {
+        Ok(call) => call,
+        Err(_) => return Ok(false),
+    };
+    let mrenclave = attestation::get_mrenclave_of_self()?;
+    if !call.verify_signature(&mrenclave.m, shard) || !call.call.is_bound_to(shard) {
+        return Ok(false);
+    }
+    let (expected, known) = if state::exists(shard) {
+        let mut state = state::load(shard)?;
+        let signer = call.call.account();
+        let expected = Stf::nonce(&mut state, signer);
+        (
+            expected,
+            expected > 0 || Stf::holds_funds(&mut state, signer, *shard),
+        )
+    } else {
+        (0, false)
+    };
+    if !via_extrinsic && !known {
+        warn!("directly submitted call of an unknown signer");
+        return Ok(false);
+    }
+    if call.nonce < expected
+        || call.nonce >= expected.saturating_add(MAX_PENDING_CALLS_PER_ACCOUNT as u32)
+    {
+        return Ok(false);
+    }
+    let mut pool = top_pool::load()?;
+    let pending = PendingCall {
+        call,
+        via_extrinsic,
+        submitted_at: pool.round(),
+    };
+    let evicted = match pool.submit(*shard, pending) {
+        Ok(evicted) => evicted,
+        Err(()) => return Ok(false),
+    };
+    top_pool::seal(&pool)?;
+    if let Some((evicted_shard, evicted)) = evicted {
+        reject_pending_call(
+            calls,
+            &evicted_shard,
+            &evicted,
+            StfError::Evicted,
+            block_number,
+        )?;
+        sidechain::mark_changed(evicted_shard)?;
+    }
+    Ok(true)
+}
+
+/// executes the pooled calls from `call_worker` extrinsics whose nonce has been reached upon
+/// import of the block with `header`, so every worker executes them on the same parentchain
+/// state and in the same order relative to the block's other state transitions
+fn execute_extrinsic_calls(calls: &mut Vec<OpaqueCall>, header: &Header) -> SgxResult<()> {
+    let mut pool = top_pool::load()?;
+    for shard in pool.shards() {
+        let (_, result) = execute_ready_calls(&mut pool, calls, &shard, header, true);
+        // executed calls must leave the pool even if a later one could not be executed
+        top_pool::seal(&pool)?;
+        result?;
+    }
+    Ok(())
+}
+
+/// executes the directly submitted pending calls of each shard in a new sidechain block.
+/// Shards without executable calls don't get a block unless their state has changed otherwise. As long as the worker hasn't confirmed to have stored
+/// the previous blocks, these are returned instead of new ones
+fn produce_blocks(
+    calls: &mut Vec<OpaqueCall>,
+    header: &Header,
+) -> SgxResult<Vec<SignedSidechainBlock>> {
+    let mut heads = sidechain::load()?;
+    let unconfirmed = heads.unconfirmed();
+    if !unconfirmed.is_empty() {
+        debug!("{} sidechain blocks are unconfirmed", unconfirmed.len());
+        return Ok(unconfirmed);
+    }
+    let mut pool = top_pool::load()?;
+    if !pool.is_empty() {
+        pool.tick();
+        top_pool::seal(&pool)?;
+    }
+    // shards whose state has changed without calls get a block too, so the change is committed
+    let changed = heads.take_changed();
+    let shards: BTreeSet<ShardIdentifier> = pool
+        .shards()
+        .into_iter()
+        .chain(changed.iter().cloned())
+        .collect();
+    let mut blocks = Vec::new();
+    if !shards.is_empty() {
+        debug!("{} calls are pending", pool.len());
+        let signer = ed25519::unseal_pair()?;
+        for shard in shards {
+            let (call_hashes, result) =
+                execute_ready_calls(&mut pool, calls, &shard, header, false);
+            // executed calls must leave the pool even if a later one could not be executed
+            top_pool::seal(&pool)?;
+            if let Err(status) = result {
+                // the calls which have not been executed stay pending for the next block
+                error!(
+                    "executing the calls of shard {} failed: {:?}",
+                    shard.encode().to_base58(),
+                    status
+                );
+            }
+            if call_hashes.is_empty() && !changed.contains(&shard) {
+                continue;
+            }
+            let state_hash = state::write_block_state(load_state(&shard)?, &shard)?;
+            let block = heads.produce_block(shard, header.number, call_hashes, state_hash, &signer);
+            sidechain::seal(&heads)?;
+            debug!(
+                "produced sidechain block {} of shard {}",
+                block.block.block_number,
+                shard.encode().to_base58()
+            );
+            blocks.push(block);
+        }
+    }
+    // calls are only expired after the ready ones have been executed, their receipts are
+    // committed with the next block
+    let expired = pool.evict_expired();
+    let any_expired = !expired.is_empty();
+    if any_expired {
+        debug!("{} pending calls expired", expired.len());
+        top_pool::seal(&pool)?;
+    }
+    for (shard, pending) in expired {
+        if state::exists(&shard) {
+            reject_pending_call(calls, &shard, &pending, StfError::Expired, header.number)?;
+            heads.mark_changed(shard);
+        }
+    }
+    let commitments = heads.take_due_commitments(header.number);
+    if !changed.is_empty() || any_expired || !commitments.is_empty() {
+        sidechain::seal(&heads)?;
+    }
+    for (shard, block_number, block_hash, state_hash) in commitments {
+        debug!(
+            "committing sidechain block {} of shard {}",
+            block_number,
+            shard.encode().to_base58()
+        );
+        state::commit_block_state(&shard)?;
+        // the block hash takes the place of the call hash
+        let confirmation = CallConfirmation {
+            state_hash,
+            success: true,
+            batch_results: None,
+        };
+        push_confirmation(calls, shard, block_hash.to_fixed_bytes(), confirmation);
+    }
+    Ok(blocks)
+}
+
+/// executes the pooled calls of `shard` whose signer's nonce has been reached and which have
+/// been submitted the given way. A signer's calls stop at the first one submitted the other
+/// way. Calls from extrinsics are confirmed one by one. Returns the hashes of all executed
+/// calls, also if executing stopped with an error. Calls which have not been executed stay in
+/// the pool
+fn execute_ready_calls(
+    pool: &mut TrustedOperationPool,
+    calls: &mut Vec<OpaqueCall>,
+    shard: &ShardIdentifier,
+    header: &Header,
+    via_extrinsic: bool,
+) -> (Vec<Hash>, SgxResult<()>) {
+    let mut executed = Vec::new();
+    for account in pool.accounts(shard) {
+        let mut nonce = match load_state(shard) {
+            Ok(mut state) => Stf::nonce(&mut state, &account),
+            Err(status) => return (executed, Err(status)),
+        };
+        // stale calls fail with an invalid nonce, which is recorded in their receipts
+        let (stale, other): (Vec<PendingCall>, Vec<PendingCall>) = pool
+            .evict_stale(shard, &account, nonce)
+            .into_iter()
+            .partition(|p| p.via_extrinsic == via_extrinsic);
+        other.into_iter().for_each(|p| pool.restore(*shard, p));
+        let mut stale = stale.into_iter();
+        while let Some(pending) = stale.next() {
+            match execute_pending_call(calls, shard, &pending, header) {
+                Ok(call_hash) => executed.push(call_hash),
+                Err(status) => {
+                    pool.restore(*shard, pending);
+                    stale.for_each(|p| pool.restore(*shard, p));
+                    return (executed, Err(status));
+                }
+            }
+        }
+        while let Some(pending) = pool.take_submitted(shard, &account, nonce, via_extrinsic) {
+            match execute_pending_call(calls, shard, &pending, header) {
+                Ok(call_hash) => executed.push(call_hash),
+                Err(status) => {
+                    pool.restore(*shard, pending);
+                    return (executed, Err(status));
+                }
+            }
+            let next = match load_state(shard) {
+                Ok(mut state) => Stf::nonce(&mut state, &account),
+                Err(status) => return (executed, Err(status)),
+            };
+            if next == nonce {
+                // only a call with an invalid nonce leaves it as it is
+                break;
+            }
+            nonce = next;
+        }
+    }
+    (executed, Ok(()))
+}
+
+fn execute_pending_call(
+    calls: &mut Vec<OpaqueCall>,
+    shard: &ShardIdentifier,
+    pending: &PendingCall,
+    header: &Header,
+) -> SgxResult<Hash> {
+    let request_vec = pending.call.encode();
+    let call_hash = blake2_256(&request_vec);
+    let confirmation = execute_trusted_call(
+        calls,
+        shard,
+        &request_vec,
+        header.clone(),
+        pending.via_extrinsic,
+    )?;
+    if let (true, Some(confirmation)) = (pending.via_extrinsic, confirmation) {
+        confirm_call(calls, *shard, call_hash, confirmation)?;
+    }
+    Ok(call_hash.into())
+}
+
+/// records the receipt of a call which has been evicted from the pool before its nonce was
+/// reached
+fn reject_pending_call(
+    calls: &mut Vec<OpaqueCall>,
+    shard: &ShardIdentifier,
+    pending: &PendingCall,
+    error: StfError,
+    block_number: BlockNumber,
+) -> SgxResult<()> {
+    if !state::exists(shard) {
+        return Ok(());
+    }
+    let call_hash = blake2_256(&pending.call.encode());
+    let confirmation = reject_call(
+        state::load(shard)?,
+        shard,
+        call_hash,
+        Some(pending.call.call.account().clone()),
+        error,
+        block_number,
+        pending.via_extrinsic,
+    )?;
+    if pending.via_extrinsic {
+        confirm_call(calls, *shard, call_hash, confirmation)?;
+    }
+    Ok(())
+}
+
+/// confirms a call executed on the current state of `shard`, which becomes the state getters
+/// are proven against
+fn confirm_call(
+    calls: &mut Vec<OpaqueCall>,
+    shard: ShardIdentifier,
+    call_hash: [u8; 32],
+    confirmation: CallConfirmation,
+) -> SgxResult<()> {
+    state::commit_state(&shard)?;
+    push_confirmation(calls, shard, call_hash, confirmation);
+    Ok(())
+}
+
+/// failed calls are confirmed as well. Why they failed is not published, only the sender learns
+/// it through `TrustedGetter::call_receipt`
+fn push_confirmation(
+    calls: &mut Vec<OpaqueCall>,
+    shard: ShardIdentifier,
+    call_hash: [u8; 32],
+    confirmation: CallConfirmation,
+) {
+    let xt_call = [SUBSRATEE_REGISTRY_MODULE, CALL_CONFIRMED];
+    calls.push(OpaqueCall(
+        (xt_call, shard, call_hash, confirmation.encode()).encode(),
+    ));
+}
+
+fn load_state(shard: &ShardIdentifier) -> SgxResult<StfState> {
+    if state::exists(shard) {
+        state::load(shard)
+    } else {
+        state::init_shard(shard)?;
+        Ok(Stf::init_state())
+    }
+}
+
+/// executes a decrypted `TrustedCallSigned` on `shard` and records its receipt. Requests to
+/// unknown shards which can't be attributed to a signer are not confirmed
+fn execute_trusted_call(
+    calls: &mut Vec<OpaqueCall>,
+    shard: &ShardIdentifier,
+    request_vec: &[u8],
+    header: Header,
+    via_extrinsic: bool,
+) -> SgxResult<Option<CallConfirmation>> {
+    let call_hash = blake2_256(request_vec);
+    debug!("Call hash 0x{}", hex::encode_hex(&call_hash));
+
+    let stf_call_signed = if let Ok(call) = TrustedCallSigned::decode(&mut &request_vec[..]) {
         call
     } else {
         error!("could not decode TrustedCallSigned");
         // do not panic here or users will be able to shoot workers dead by supplying funky calls
-        return Ok(());
+        return reject_unattributed_call(
+            shard,
+            call_hash,
+            StfError::UndecodableCall,
+            header.number,
+        );
     };
 
     debug!("query mrenclave of self");
     let mrenclave = attestation::get_mrenclave_of_self()?;
 
     debug!("MRENCLAVE of self is {}", mrenclave.m.to_base58());
-    if let false = stf_call_signed.verify_signature(&mrenclave.m, &shard) {
+    if let false = stf_call_signed.verify_signature(&mrenclave.m, shard) {
         error!("TrustedCallSigned: bad signature");
         // do not panic here or users will be able to shoot workers dead by supplying a bad signature
-        return Ok(());
+        return reject_unattributed_call(shard, call_hash, StfError::BadSignature, header.number);
     }
 
-    let mut state = if state::exists(&shard) {
-        state::load(&shard)?
-    } else {
-        state::init_shard(&shard)?;
-        Stf::init_state()
-    };
+    let mut state = load_state(shard)?;
+    let signer = stf_call_signed.call.account().clone();
+    if !stf_call_signed.call.is_bound_to(shard) {
+        error!(
+            "TrustedCallSigned: call touches another currency than shard {:?}",
+            shard
+        );
+        return reject_call(
+            state,
+            shard,
+            call_hash,
+            Some(signer),
+            StfError::ForeignShard,
+            header.number,
+            via_extrinsic,
+        )
+        .map(Some);
+    }
 
     debug!("Update STF storage!");
     let requests = Stf::get_storage_hashes_to_update(&stf_call_signed)
@@ -511,27 +1251,118 @@ fn handle_call_worker_xt(
 
     let responses: Vec<WorkerResponse<Vec<u8>>> = worker_request(requests)?;
 
+    let block_number = header.number;
     let update_map = verify_worker_responses(responses, header)?;
 
-    Stf::update_storage(&mut state, &update_map);
-
     debug!("execute STF");
-    if let Err(e) = Stf::execute(&mut state, stf_call_signed, calls) {
-        error!("Error performing Stf::execute. Error: {:?}", e);
-        return Ok(());
-    }
+    // a failed call has been reverted, including the storage update, but has used up its nonce.
+    // Which calls of a failed batch failed is published along with the confirmation
+    let batch_results =
+        match Stf::execute_with_storage(&mut state, stf_call_signed, &update_map, calls) {
+            Ok(batch_results) => batch_results,
+            Err(e) => {
+                error!("Error performing Stf::execute. Error: {:?}", e);
+                let batch_results = match &e {
+                    StfError::BatchFailed(results) => Some(results.clone()),
+                    _ => None,
+                };
+                let confirmation = reject_call(
+                    state,
+                    shard,
+                    call_hash,
+                    Some(signer),
+                    e,
+                    block_number,
+                    via_extrinsic,
+                )?;
+                return Ok(Some(CallConfirmation {
+                    batch_results,
+                    ..confirmation
+                }));
+            }
+        };
 
-    let state_hash = state::write(state, &shard)?;
+    let receipt = CallReceipt {
+        signer: Some(signer),
+        result: Ok(()),
+        block_number,
+        state_hash: state::hash(&state)?,
+    };
+    record_call_receipt(&mut state, shard, call_hash, receipt, via_extrinsic);
+    let state_hash = state::write(state, shard)?;
+
+    Ok(Some(CallConfirmation {
+        state_hash,
+        success: true,
+        batch_results,
+    }))
+}
 
-    let xt_call = [SUBSRATEE_REGISTRY_MODULE, CALL_CONFIRMED];
-    let call_hash = blake2_256(&request_vec);
-    debug!("Call hash 0x{}", hex::encode_hex(&call_hash));
+/// receipts take up state for `CALL_RECEIPT_RETENTION_PERIOD` blocks. A call from an extrinsic
+/// has paid a fee for that, a directly submitted one only gets a receipt if its signer holds
+/// funds in the shard's currency
+fn record_call_receipt(
+    state: &mut StfState,
+    shard: &ShardIdentifier,
+    call_hash: [u8; 32],
+    receipt: CallReceipt,
+    via_extrinsic: bool,
+) {
+    let funded = receipt
+        .signer
+        .as_ref()
+        .map_or(false, |signer| Stf::holds_funds(state, signer, *shard));
+    if via_extrinsic || funded {
+        Stf::record_call_receipt(state, call_hash.into(), receipt);
+    }
+}
 
-    calls.push(OpaqueCall(
-        (xt_call, shard, call_hash, state_hash.encode()).encode(),
-    ));
+/// records the receipt of a call which has not been executed
+fn reject_call(
+    mut state: StfState,
+    shard: &ShardIdentifier,
+    call_hash: [u8; 32],
+    signer: Option<AccountId>,
+    error: StfError,
+    block_number: BlockNumber,
+    via_extrinsic: bool,
+) -> SgxResult<CallConfirmation> {
+    let receipt = CallReceipt {
+        signer,
+        result: Err(error),
+        block_number,
+        state_hash: state::hash(&state)?,
+    };
+    record_call_receipt(&mut state, shard, call_hash, receipt, via_extrinsic);
+    let state_hash = state::write(state, shard)?;
+    Ok(CallConfirmation {
+        state_hash,
+        success: false,
+        batch_results: None,
+    })
+}
 
-    Ok(())
+/// records the receipt of a request from an extrinsic which can't be attributed to a signer.
+/// Such requests don't create shards
+fn reject_unattributed_call(
+    shard: &ShardIdentifier,
+    call_hash: [u8; 32],
+    error: StfError,
+    block_number: BlockNumber,
+) -> SgxResult<Option<CallConfirmation>> {
+    if !state::exists(shard) {
+        return Ok(None);
+    }
+    reject_call(
+        state::load(shard)?,
+        shard,
+        call_hash,
+        None,
+        error,
+        block_number,
+        true,
+    )
+    .map(Some)
 }
 
 fn verify_worker_responses(
@@ -599,6 +1430,36 @@ extern "C" {
 pub extern "C" fn test_main_entrance() -> size_t {
     rsgx_unit_tests!(
         state::test_encrypted_state_io_works,
+        top_pool::test_pool_orders_calls_by_nonce,
+        top_pool::test_pool_gives_every_signer_a_fair_share,
+        top_pool::test_pool_evicts_expired_calls,
+        sidechain::test_sidechain_blocks_are_chained_per_shard,
+        sidechain::test_sidechain_blocks_are_confirmed_and_committed,
+        sgx_tests::test_failed_balance_transfer_is_rolled_back,
+        sgx_tests::test_failed_register_participant_is_rolled_back,
+        sgx_tests::test_failed_register_attestations_is_rolled_back,
+        sgx_tests::test_failed_grant_reputation_is_rolled_back,
+        sgx_tests::test_unsynced_role_is_missing_privileges,
+        sgx_tests::test_failed_batch_is_rolled_back,
+        sgx_tests::test_batch_reports_results,
+        sgx_tests::test_call_with_invalid_nonce_is_rolled_back,
+        sgx_tests::test_failed_call_can_not_be_replayed,
+        sgx_tests::test_encode_state_is_deterministic,
+        sgx_tests::test_transfer_history_is_recorded_for_both_parties,
+        sgx_tests::test_parameter_changes_are_paged,
+        sgx_tests::test_transfer_memo_is_recorded,
+        sgx_tests::test_transfer_with_too_long_memo_is_rolled_back,
+        sgx_tests::test_scheduled_transfer_is_executed_on_block,
+        sgx_tests::test_scheduled_transfer_needs_funds,
+        sgx_tests::test_cancelled_scheduled_transfer_is_not_executed,
+        sgx_tests::test_poll_tallies_reputation_weighted_votes_after_close,
+        sgx_tests::test_viewing_key_is_limited_to_its_scopes,
+        sgx_tests::test_proxy_can_only_sign_permitted_calls_until_expiry,
+        sgx_tests::test_multisig_transfer_needs_threshold_approvals,
+        sgx_tests::test_call_receipt_is_only_visible_to_signer,
+        sgx_tests::test_call_receipt_expires,
+        sgx_tests::test_events_of_call_are_stored_for_sender,
+        sgx_tests::test_balance_proof_verifies_against_state_root,
         ipfs::test_creates_ipfs_content_struct_works,
         ipfs::test_verification_ok_for_correct_content,
         ipfs::test_verification_fails_for_incorrect_content,
@@ -0,0 +1,58 @@
+/*
+    Copyright 2019 Supercomputing Systems AG
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+
+*/
+
+//! Parentchain calls of the enclave waiting to be composed into extrinsics. The worker asks for
+//! them once it can send, and gets as many as fit into its buffer.
+
+use std::sgxfs::SgxFile;
+use std::vec::Vec;
+
+use codec::{Decode, Encode};
+use log::*;
+use sgx_types::{sgx_status_t, SgxResult};
+use substratee_stf::sgx::OpaqueCall;
+
+use crate::constants::OUTBOX_DB;
+use crate::io;
+
+pub fn load() -> SgxResult<Vec<OpaqueCall>> {
+    if SgxFile::open(OUTBOX_DB).is_err() {
+        return Ok(Vec::new());
+    }
+    let vec = io::unseal(OUTBOX_DB)?;
+    let calls: Vec<Vec<u8>> =
+        Decode::decode(&mut vec.as_slice()).map_err(|_| sgx_status_t::SGX_ERROR_UNEXPECTED)?;
+    Ok(calls.into_iter().map(OpaqueCall).collect())
+}
+
+pub fn seal(calls: &[OpaqueCall]) -> SgxResult<()> {
+    debug!("seal outbox with {} calls", calls.len());
+    let encoded: Vec<&Vec<u8>> = calls.iter().map(|c| &c.0).collect();
+    io::seal(encoded.encode().as_slice(), OUTBOX_DB)?;
+    Ok(())
+}
+
+/// appends `calls` to the outbox and returns the number of calls waiting in it
+pub fn push(calls: Vec<OpaqueCall>) -> SgxResult<usize> {
+    let mut outbox = load()?;
+    if calls.is_empty() {
+        return Ok(outbox.len());
+    }
+    outbox.extend(calls);
+    seal(&outbox)?;
+    Ok(outbox.len())
+}
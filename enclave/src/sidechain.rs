@@ -0,0 +1,204 @@
+/*
+    Copyright 2019 Supercomputing Systems AG
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+
+*/
+
+//! Heads of the sidechain of each shard. Pending calls are executed in sidechain blocks which
+//! the worker produces in short intervals, so they don't have to wait for the parentchain. Of
+//! the workers of an MRENCLAVE only the first registered one produces blocks, the others
+//! forward their requests to it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sgxfs::SgxFile;
+use std::vec::Vec;
+
+use codec::{Decode, Encode};
+use log::*;
+use sgx_types::{sgx_status_t, SgxResult};
+use sp_core::{ed25519, Pair, H256};
+use substratee_stf::{BlockNumber, ShardIdentifier, SidechainBlock, SignedSidechainBlock};
+
+use crate::constants::{SIDECHAIN_COMMITMENT_INTERVAL, SIDECHAIN_COMMITMENT_PERIOD, SIDECHAIN_DB};
+use crate::io;
+
+#[derive(Encode, Decode, Clone, Default, Debug)]
+struct ShardHead {
+    number: u64,
+    hash: H256,
+    state_hash: H256,
+    /// latest block committed on the parentchain
+    committed: u64,
+    /// parentchain block the latest commitment has been sent at
+    committed_at: BlockNumber,
+}
+
+#[derive(Encode, Decode, Default, Debug)]
+pub struct SidechainHeads {
+    heads: BTreeMap<ShardIdentifier, ShardHead>,
+    /// produced blocks the worker has not confirmed to have stored yet
+    unconfirmed: Vec<SignedSidechainBlock>,
+    /// shards whose state has changed outside of sidechain blocks, e.g. by shielding or
+    /// scheduled transfers. They get a block even without calls
+    changed: BTreeSet<ShardIdentifier>,
+}
+
+impl SidechainHeads {
+    /// number and hash of the latest block of `shard`
+    pub fn head(&self, shard: &ShardIdentifier) -> (u64, H256) {
+        self.heads
+            .get(shard)
+            .map_or((0, H256::default()), |h| (h.number, h.hash))
+    }
+
+    /// signs the next block of `shard` and makes it the new head. The block stays
+    /// unconfirmed until the worker has stored it
+    pub fn produce_block(
+        &mut self,
+        shard: ShardIdentifier,
+        parentchain_block_number: BlockNumber,
+        call_hashes: Vec<H256>,
+        state_hash: H256,
+        signer: &ed25519::Pair,
+    ) -> SignedSidechainBlock {
+        let (number, parent_hash) = self.head(&shard);
+        let block = SidechainBlock {
+            shard,
+            block_number: number + 1,
+            parent_hash,
+            parentchain_block_number,
+            call_hashes,
+            state_hash,
+            signer: signer.public(),
+        };
+        let head = self.heads.entry(shard).or_default();
+        head.number = block.block_number;
+        head.hash = block.hash();
+        head.state_hash = state_hash;
+        let signed = block.sign(signer);
+        self.unconfirmed.push(signed.clone());
+        signed
+    }
+
+    pub fn mark_changed(&mut self, shard: ShardIdentifier) {
+        self.changed.insert(shard);
+    }
+
+    /// the shards marked as changed since this has last been called
+    pub fn take_changed(&mut self) -> BTreeSet<ShardIdentifier> {
+        std::mem::replace(&mut self.changed, BTreeSet::new())
+    }
+
+    pub fn unconfirmed(&self) -> Vec<SignedSidechainBlock> {
+        self.unconfirmed.clone()
+    }
+
+    /// the worker has stored block `number` of `shard`
+    pub fn confirm(&mut self, shard: &ShardIdentifier, number: u64) {
+        self.unconfirmed
+            .retain(|b| !(b.block.shard == *shard && b.block.block_number == number));
+    }
+
+    /// (shard, block number, block hash, state hash) of the heads which are due to be
+    /// committed at parentchain block `number`. A shard is committed once it has
+    /// `SIDECHAIN_COMMITMENT_INTERVAL` new blocks, or `SIDECHAIN_COMMITMENT_PERIOD`
+    /// parentchain blocks after its last commitment if it has any new block at all
+    pub fn take_due_commitments(
+        &mut self,
+        number: BlockNumber,
+    ) -> Vec<(ShardIdentifier, u64, H256, H256)> {
+        let mut due = Vec::new();
+        for (shard, head) in self.heads.iter_mut() {
+            let new_blocks = head.number - head.committed;
+            if new_blocks == 0 {
+                continue;
+            }
+            if new_blocks >= SIDECHAIN_COMMITMENT_INTERVAL
+                || number.saturating_sub(head.committed_at) >= SIDECHAIN_COMMITMENT_PERIOD
+            {
+                head.committed = head.number;
+                head.committed_at = number;
+                due.push((*shard, head.number, head.hash, head.state_hash));
+            }
+        }
+        due
+    }
+}
+
+pub fn load() -> SgxResult<SidechainHeads> {
+    if SgxFile::open(SIDECHAIN_DB).is_err() {
+        return Ok(SidechainHeads::default());
+    }
+    let vec = io::unseal(SIDECHAIN_DB)?;
+    SidechainHeads::decode(&mut vec.as_slice()).map_err(|_| sgx_status_t::SGX_ERROR_UNEXPECTED)
+}
+
+pub fn seal(heads: &SidechainHeads) -> SgxResult<()> {
+    debug!("seal sidechain heads of {} shards", heads.heads.len());
+    io::seal(heads.encode().as_slice(), SIDECHAIN_DB)?;
+    Ok(())
+}
+
+/// the state of `shard` has changed outside of a sidechain block
+pub fn mark_changed(shard: ShardIdentifier) -> SgxResult<()> {
+    let mut heads = load()?;
+    heads.mark_changed(shard);
+    seal(&heads)
+}
+
+pub fn test_sidechain_blocks_are_chained_per_shard() {
+    let signer = ed25519::Pair::from_seed(&[1u8; 32]);
+    let shard = ShardIdentifier::default();
+    let other_shard = ShardIdentifier::from([1u8; 32]);
+    let mut heads = SidechainHeads::default();
+
+    let first = heads.produce_block(shard, 1, Vec::new(), H256::default(), &signer);
+    let second = heads.produce_block(shard, 1, Vec::new(), H256::default(), &signer);
+    assert_eq!(first.block.block_number, 1);
+    assert_eq!(first.block.parent_hash, H256::default());
+    assert_eq!(second.block.block_number, 2);
+    assert_eq!(second.block.parent_hash, first.block.hash());
+    assert!(second.verify_signature());
+    assert_eq!(heads.head(&shard), (2, second.block.hash()));
+
+    let other = heads.produce_block(other_shard, 1, Vec::new(), H256::default(), &signer);
+    assert_eq!(other.block.block_number, 1);
+}
+
+pub fn test_sidechain_blocks_are_confirmed_and_committed() {
+    let signer = ed25519::Pair::from_seed(&[1u8; 32]);
+    let shard = ShardIdentifier::default();
+    let mut heads = SidechainHeads::default();
+
+    let first = heads.produce_block(shard, 1, Vec::new(), H256::default(), &signer);
+    let second = heads.produce_block(shard, 1, Vec::new(), H256::default(), &signer);
+    assert_eq!(heads.unconfirmed(), vec![first.clone(), second.clone()]);
+    heads.confirm(&shard, 1);
+    assert_eq!(heads.unconfirmed(), vec![second.clone()]);
+
+    // a quiet shard is committed after the commitment period
+    let due = heads.take_due_commitments(SIDECHAIN_COMMITMENT_PERIOD);
+    assert_eq!(due, vec![(shard, 2, second.block.hash(), H256::default())]);
+    assert!(heads
+        .take_due_commitments(2 * SIDECHAIN_COMMITMENT_PERIOD)
+        .is_empty());
+
+    // a busy shard as soon as it has enough new blocks
+    for _ in 0..SIDECHAIN_COMMITMENT_INTERVAL {
+        heads.produce_block(shard, 1, Vec::new(), H256::default(), &signer);
+    }
+    let due = heads.take_due_commitments(SIDECHAIN_COMMITMENT_PERIOD + 1);
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].1, 2 + SIDECHAIN_COMMITMENT_INTERVAL);
+}
@@ -18,6 +18,7 @@
 use std::fs;
 
 use std::io::Write;
+use std::string::String;
 use std::vec::Vec;
 
 use log::*;
@@ -25,7 +26,7 @@ use sgx_tcrypto::rsgx_sha256_slice;
 use sgx_types::*;
 
 use crate::aes;
-use crate::constants::{ENCRYPTED_STATE_FILE, SHARDS_PATH};
+use crate::constants::{BLOCK_STATE_FILE, COMMITTED_STATE_FILE, ENCRYPTED_STATE_FILE, SHARDS_PATH};
 use crate::hex;
 use crate::io;
 use crate::utils::UnwrapOrSgxErrorUnexpected;
@@ -34,18 +35,20 @@ use codec::{Decode, Encode};
 use sgx_externalities::SgxExternalitiesTrait;
 use sp_core::H256;
 use std::path::Path;
+use substratee_stf::sgx::{decode_state, encode_state, state_root};
 use substratee_stf::{ShardIdentifier, State as StfState, Stf};
 
+fn state_path(shard: &ShardIdentifier, file: &str) -> String {
+    format!("{}/{}/{}", SHARDS_PATH, shard.encode().to_base58(), file)
+}
+
 pub fn load(shard: &ShardIdentifier) -> SgxResult<StfState> {
-    // load last state
-    let state_path = format!(
-        "{}/{}/{}",
-        SHARDS_PATH,
-        shard.encode().to_base58(),
-        ENCRYPTED_STATE_FILE
-    );
+    load_file(&state_path(shard, ENCRYPTED_STATE_FILE))
+}
+
+fn load_file(state_path: &str) -> SgxResult<StfState> {
     trace!("loading state from: {}", state_path);
-    let state_vec = read(&state_path)?;
+    let state_vec = read(state_path)?;
 
     // state is now decrypted!
     let state: StfState = match state_vec.len() {
@@ -58,7 +61,13 @@ pub fn load(shard: &ShardIdentifier) -> SgxResult<StfState> {
                 "State loaded from {} with size {}B, deserializing...",
                 state_path, n
             );
-            StfState::decode(state_vec)
+            match decode_state(&state_vec) {
+                Some(state) => state,
+                None => {
+                    warn!("state is not sorted SCALE, decoding legacy format");
+                    StfState::decode(state_vec)
+                }
+            }
         }
     };
     trace!("state decoded successfully");
@@ -66,20 +75,14 @@ pub fn load(shard: &ShardIdentifier) -> SgxResult<StfState> {
 }
 
 pub fn write(state: StfState, shard: &ShardIdentifier) -> SgxResult<H256> {
-    let state_path = format!(
-        "{}/{}/{}",
-        SHARDS_PATH,
-        shard.encode().to_base58(),
-        ENCRYPTED_STATE_FILE
-    );
-    trace!("writing state to: {}", state_path);
+    write_file(state, &state_path(shard, ENCRYPTED_STATE_FILE))
+}
 
-    let cyphertext = encrypt(state.encode())?;
+fn write_file(state: StfState, state_path: &str) -> SgxResult<H256> {
+    trace!("writing state to: {}", state_path);
 
-    let state_hash = match rsgx_sha256_slice(&cyphertext) {
-        Ok(h) => h,
-        Err(status) => return Err(status),
-    };
+    let state_hash = hash(&state)?;
+    let cyphertext = encrypt(encode_state(&state))?;
 
     debug!(
         "new encrypted state with hash=0x{} written to {}",
@@ -87,8 +90,44 @@ pub fn write(state: StfState, shard: &ShardIdentifier) -> SgxResult<H256> {
         state_path
     );
 
-    io::write(&cyphertext, &state_path)?;
-    Ok(state_hash.into())
+    io::write(&cyphertext, state_path)?;
+    Ok(state_hash)
+}
+
+/// keeps the state of the latest sidechain block of `shard` until the block is committed
+pub fn write_block_state(state: StfState, shard: &ShardIdentifier) -> SgxResult<H256> {
+    write_file(state, &state_path(shard, BLOCK_STATE_FILE))
+}
+
+/// the latest sidechain block of `shard` is being committed, its state becomes the one
+/// getters are proven against
+pub fn commit_block_state(shard: &ShardIdentifier) -> SgxResult<()> {
+    let cyphertext = io::read(&state_path(shard, BLOCK_STATE_FILE))?;
+    io::write(&cyphertext, &state_path(shard, COMMITTED_STATE_FILE))?;
+    Ok(())
+}
+
+/// a confirmation of the current state of `shard` is being sent, it becomes the state getters
+/// are proven against
+pub fn commit_state(shard: &ShardIdentifier) -> SgxResult<()> {
+    let cyphertext = io::read(&state_path(shard, ENCRYPTED_STATE_FILE))?;
+    io::write(&cyphertext, &state_path(shard, COMMITTED_STATE_FILE))?;
+    Ok(())
+}
+
+/// the state of the latest confirmation of `shard`, if there is one
+pub fn load_committed(shard: &ShardIdentifier) -> SgxResult<Option<StfState>> {
+    let path = state_path(shard, COMMITTED_STATE_FILE);
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+    load_file(&path).map(Some)
+}
+
+/// Merkle root over the plaintext state. Equal states have equal roots on all workers and
+/// getters can prove storage values against it
+pub fn hash(state: &StfState) -> SgxResult<H256> {
+    Ok(state_root(state))
 }
 
 pub fn exists(shard: &ShardIdentifier) -> bool {
@@ -0,0 +1,421 @@
+/*
+    Copyright 2019 Supercomputing Systems AG
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+
+*/
+
+//! Pool of trusted calls waiting for execution. Calls are kept per shard and signer, ordered
+//! by nonce, and executed once the nonce of their signer reaches them.
+
+use std::collections::BTreeMap;
+use std::sgxfs::SgxFile;
+use std::vec::Vec;
+
+use codec::{Decode, Encode};
+use log::*;
+use sgx_types::{sgx_status_t, SgxResult};
+use substratee_stf::{AccountId, ShardIdentifier, TrustedCallSigned};
+
+use crate::constants::{MAX_PENDING_CALLS_PER_ACCOUNT, TOP_POOL_DB, TOP_POOL_SIZE, TOP_POOL_TTL};
+use crate::io;
+
+#[derive(Encode, Decode, Clone, Debug)]
+pub struct PendingCall {
+    pub call: TrustedCallSigned,
+    /// calls from a `call_worker` extrinsic are confirmed one by one, directly submitted calls
+    /// in batches
+    pub via_extrinsic: bool,
+    /// round of block production the call has been added to the pool in
+    pub submitted_at: u64,
+}
+
+#[derive(Encode, Decode, Default, Debug)]
+pub struct TrustedOperationPool {
+    shards: BTreeMap<ShardIdentifier, BTreeMap<AccountId, BTreeMap<u32, PendingCall>>>,
+    /// rounds of block production while calls have been pending
+    round: u64,
+}
+
+impl TrustedOperationPool {
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    /// a round of block production has passed
+    pub fn tick(&mut self) {
+        self.round += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .values()
+            .flat_map(|accounts| accounts.values())
+            .map(|calls| calls.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// adds a call. A pending call of the same signer and nonce is replaced. If the pool is
+    /// full, the signer with the most pending calls gives up its last one, as long as it has
+    /// more than the submitter would have, so every signer gets a fair share of the pool.
+    /// Returns the evicted call, `Err` if the pool or the signer's share of it is full
+    pub fn submit(
+        &mut self,
+        shard: ShardIdentifier,
+        pending: PendingCall,
+    ) -> Result<Option<(ShardIdentifier, PendingCall)>, ()> {
+        let account = pending.call.call.account().clone();
+        let nonce = pending.call.nonce;
+        let replaces = self
+            .shards
+            .get(&shard)
+            .and_then(|accounts| accounts.get(&account))
+            .map_or(false, |calls| calls.contains_key(&nonce));
+        let mut evicted = None;
+        if !replaces {
+            let own = self.pending_of(&shard, &account).len();
+            if own >= MAX_PENDING_CALLS_PER_ACCOUNT {
+                warn!("too many pending calls for {:?}", account);
+                return Err(());
+            }
+            if self.len() >= TOP_POOL_SIZE {
+                match self.largest_share() {
+                    Some((s, a, len)) if len > own + 1 => {
+                        evicted = self.evict_last(&s, &a).map(|c| (s, c));
+                    }
+                    _ => {
+                        warn!("trusted operation pool is full");
+                        return Err(());
+                    }
+                }
+            }
+        }
+        self.shards
+            .entry(shard)
+            .or_default()
+            .entry(account)
+            .or_default()
+            .insert(nonce, pending);
+        Ok(evicted)
+    }
+
+    /// (shard, signer, number of pending calls) of the signer with the most pending calls
+    fn largest_share(&self) -> Option<(ShardIdentifier, AccountId, usize)> {
+        self.shards
+            .iter()
+            .flat_map(|(shard, accounts)| {
+                accounts
+                    .iter()
+                    .map(move |(account, calls)| (*shard, account.clone(), calls.len()))
+            })
+            .max_by_key(|(_, _, len)| *len)
+    }
+
+    /// removes and returns the call of `account` with the highest nonce
+    fn evict_last(&mut self, shard: &ShardIdentifier, account: &AccountId) -> Option<PendingCall> {
+        let nonce = *self.shards.get(shard)?.get(account)?.keys().next_back()?;
+        self.take(shard, account, nonce)
+    }
+
+    /// puts back a call which has been taken but not executed. It had its place in the pool
+    /// already, so the limits don't apply
+    pub fn restore(&mut self, shard: ShardIdentifier, pending: PendingCall) {
+        let account = pending.call.call.account().clone();
+        self.shards
+            .entry(shard)
+            .or_default()
+            .entry(account)
+            .or_default()
+            .insert(pending.call.nonce, pending);
+    }
+
+    pub fn shards(&self) -> Vec<ShardIdentifier> {
+        self.shards.keys().cloned().collect()
+    }
+
+    pub fn accounts(&self, shard: &ShardIdentifier) -> Vec<AccountId> {
+        self.shards
+            .get(shard)
+            .map(|accounts| accounts.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// pending calls of `account`, ordered by nonce
+    pub fn pending_of(&self, shard: &ShardIdentifier, account: &AccountId) -> Vec<PendingCall> {
+        self.shards
+            .get(shard)
+            .and_then(|accounts| accounts.get(account))
+            .map(|calls| calls.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// removes and returns the call of `account` with `nonce`
+    pub fn take(
+        &mut self,
+        shard: &ShardIdentifier,
+        account: &AccountId,
+        nonce: u32,
+    ) -> Option<PendingCall> {
+        let call = self.shards.get_mut(shard)?.get_mut(account)?.remove(&nonce);
+        self.prune(shard, account);
+        call
+    }
+
+    /// removes and returns the call of `account` with `nonce` if it has been submitted the given
+    /// way, see `PendingCall::via_extrinsic`
+    pub fn take_submitted(
+        &mut self,
+        shard: &ShardIdentifier,
+        account: &AccountId,
+        nonce: u32,
+        via_extrinsic: bool,
+    ) -> Option<PendingCall> {
+        let submitted = self
+            .shards
+            .get(shard)
+            .and_then(|accounts| accounts.get(account))
+            .and_then(|calls| calls.get(&nonce))
+            .map_or(false, |c| c.via_extrinsic == via_extrinsic);
+        if !submitted {
+            return None;
+        }
+        self.take(shard, account, nonce)
+    }
+
+    /// removes and returns the calls of `account` with a nonce below `nonce`. They can't be
+    /// executed anymore
+    pub fn evict_stale(
+        &mut self,
+        shard: &ShardIdentifier,
+        account: &AccountId,
+        nonce: u32,
+    ) -> Vec<PendingCall> {
+        let stale = match self
+            .shards
+            .get_mut(shard)
+            .and_then(|accounts| accounts.get_mut(account))
+        {
+            Some(calls) => {
+                let valid = calls.split_off(&nonce);
+                std::mem::replace(calls, valid)
+                    .into_iter()
+                    .map(|(_, c)| c)
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        self.prune(shard, account);
+        stale
+    }
+
+    /// removes and returns the calls which have been waiting for longer than `TOP_POOL_TTL`
+    /// rounds
+    pub fn evict_expired(&mut self) -> Vec<(ShardIdentifier, PendingCall)> {
+        let round = self.round;
+        let mut expired = Vec::new();
+        for (shard, accounts) in self.shards.iter_mut() {
+            for calls in accounts.values_mut() {
+                let nonces: Vec<u32> = calls
+                    .iter()
+                    .filter(|(_, c)| round.saturating_sub(c.submitted_at) > TOP_POOL_TTL)
+                    .map(|(nonce, _)| *nonce)
+                    .collect();
+                for nonce in nonces {
+                    if let Some(call) = calls.remove(&nonce) {
+                        expired.push((*shard, call));
+                    }
+                }
+            }
+            accounts.retain(|_, calls| !calls.is_empty());
+        }
+        self.shards.retain(|_, accounts| !accounts.is_empty());
+        expired
+    }
+
+    fn prune(&mut self, shard: &ShardIdentifier, account: &AccountId) {
+        if let Some(accounts) = self.shards.get_mut(shard) {
+            if accounts
+                .get(account)
+                .map_or(false, |calls| calls.is_empty())
+            {
+                accounts.remove(account);
+            }
+            if accounts.is_empty() {
+                self.shards.remove(shard);
+            }
+        }
+    }
+}
+
+pub fn load() -> SgxResult<TrustedOperationPool> {
+    if SgxFile::open(TOP_POOL_DB).is_err() {
+        return Ok(TrustedOperationPool::default());
+    }
+    let vec = io::unseal(TOP_POOL_DB)?;
+    TrustedOperationPool::decode(&mut vec.as_slice())
+        .map_err(|_| sgx_status_t::SGX_ERROR_UNEXPECTED)
+}
+
+pub fn seal(pool: &TrustedOperationPool) -> SgxResult<()> {
+    debug!("seal trusted operation pool with {} calls", pool.len());
+    io::seal(pool.encode().as_slice(), TOP_POOL_DB)?;
+    Ok(())
+}
+
+pub fn test_pool_orders_calls_by_nonce() {
+    use sp_core::{sr25519, Pair};
+    use substratee_stf::{BalanceType, KeyPair, TrustedCall};
+
+    let shard = ShardIdentifier::default();
+    let pair = KeyPair::from(sr25519::Pair::from_seed(&[1u8; 32]));
+    let pending = |nonce: u32| PendingCall {
+        call: TrustedCall::balance_transfer(
+            pair.account(),
+            pair.account(),
+            shard,
+            BalanceType::from_num(1),
+        )
+        .sign(&pair, nonce, &[0u8; 32], &shard),
+        via_extrinsic: false,
+        submitted_at: 0,
+    };
+    let mut pool = TrustedOperationPool::default();
+    for nonce in [3u32, 1, 2, 0].iter() {
+        assert!(pool.submit(shard, pending(*nonce)).unwrap().is_none());
+    }
+    // resubmitting a nonce replaces the pending call
+    assert!(pool.submit(shard, pending(2)).unwrap().is_none());
+    assert_eq!(pool.len(), 4);
+
+    let nonces: Vec<u32> = pool
+        .pending_of(&shard, &pair.account())
+        .iter()
+        .map(|p| p.call.nonce)
+        .collect();
+    assert_eq!(nonces, vec![0, 1, 2, 3]);
+
+    let stale = pool.evict_stale(&shard, &pair.account(), 2);
+    assert_eq!(stale.len(), 2);
+    assert!(pool.take(&shard, &pair.account(), 1).is_none());
+    assert_eq!(
+        pool.take(&shard, &pair.account(), 2).map(|p| p.call.nonce),
+        Some(2)
+    );
+    assert_eq!(
+        pool.take(&shard, &pair.account(), 3).map(|p| p.call.nonce),
+        Some(3)
+    );
+    assert!(pool.is_empty());
+
+    for nonce in 0..MAX_PENDING_CALLS_PER_ACCOUNT as u32 {
+        assert!(pool.submit(shard, pending(nonce)).is_ok());
+    }
+    assert!(pool
+        .submit(shard, pending(MAX_PENDING_CALLS_PER_ACCOUNT as u32))
+        .is_err());
+
+    // calls are only taken the way they have been submitted
+    assert!(pool
+        .take_submitted(&shard, &pair.account(), 0, true)
+        .is_none());
+    assert!(pool
+        .take_submitted(&shard, &pair.account(), 0, false)
+        .is_some());
+    pool.restore(shard, pending(0));
+
+    // a call which could not be executed returns to the pool
+    let taken = pool.take(&shard, &pair.account(), 0).unwrap();
+    pool.restore(shard, taken);
+    assert_eq!(pool.len(), MAX_PENDING_CALLS_PER_ACCOUNT);
+    assert_eq!(pool.pending_of(&shard, &pair.account())[0].call.nonce, 0);
+}
+
+pub fn test_pool_gives_every_signer_a_fair_share() {
+    use sp_core::{sr25519, Pair};
+    use substratee_stf::{BalanceType, KeyPair, TrustedCall};
+
+    let shard = ShardIdentifier::default();
+    let pending = |seed: u8, nonce: u32| {
+        let pair = KeyPair::from(sr25519::Pair::from_seed(&[seed; 32]));
+        PendingCall {
+            call: TrustedCall::balance_transfer(
+                pair.account(),
+                pair.account(),
+                shard,
+                BalanceType::from_num(1),
+            )
+            .sign(&pair, nonce, &[0u8; 32], &shard),
+            via_extrinsic: false,
+            submitted_at: 0,
+        }
+    };
+    let mut pool = TrustedOperationPool::default();
+    let spammers = (TOP_POOL_SIZE / MAX_PENDING_CALLS_PER_ACCOUNT) as u8;
+    for seed in 0..spammers {
+        for nonce in 0..MAX_PENDING_CALLS_PER_ACCOUNT as u32 {
+            assert!(pool.submit(shard, pending(seed, nonce)).unwrap().is_none());
+        }
+    }
+    assert_eq!(pool.len(), TOP_POOL_SIZE);
+
+    // another signer takes the place of the last call of a signer with the most calls
+    let (_, evicted) = pool.submit(shard, pending(spammers, 0)).unwrap().unwrap();
+    assert_eq!(evicted.call.nonce, MAX_PENDING_CALLS_PER_ACCOUNT as u32 - 1);
+    assert_eq!(pool.len(), TOP_POOL_SIZE);
+    // but a signer doesn't push out one which has as many calls as it would have
+    assert!(pool.submit(shard, evicted).is_err());
+}
+
+pub fn test_pool_evicts_expired_calls() {
+    use sp_core::{sr25519, Pair};
+    use substratee_stf::{BalanceType, KeyPair, TrustedCall};
+
+    let shard = ShardIdentifier::default();
+    let pair = KeyPair::from(sr25519::Pair::from_seed(&[1u8; 32]));
+    let pending = |nonce: u32, submitted_at: u64| PendingCall {
+        call: TrustedCall::balance_transfer(
+            pair.account(),
+            pair.account(),
+            shard,
+            BalanceType::from_num(1),
+        )
+        .sign(&pair, nonce, &[0u8; 32], &shard),
+        via_extrinsic: false,
+        submitted_at,
+    };
+    let mut pool = TrustedOperationPool::default();
+    // calls waiting for a nonce which is never reached
+    assert!(pool.submit(shard, pending(5, 1)).is_ok());
+    assert!(pool.submit(shard, pending(6, 10)).is_ok());
+
+    let tick_to = |pool: &mut TrustedOperationPool, round: u64| {
+        while pool.round() < round {
+            pool.tick();
+        }
+    };
+    tick_to(&mut pool, 1 + TOP_POOL_TTL);
+    assert!(pool.evict_expired().is_empty());
+    tick_to(&mut pool, 2 + TOP_POOL_TTL);
+    let expired = pool.evict_expired();
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].1.call.nonce, 5);
+    assert_eq!(pool.len(), 1);
+
+    tick_to(&mut pool, 11 + TOP_POOL_TTL);
+    assert_eq!(pool.evict_expired().len(), 1);
+    assert!(pool.is_empty());
+}
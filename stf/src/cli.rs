@@ -16,27 +16,32 @@
 */
 
 use crate::{
-    AccountId, Attestation, PublicGetter, ShardIdentifier, TrustedCall, TrustedGetter,
-    TrustedOperation,
+    AccountId, Attestation, BlockNumber, CallReceipt, CeremonyParameter, CeremonyRecord,
+    EventPallet, KeyPair, MeetupAssignment, Multisig, ParameterChange, PollIndexType, PollResult,
+    Proxy, ProxyPermission, PublicGetter, Reputation, ScheduledTransfer, ShardIdentifier, StfEvent,
+    TransferRecord, TrustedCall, TrustedCallSigned, TrustedGetter, TrustedOperation, ViewingScope,
+    GETTER_VALIDITY_PERIOD, MAX_MEMO_LEN,
 };
 use base58::{FromBase58, ToBase58};
-use clap::{AppSettings, Arg, ArgMatches};
+use clap::{AppSettings, Arg, ArgGroup, ArgMatches};
 use clap_nested::{Command, Commander, MultiCommand};
 use codec::{Decode, Encode};
 use encointer_balances::{BalanceEntry, BalanceType};
 use encointer_ceremonies::{
     ClaimOfAttendance, MeetupIndexType, ParticipantIndexType, ProofOfAttendance,
 };
-use encointer_currencies::{CurrencyIdentifier, CurrencyPropertiesType, Location};
+use encointer_currencies::{CurrencyIdentifier, CurrencyPropertiesType};
 use encointer_scheduler::{CeremonyIndexType, CeremonyPhaseType};
-use fixed::traits::LossyInto;
 use fixed::transcendental::exp;
 use log::*;
-use my_node_runtime::{BlockNumber, Header, Signature, ONE_DAY};
-use sp_application_crypto::{ed25519, sr25519};
-use sp_core::{crypto::Ss58Codec, sr25519 as sr25519_core, Pair};
+use my_node_runtime::{BlockNumber, Header, Runtime, Signature};
+use sp_application_crypto::{ecdsa, ed25519, sr25519};
+use sp_core::{
+    crypto::Ss58Codec, ecdsa as ecdsa_core, ed25519 as ed25519_core, sr25519 as sr25519_core, Pair,
+    H256,
+};
 use sp_runtime::traits::IdentifyAccount;
-use sp_runtime::{AccountId32, MultiSignature};
+use sp_runtime::{AccountId32, MultiSignature, MultiSigner};
 use std::path::PathBuf;
 use substrate_api_client::Api;
 use substrate_client_keystore::LocalKeystore;
@@ -80,6 +85,20 @@ pub fn cmd<'a>(
                         .default_value("//Alice")
                         .help("signer for publicly observable extrinsic"),
                 )
+                .arg(
+                    Arg::with_name("direct")
+                        .long("direct")
+                        .global(true)
+                        .takes_value(false)
+                        .help("send trusted calls to the worker instead of via an extrinsic. The signer must have used the shard before or hold funds in it"),
+                )
+                .arg(
+                    Arg::with_name("prove")
+                        .long("prove")
+                        .global(true)
+                        .takes_value(false)
+                        .help("ask for a proof of the storage queried by trusted getters"),
+                )
                 .name("encointer-client-teeproxy")
                 .version(VERSION)
                 .author("Supercomputing Systems AG <info@scs.ch>")
@@ -89,11 +108,32 @@ pub fn cmd<'a>(
         .add_cmd(
             Command::new("new-account")
                 .description("generates a new incognito account for the given substraTEE shard")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp).arg(
+                        Arg::with_name("scheme")
+                            .long("scheme")
+                            .takes_value(true)
+                            .possible_values(&["sr25519", "ed25519", "ecdsa"])
+                            .default_value("sr25519")
+                            .help("signature scheme of the new key"),
+                    )
+                })
                 .runner(|_args: &str, matches: &ArgMatches<'_>| {
                     let store = LocalKeystore::open(get_keystore_path(matches), None).unwrap();
-                    let key: sr25519::AppPair = store.generate().unwrap();
+                    let key: KeyPair = match matches.value_of("scheme").unwrap() {
+                        "ed25519" => ed25519_core::Pair::from(
+                            store.generate::<ed25519::AppPair>().unwrap(),
+                        )
+                        .into(),
+                        "ecdsa" => {
+                            ecdsa_core::Pair::from(store.generate::<ecdsa::AppPair>().unwrap())
+                                .into()
+                        }
+                        _ => sr25519_core::Pair::from(store.generate::<sr25519::AppPair>().unwrap())
+                            .into(),
+                    };
                     drop(store);
-                    println!("{}", key.public().to_ss58check());
+                    println!("{}", key.account().to_ss58check());
                     Ok(())
                 }),
         )
@@ -118,6 +158,17 @@ pub fn cmd<'a>(
                     {
                         println!("{}", pubkey.to_ss58check());
                     }
+                    info!("ecdsa keys:");
+                    for pubkey in store
+                        .public_keys::<ecdsa::AppPublic>()
+                        .unwrap()
+                        .into_iter()
+                    {
+                        // the account is the hash of the public key
+                        let account =
+                            MultiSigner::from(ecdsa_core::Public::from(pubkey)).into_account();
+                        println!("{}", account.to_ss58check());
+                    }
                     drop(store);
                     Ok(())
                 }),
@@ -148,40 +199,179 @@ pub fn cmd<'a>(
                                 .value_name("U128")
                                 .help("amount to be transferred"),
                         )
+                        .arg(
+                            Arg::with_name("memo")
+                                .long("memo")
+                                .takes_value(true)
+                                .value_name("STRING")
+                                .help("memo for the recipient, e.g. an invoice reference"),
+                        )
+                        .arg(
+                            Arg::with_name("proxy")
+                                .long("proxy")
+                                .takes_value(true)
+                                .value_name("SS58")
+                                .help("sign with this proxy key instead of the sender's key"),
+                        )
                 })
                 .runner(move |_args: &str, matches: &ArgMatches<'_>| {
                     let arg_from = matches.value_of("from").unwrap();
                     let arg_to = matches.value_of("to").unwrap();
                     let amount = u128::from_str_radix(matches.value_of("amount").unwrap(), 10)
                         .expect("amount can be converted to u128");
-                    let from = get_pair_from_str(matches, arg_from);
+                    let sender = get_accountid_from_str(arg_from);
                     let to = get_accountid_from_str(arg_to);
-                    info!("from ss58 is {}", from.public().to_ss58check());
+                    info!("from ss58 is {}", sender.to_ss58check());
                     info!("to ss58 is {}", to.to_ss58check());
 
                     println!(
                         "send trusted call transfer from {} to {}: {}",
-                        from.public(),
-                        to,
-                        amount
+                        sender, to, amount
                     );
                     let (mrenclave, shard) = get_identifiers(matches);
-                    let nonce = 0; // FIXME: hard coded for now
-                    let top: TrustedOperation = TrustedCall::balance_transfer(
-                        sr25519_core::Public::from(from.public()),
-                        to,
-                        shard, // for encointer we assume that every currency has its own shard. so shard == cid
-                        BalanceType::from_num(amount),
+                    let signer =
+                        get_pair_from_str(matches, matches.value_of("proxy").unwrap_or(arg_from));
+                    let nonce = get_trusted_nonce(perform_operation, matches, &signer);
+                    let amount = BalanceType::from_num(amount);
+                    let call = match matches.value_of("memo") {
+                        Some(memo) => {
+                            let memo = memo.as_bytes().to_vec();
+                            if memo.len() > MAX_MEMO_LEN {
+                                error!("memo must not exceed {} bytes", MAX_MEMO_LEN);
+                                return Ok(());
+                            }
+                            // for encointer we assume that every currency has its own shard. so shard == cid
+                            TrustedCall::balance_transfer_with_memo(sender.clone(), to, shard, amount, memo)
+                        }
+                        None => TrustedCall::balance_transfer(sender.clone(), to, shard, amount),
+                    };
+                    let call = if matches.is_present("proxy") {
+                        TrustedCall::proxied(
+                            signer.account(),
+                            sender,
+                            Box::new(call),
+                        )
+                    } else {
+                        call
+                    };
+                    let top: TrustedOperation = call
+                        .sign(&signer, nonce, &mrenclave, &shard)
+                        .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("add-proxy")
+                .description("allow a proxy key to sign calls of an incognito account")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("owner's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("delegate")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("proxy's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("expiry")
+                                .long("expiry")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("BLOCK")
+                                .help("last block at which the proxy is valid"),
+                        )
+                        .arg(
+                            Arg::with_name("transfer")
+                                .long("transfer")
+                                .help("allow transfers"),
+                        )
+                        .arg(
+                            Arg::with_name("ceremonies")
+                                .long("ceremonies")
+                                .help("allow registering for ceremonies and attestations"),
+                        )
+                        .group(
+                            ArgGroup::with_name("permissions")
+                                .args(&["transfer", "ceremonies"])
+                                .multiple(true)
+                                .required(true),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let delegate = get_accountid_from_str(matches.value_of("delegate").unwrap());
+                    let expiry: BlockNumber = matches.value_of("expiry").unwrap().parse().unwrap();
+                    let mut permissions = Vec::new();
+                    if matches.is_present("transfer") {
+                        permissions.push(ProxyPermission::Transfer);
+                    }
+                    if matches.is_present("ceremonies") {
+                        permissions.push(ProxyPermission::Ceremonies);
+                    }
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &who);
+                    let top: TrustedOperation = TrustedCall::add_proxy(
+                        who.account(),
+                        shard,
+                        delegate,
+                        permissions,
+                        expiry,
                     )
-                    .sign(&sr25519_core::Pair::from(from), nonce, &mrenclave, &shard)
+                    .sign(&who, nonce, &mrenclave, &shard)
                     .into();
                     let _ = perform_operation(matches, &top);
                     Ok(())
                 }),
         )
         .add_cmd(
-            Command::new("balance")
-                .description("query balance for incognito account in keystore")
+            Command::new("remove-proxy")
+                .description("revoke a proxy key of an incognito account")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("owner's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("delegate")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("proxy's AccountId in ss58check format"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let delegate = get_accountid_from_str(matches.value_of("delegate").unwrap());
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &who);
+                    let top: TrustedOperation = TrustedCall::remove_proxy(
+                        who.account(),
+                        shard,
+                        delegate,
+                    )
+                    .sign(&who, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("proxies")
+                .description("list the proxies of an incognito account in keystore")
                 .options(|app| {
                     app.setting(AppSettings::ColoredHelp).arg(
                         Arg::with_name("accountid")
@@ -195,29 +385,715 @@ pub fn cmd<'a>(
                     let arg_who = matches.value_of("accountid").unwrap();
                     let who = get_pair_from_str(matches, arg_who);
                     let (_mrenclave, shard) = get_identifiers(matches);
-                    let top: TrustedOperation =
-                        TrustedGetter::balance(sr25519_core::Public::from(who.public()), shard)
-                            .sign(&sr25519_core::Pair::from(who))
-                            .into();
-                    let res = perform_operation(matches, &top);
-                    let bal = if let Some(v) = res {
-                        if let Ok(vd) = <BalanceEntry<BlockNumber>>::decode(&mut v.as_slice()) {
-                            let api = get_chain_api(matches);
-                            let bn = get_block_number(&api);
-                            let dr = get_demurrage_per_block(&api, shard);
-                            debug!(
-                                "will apply demurrage to {:?}. blocknumber {}, demurrage rate {}",
-                                vd, bn, dr
-                            );
-                            apply_demurrage(vd, bn, dr)
-                        } else {
-                            info!("could not decode value. maybe hasn't been set? {:x?}", v);
-                            BalanceType::from_num(0)
+                    let top = sign_getter(
+                        matches,
+                        TrustedGetter::proxies(who.account(), shard),
+                        &who,
+                    );
+                    let proxies: Vec<Proxy> = perform_operation(matches, &top)
+                        .and_then(|v| Decode::decode(&mut v.as_slice()).ok())
+                        .unwrap_or_default();
+                    for p in proxies.iter() {
+                        println!(
+                            "{}: {:?} until block {}",
+                            p.delegate, p.permissions, p.expiry
+                        );
+                    }
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("call-receipt")
+                .description("query the outcome of a trusted call sent by an incognito account")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("signer's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("call-hash")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("HEX")
+                                .help("blake2 hash of the encoded signed call"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let arg_hash = matches.value_of("call-hash").unwrap();
+                    let call_hash = H256::from_slice(
+                        &hex::decode(arg_hash.trim_start_matches("0x"))
+                            .expect("call hash must be hex encoded"),
+                    );
+                    let top = sign_getter(
+                        matches,
+                        TrustedGetter::call_receipt(who.account(), call_hash),
+                        &who,
+                    );
+                    let receipt: Option<CallReceipt> = perform_operation(matches, &top)
+                        .and_then(|v| Decode::decode(&mut v.as_slice()).ok());
+                    match receipt {
+                        Some(r) => {
+                            match r.result {
+                                Ok(()) => println!("executed at block {}", r.block_number),
+                                Err(e) => println!("failed at block {}: {}", r.block_number, e),
+                            }
+                            println!("state hash: {:?}", r.state_hash);
                         }
-                    } else {
-                        BalanceType::from_num(0)
-                    };
-                    println!("{}", bal);
+                        None => println!("no receipt found for {}", arg_hash),
+                    }
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("events")
+                .description("list the runtime events caused by an incognito account in keystore")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .takes_value(true)
+                                .default_value("0")
+                                .help("index of the first event"),
+                        )
+                        .arg(
+                            Arg::with_name("limit")
+                                .long("limit")
+                                .takes_value(true)
+                                .default_value("12")
+                                .help("max number of events"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let from: u32 = matches.value_of("from").unwrap().parse().unwrap();
+                    let limit: u32 = matches.value_of("limit").unwrap().parse().unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let (_mrenclave, shard) = get_identifiers(matches);
+                    let top = sign_getter(
+                        matches,
+                        TrustedGetter::events(who.account(), shard, from, limit),
+                        &who,
+                    );
+                    let events: Vec<StfEvent> = perform_operation(matches, &top)
+                        .and_then(|v| Decode::decode(&mut v.as_slice()).ok())
+                        .unwrap_or_default();
+                    for e in events.iter() {
+                        println!("block {}: {}", e.block_number, decode_event(e));
+                    }
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("pending-calls")
+                .description("list the pending calls of an incognito account in keystore")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp).arg(
+                        Arg::with_name("accountid")
+                            .takes_value(true)
+                            .required(true)
+                            .value_name("SS58")
+                            .help("AccountId in ss58check format"),
+                    )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let (_mrenclave, shard) = get_identifiers(matches);
+                    let top = sign_getter(
+                        matches,
+                        TrustedGetter::pending_calls(who.account(), shard),
+                        &who,
+                    );
+                    let pending: Vec<TrustedCallSigned> = perform_operation(matches, &top)
+                        .and_then(|v| Decode::decode(&mut v.as_slice()).ok())
+                        .unwrap_or_default();
+                    for c in pending.iter() {
+                        println!("nonce {}: {:?}", c.nonce, c.call);
+                    }
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("create-multisig")
+                .description("register a k-of-n multisig incognito account")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("signer")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("AccountId of one of the members in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("threshold")
+                                .takes_value(true)
+                                .required(true)
+                                .help("number of approvals needed for a transfer"),
+                        )
+                        .arg(
+                            Arg::with_name("members")
+                                .takes_value(true)
+                                .required(true)
+                                .multiple(true)
+                                .value_name("SS58")
+                                .help("AccountIds of all members in ss58check format"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let signer = get_pair_from_str(matches, matches.value_of("signer").unwrap());
+                    let threshold: u16 = matches.value_of("threshold").unwrap().parse().unwrap();
+                    let members: Vec<AccountId> = matches
+                        .values_of("members")
+                        .unwrap()
+                        .map(get_accountid_from_str)
+                        .collect();
+                    let multisig = Multisig::new(members.clone(), threshold);
+                    println!("{}", multisig.account().to_ss58check());
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &signer);
+                    let top: TrustedOperation = TrustedCall::multisig_create(
+                        signer.account(),
+                        shard,
+                        members,
+                        threshold,
+                    )
+                    .sign(&signer, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("approve-multisig-transfer")
+                .description("approve a transfer from a multisig incognito account")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("signer")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("member's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("multisig")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("multisig's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("recipient's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("amount")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("U128")
+                                .help("amount to be transferred"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let signer = get_pair_from_str(matches, matches.value_of("signer").unwrap());
+                    let multisig = get_accountid_from_str(matches.value_of("multisig").unwrap());
+                    let to = get_accountid_from_str(matches.value_of("to").unwrap());
+                    let amount = u128::from_str_radix(matches.value_of("amount").unwrap(), 10)
+                        .expect("amount can be converted to u128");
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &signer);
+                    let top: TrustedOperation = TrustedCall::multisig_approve(
+                        signer.account(),
+                        shard,
+                        multisig.clone(),
+                        Box::new(TrustedCall::balance_transfer(
+                            multisig,
+                            to,
+                            shard,
+                            BalanceType::from_num(amount),
+                        )),
+                    )
+                    .sign(&signer, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("multisig-balance")
+                .description("query the balance of a multisig incognito account as a member")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("signer")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("member's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("multisig")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("multisig's AccountId in ss58check format"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let signer = get_pair_from_str(matches, matches.value_of("signer").unwrap());
+                    let multisig = get_accountid_from_str(matches.value_of("multisig").unwrap());
+                    let (_mrenclave, shard) = get_identifiers(matches);
+                    let top = sign_getter(
+                        matches,
+                        TrustedGetter::multisig_balance(
+                            signer.account(),
+                            shard,
+                            multisig,
+                        ),
+                        &signer,
+                    );
+                    let bal = perform_operation(matches, &top)
+                        .and_then(|v| BalanceType::decode(&mut v.as_slice()).ok())
+                        .unwrap_or_else(|| BalanceType::from_num(0));
+                    println!("{}", bal);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("unshield")
+                .description("move funds from an incognito account to an on-chain account")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("from")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("incognito sender's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("on-chain recipient's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("amount")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("U128")
+                                .help("amount to be unshielded"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_from = matches.value_of("from").unwrap();
+                    let arg_to = matches.value_of("to").unwrap();
+                    let amount = u128::from_str_radix(matches.value_of("amount").unwrap(), 10)
+                        .expect("amount can be converted to u128");
+                    let from = get_pair_from_str(matches, arg_from);
+                    let to = get_accountid_from_str(arg_to);
+                    println!(
+                        "send trusted call unshield from {} to {}: {}",
+                        from.account(),
+                        to,
+                        amount
+                    );
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &from);
+                    let top: TrustedOperation = TrustedCall::unshield(
+                        from.account(),
+                        to,
+                        shard, // for encointer we assume that every currency has its own shard. so shard == cid
+                        BalanceType::from_num(amount),
+                    )
+                    .sign(&from, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("schedule-transfer")
+                .description("set up a standing order between incognito accounts")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("from")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("sender's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("recipient's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("amount")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("U128")
+                                .help("amount to be transferred each time"),
+                        )
+                        .arg(
+                            Arg::with_name("start")
+                                .long("start")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("BLOCK")
+                                .help("block number of the first transfer"),
+                        )
+                        .arg(
+                            Arg::with_name("period")
+                                .long("period")
+                                .takes_value(true)
+                                .default_value("0")
+                                .value_name("BLOCKS")
+                                .help("number of blocks between two transfers"),
+                        )
+                        .arg(
+                            Arg::with_name("count")
+                                .long("count")
+                                .takes_value(true)
+                                .default_value("1")
+                                .help("total number of transfers"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_from = matches.value_of("from").unwrap();
+                    let arg_to = matches.value_of("to").unwrap();
+                    let amount = u128::from_str_radix(matches.value_of("amount").unwrap(), 10)
+                        .expect("amount can be converted to u128");
+                    let start: BlockNumber = matches.value_of("start").unwrap().parse().unwrap();
+                    let period: BlockNumber = matches.value_of("period").unwrap().parse().unwrap();
+                    let count: u32 = matches.value_of("count").unwrap().parse().unwrap();
+                    let from = get_pair_from_str(matches, arg_from);
+                    let to = get_accountid_from_str(arg_to);
+                    println!(
+                        "schedule {} transfers from {} to {} of {} each, starting at block {} every {} blocks",
+                        count,
+                        from.account(),
+                        to,
+                        amount,
+                        start,
+                        period
+                    );
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &from);
+                    let top: TrustedOperation = TrustedCall::schedule_transfer(
+                        from.account(),
+                        to,
+                        shard, // for encointer we assume that every currency has its own shard. so shard == cid
+                        BalanceType::from_num(amount),
+                        start,
+                        period,
+                        count,
+                    )
+                    .sign(&from, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("cancel-scheduled-transfer")
+                .description("cancel a standing order")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("owner's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("id")
+                                .takes_value(true)
+                                .required(true)
+                                .help("id of the standing order"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let id: u32 = matches.value_of("id").unwrap().parse().unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &who);
+                    let top: TrustedOperation = TrustedCall::cancel_scheduled_transfer(
+                        who.account(),
+                        shard,
+                        id,
+                    )
+                    .sign(&who, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("scheduled-transfers")
+                .description("list the standing orders of an incognito account in keystore")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp).arg(
+                        Arg::with_name("accountid")
+                            .takes_value(true)
+                            .required(true)
+                            .value_name("SS58")
+                            .help("AccountId in ss58check format"),
+                    )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let (_mrenclave, shard) = get_identifiers(matches);
+                    let top = sign_getter(
+                        matches,
+                        TrustedGetter::scheduled_transfers(
+                            who.account(),
+                            shard,
+                        ),
+                        &who,
+                    );
+                    let scheduled: Vec<ScheduledTransfer> = perform_operation(matches, &top)
+                        .and_then(|v| Decode::decode(&mut v.as_slice()).ok())
+                        .unwrap_or_default();
+                    for t in scheduled.iter() {
+                        println!(
+                            "{}: {} to {}, next at block {}, every {} blocks, {} remaining, deposit {}",
+                            t.id, t.amount, t.to, t.next_block, t.period, t.remaining, t.deposit
+                        );
+                    }
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("balance")
+                .description("query balance for incognito account in keystore")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("viewer")
+                                .long("viewer")
+                                .takes_value(true)
+                                .value_name("SS58")
+                                .help("sign with this viewing key instead of the account's key"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let (_mrenclave, shard) = get_identifiers(matches);
+                    let top = sign_viewable_getter(
+                        matches,
+                        TrustedGetter::balance(get_accountid_from_str(arg_who), shard),
+                        arg_who,
+                    );
+                    let res = perform_operation(matches, &top);
+                    // demurrage has already been applied by the enclave
+                    let bal = if let Some(v) = res {
+                        if let Ok(vd) = BalanceType::decode(&mut v.as_slice()) {
+                            vd
+                        } else {
+                            info!("could not decode value. maybe hasn't been set? {:x?}", v);
+                            BalanceType::from_num(0)
+                        }
+                    } else {
+                        BalanceType::from_num(0)
+                    };
+                    println!("{}", bal);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("add-viewing-key")
+                .description("grant a viewing key read access to an incognito account")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("owner's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("viewer")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("viewing key's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("balance")
+                                .long("balance")
+                                .help("allow to query the balance"),
+                        )
+                        .arg(
+                            Arg::with_name("transfer-history")
+                                .long("transfer-history")
+                                .help("allow to query the transfer history"),
+                        )
+                        .group(
+                            ArgGroup::with_name("scope")
+                                .args(&["balance", "transfer-history"])
+                                .multiple(true)
+                                .required(true),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let viewer = get_accountid_from_str(matches.value_of("viewer").unwrap());
+                    let mut scopes = Vec::new();
+                    if matches.is_present("balance") {
+                        scopes.push(ViewingScope::Balance);
+                    }
+                    if matches.is_present("transfer-history") {
+                        scopes.push(ViewingScope::TransferHistory);
+                    }
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &who);
+                    let top: TrustedOperation = TrustedCall::register_viewing_key(
+                        who.account(),
+                        shard,
+                        viewer,
+                        scopes,
+                    )
+                    .sign(&who, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("revoke-viewing-key")
+                .description("revoke the read access of a viewing key")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("owner's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("viewer")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("viewing key's AccountId in ss58check format"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let viewer = get_accountid_from_str(matches.value_of("viewer").unwrap());
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &who);
+                    let top: TrustedOperation = TrustedCall::revoke_viewing_key(
+                        who.account(),
+                        shard,
+                        viewer,
+                    )
+                    .sign(&who, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("transfer-history")
+                .description("list transfers of an incognito account in keystore")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .takes_value(true)
+                                .default_value("0")
+                                .help("index of the first record"),
+                        )
+                        .arg(
+                            Arg::with_name("limit")
+                                .long("limit")
+                                .takes_value(true)
+                                .default_value("16")
+                                .help("max number of records"),
+                        )
+                        .arg(
+                            Arg::with_name("viewer")
+                                .long("viewer")
+                                .takes_value(true)
+                                .value_name("SS58")
+                                .help("sign with this viewing key instead of the account's key"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let from: u32 = matches.value_of("from").unwrap().parse().unwrap();
+                    let limit: u32 = matches.value_of("limit").unwrap().parse().unwrap();
+                    let (_mrenclave, shard) = get_identifiers(matches);
+                    let top = sign_viewable_getter(
+                        matches,
+                        TrustedGetter::transfer_history(
+                            get_accountid_from_str(arg_who),
+                            shard,
+                            from,
+                            limit,
+                        ),
+                        arg_who,
+                    );
+                    let records: Vec<TransferRecord> = perform_operation(matches, &top)
+                        .and_then(|v| Decode::decode(&mut v.as_slice()).ok())
+                        .unwrap_or_default();
+                    for r in records.iter() {
+                        let memo = r
+                            .memo
+                            .as_ref()
+                            .map(|m| String::from_utf8_lossy(m).into_owned())
+                            .unwrap_or_default();
+                        println!(
+                            "block {}: {:?} {} {} {}",
+                            r.block_number, r.direction, r.counterparty, r.amount, memo
+                        );
+                    }
                     Ok(())
                 }),
         )
@@ -325,6 +1201,272 @@ pub fn cmd<'a>(
                     Ok(())
                 }),
         )
+        .add_cmd(
+            Command::new("set-ceremony-parameter")
+                .description("change a ceremony parameter of the currency (bootstrappers only)")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("signer")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("bootstrapper's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("reward")
+                                .long("reward")
+                                .takes_value(true)
+                                .value_name("U128")
+                                .help("ceremony reward"),
+                        )
+                        .arg(
+                            Arg::with_name("time-tolerance")
+                                .long("time-tolerance")
+                                .takes_value(true)
+                                .value_name("MS")
+                                .help("time tolerance for meetups in ms"),
+                        )
+                        .arg(
+                            Arg::with_name("location-tolerance")
+                                .long("location-tolerance")
+                                .takes_value(true)
+                                .value_name("M")
+                                .help("location tolerance for meetups in m"),
+                        )
+                        .group(
+                            ArgGroup::with_name("parameter")
+                                .args(&["reward", "time-tolerance", "location-tolerance"])
+                                .required(true),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_signer = matches.value_of("signer").unwrap();
+                    let signer = get_pair_from_str(matches, arg_signer);
+                    let parameter = if let Some(v) = matches.value_of("reward") {
+                        let reward = u128::from_str_radix(v, 10)
+                            .ok()
+                            .and_then(BalanceType::checked_from_num);
+                        match reward {
+                            Some(reward) => CeremonyParameter::Reward(reward),
+                            None => {
+                                error!("reward {} is out of range", v);
+                                return Ok(());
+                            }
+                        }
+                    } else if let Some(v) = matches.value_of("time-tolerance") {
+                        CeremonyParameter::TimeTolerance(v.parse().expect("tolerance must be u64"))
+                    } else {
+                        let v = matches.value_of("location-tolerance").unwrap();
+                        CeremonyParameter::LocationTolerance(
+                            v.parse().expect("tolerance must be u32"),
+                        )
+                    };
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &signer);
+                    println!("send TrustedCall::ceremonies_set_parameter {:?}", parameter);
+                    let top: TrustedOperation = TrustedCall::ceremonies_set_parameter(
+                        signer.account(),
+                        shard, // for encointer we assume that every currency has its own shard. so shard == cid
+                        parameter,
+                    )
+                    .sign(&signer, nonce, &mrenclave, &shard)
+                    .into();
+                    perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("parameter-changes")
+                .description("list changes of ceremony parameters of the currency")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .takes_value(true)
+                                .default_value("0")
+                                .help("index of the first change"),
+                        )
+                        .arg(
+                            Arg::with_name("limit")
+                                .long("limit")
+                                .takes_value(true)
+                                .default_value("32")
+                                .help("max number of changes"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let from: u32 = matches.value_of("from").unwrap().parse().unwrap();
+                    let limit: u32 = matches.value_of("limit").unwrap().parse().unwrap();
+                    let (_mrenclave, shard) = get_identifiers(matches);
+                    let top: TrustedOperation =
+                        PublicGetter::parameter_changes(shard, from, limit).into();
+                    let changes: Vec<ParameterChange> = perform_operation(matches, &top)
+                        .and_then(|v| Decode::decode(&mut v.as_slice()).ok())
+                        .unwrap_or_default();
+                    for c in changes.iter() {
+                        println!(
+                            "block {}: {:?} by {}",
+                            c.block_number, c.parameter, c.changed_by
+                        );
+                    }
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("create-poll")
+                .description("open a poll for the currency community (ceremony master or bootstrappers only)")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("signer")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("ceremony master's or bootstrapper's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("proposal")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("STRING")
+                                .help("what the poll is about"),
+                        )
+                        .arg(
+                            Arg::with_name("options")
+                                .long("options")
+                                .takes_value(true)
+                                .default_value("2")
+                                .help("number of options to vote for"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let signer = get_pair_from_str(matches, matches.value_of("signer").unwrap());
+                    let proposal = matches.value_of("proposal").unwrap().as_bytes().to_vec();
+                    let options: u8 = matches.value_of("options").unwrap().parse().unwrap();
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &signer);
+                    let top: TrustedOperation = TrustedCall::polls_create(
+                        signer.account(),
+                        shard,
+                        proposal,
+                        options,
+                    )
+                    .sign(&signer, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("vote")
+                .description("vote anonymously in a poll, weighted by reputation")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("signer")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("voter's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("poll")
+                                .takes_value(true)
+                                .required(true)
+                                .help("index of the poll"),
+                        )
+                        .arg(
+                            Arg::with_name("option")
+                                .takes_value(true)
+                                .required(true)
+                                .help("index of the option to vote for"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let signer = get_pair_from_str(matches, matches.value_of("signer").unwrap());
+                    let poll: PollIndexType = matches.value_of("poll").unwrap().parse().unwrap();
+                    let option: u8 = matches.value_of("option").unwrap().parse().unwrap();
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &signer);
+                    let top: TrustedOperation = TrustedCall::polls_vote(
+                        signer.account(),
+                        shard,
+                        poll,
+                        option,
+                    )
+                    .sign(&signer, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("close-poll")
+                .description("close a poll and publish its result (ceremony master or bootstrappers only)")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("signer")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("ceremony master's or bootstrapper's AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("poll")
+                                .takes_value(true)
+                                .required(true)
+                                .help("index of the poll"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let signer = get_pair_from_str(matches, matches.value_of("signer").unwrap());
+                    let poll: PollIndexType = matches.value_of("poll").unwrap().parse().unwrap();
+                    let (mrenclave, shard) = get_identifiers(matches);
+                    let nonce = get_trusted_nonce(perform_operation, matches, &signer);
+                    let top: TrustedOperation = TrustedCall::polls_close(
+                        signer.account(),
+                        shard,
+                        poll,
+                    )
+                    .sign(&signer, nonce, &mrenclave, &shard)
+                    .into();
+                    let _ = perform_operation(matches, &top);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("poll-result")
+                .description("query the result of a closed poll")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp).arg(
+                        Arg::with_name("poll")
+                            .takes_value(true)
+                            .required(true)
+                            .help("index of the poll"),
+                    )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let poll: PollIndexType = matches.value_of("poll").unwrap().parse().unwrap();
+                    let (_mrenclave, shard) = get_identifiers(matches);
+                    let top: TrustedOperation = PublicGetter::poll_result(shard, poll).into();
+                    match perform_operation(matches, &top)
+                        .and_then(|v| PollResult::decode(&mut v.as_slice()).ok())
+                    {
+                        Some(result) => {
+                            println!("{}", String::from_utf8_lossy(&result.proposal));
+                            for (option, weight) in result.tallies.iter().enumerate() {
+                                println!("option {}: {}", option, weight);
+                            }
+                            println!("{} voters", result.voters);
+                        }
+                        None => println!("poll does not exist or is still open"),
+                    }
+                    Ok(())
+                }),
+        )
         .add_cmd(
             Command::new("register-participant")
                 .description("register participant for next encointer ceremony")
@@ -349,25 +1491,37 @@ pub fn cmd<'a>(
                     let accountid = get_accountid_from_str(arg_who);
                     let who = get_pair_from_str(matches, arg_who);
                     let (mrenclave, shard) = get_identifiers(matches);
-                    let api = get_chain_api(matches);
-                    let cindex = get_ceremony_index(&api);
-                    let nonce = 0; // FIXME: hard coded for now
+                    let nonce = get_trusted_nonce(perform_operation, matches, &who);
                     println!(
                         "send TrustedCall::register_participant for {}",
-                        who.public(),
+                        who.account(),
                     );
                     let proof = if matches.is_present("reputation") {
-                        Some(prove_attendance(&accountid, shard, cindex - 1, &who))
+                        // prove the latest reputation which hasn't been used yet
+                        let history = get_ceremony_history(perform_operation, matches, &who);
+                        match history
+                            .iter()
+                            .rev()
+                            .find(|r| r.reputation == Reputation::VerifiedUnlinked)
+                        {
+                            Some(record) => {
+                                Some(prove_attendance(&accountid, shard, record.cindex, &who))
+                            }
+                            None => {
+                                println!("no unused reputation found for {}", arg_who);
+                                return Ok(());
+                            }
+                        }
                     } else {
                         None
                     };
                     println!("reputation: {:?}", proof);
                     let top: TrustedOperation = TrustedCall::ceremonies_register_participant(
-                        sr25519_core::Public::from(who.public()),
+                        who.account(),
                         shard, // for encointer we assume that every currency has its own shard. so shard == cid
                         proof,
                     )
-                    .sign(&sr25519_core::Pair::from(who), nonce, &mrenclave, &shard)
+                    .sign(&who, nonce, &mrenclave, &shard)
                     .into();
                     perform_operation(matches, &top);
                     Ok(())
@@ -389,13 +1543,15 @@ pub fn cmd<'a>(
                     let arg_who = matches.value_of("accountid").unwrap();
                     let who = get_pair_from_str(matches, arg_who);
                     let (_mrenclave, shard) = get_identifiers(matches);
-                    debug!("send TrustedGetter::get_registration for {}", who.public());
-                    let top: TrustedOperation = TrustedGetter::participant_index(
-                        sr25519_core::Public::from(who.public()),
-                        shard, // for encointer we assume that every currency has its own shard. so shard == cid
-                    )
-                    .sign(&sr25519_core::Pair::from(who))
-                    .into();
+                    debug!("send TrustedGetter::get_registration for {}", who.account());
+                    let top = sign_getter(
+                        matches,
+                        TrustedGetter::participant_index(
+                            who.account(),
+                            shard, // for encointer we assume that every currency has its own shard. so shard == cid
+                        ),
+                        &who,
+                    );
                     let part = perform_operation(matches, &top).unwrap();
                     let participant: ParticipantIndexType =
                         Decode::decode(&mut part.as_slice()).unwrap();
@@ -416,8 +1572,18 @@ pub fn cmd<'a>(
                     )
                 })
                 .runner(move |_args: &str, matches: &ArgMatches<'_>| {
-                    match get_meetup_index_and_location(perform_operation, matches) {
-                        Ok((index, _location)) => println!("{}", index),
+                    match get_meetup_assignment(perform_operation, matches) {
+                        Ok(a) => {
+                            println!("index: {}", a.meetup_index);
+                            println!("location: {:?}", a.location);
+                            match a.time {
+                                Some(t) => println!("time: {}", t),
+                                None => println!("time: unknown"),
+                            }
+                            for p in a.participants.iter() {
+                                println!("participant: {}", p);
+                            }
+                        }
                         Err(e) => panic!(e),
                     }
                     Ok(())
@@ -447,7 +1613,7 @@ pub fn cmd<'a>(
                     let arg_who = matches.value_of("accountid").unwrap();
                     let who = get_pair_from_str(matches, arg_who);
                     let (mrenclave, shard) = get_identifiers(matches);
-                    let nonce = 0; // FIXME: hard coded for now
+                    let nonce = get_trusted_nonce(perform_operation, matches, &who);
                     let attestation_args: Vec<_> =
                         matches.values_of("attestations").unwrap().collect();
                     let mut attestations: Vec<Attestation<MultiSignature, AccountId32, Moment>> =
@@ -459,13 +1625,13 @@ pub fn cmd<'a>(
                     debug!("attestations: {:?}", attestations);
                     println!(
                         "send TrustedCall::register_attestations for {}",
-                        who.public()
+                        who.account()
                     );
                     let top: TrustedOperation = TrustedCall::ceremonies_register_attestations(
-                        sr25519_core::Public::from(who.public()),
+                        who.account(),
                         attestations,
                     )
-                    .sign(&sr25519_core::Pair::from(who), nonce, &mrenclave, &shard)
+                    .sign(&who, nonce, &mrenclave, &shard)
                     .into();
                     perform_operation(matches, &top);
                     Ok(())
@@ -487,13 +1653,15 @@ pub fn cmd<'a>(
                     let arg_who = matches.value_of("accountid").unwrap();
                     let who = get_pair_from_str(matches, arg_who);
                     let (_mrenclave, shard) = get_identifiers(matches);
-                    println!("send TrustedGetter::get_attestations for {}", who.public(),);
-                    let top: TrustedOperation = TrustedGetter::attestations(
-                        sr25519_core::Public::from(who.public()),
-                        shard, // for encointer we assume that every currency has its own shard. so shard == cid
-                    )
-                    .sign(&sr25519_core::Pair::from(who))
-                    .into();
+                    println!("send TrustedGetter::get_attestations for {}", who.account(),);
+                    let top = sign_getter(
+                        matches,
+                        TrustedGetter::attestations(
+                            who.account(),
+                            shard, // for encointer we assume that every currency has its own shard. so shard == cid
+                        ),
+                        &who,
+                    );
                     let att_enc = perform_operation(matches, &top).unwrap();
                     let attestations: Vec<AccountId> =
                         Decode::decode(&mut att_enc.as_slice()).unwrap();
@@ -501,6 +1669,81 @@ pub fn cmd<'a>(
                     Ok(())
                 }),
         )
+        .add_cmd(
+            Command::new("reputation")
+                .description("get the reputation of an account for a past ceremony")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("accountid")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("SS58")
+                                .help("AccountId in ss58check format"),
+                        )
+                        .arg(
+                            Arg::with_name("cindex")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("U32")
+                                .help("ceremony index"),
+                        )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let cindex: CeremonyIndexType = matches
+                        .value_of("cindex")
+                        .unwrap()
+                        .parse()
+                        .expect("cindex must be a number");
+                    let (_mrenclave, shard) = get_identifiers(matches);
+                    let top = sign_getter(
+                        matches,
+                        TrustedGetter::reputation(
+                            who.account(),
+                            shard, // for encointer we assume that every currency has its own shard. so shard == cid
+                            cindex,
+                        ),
+                        &who,
+                    );
+                    let rep_enc = perform_operation(matches, &top).unwrap();
+                    let reputation: Reputation = Decode::decode(&mut rep_enc.as_slice()).unwrap();
+                    println!("{:?}", reputation);
+                    Ok(())
+                }),
+        )
+        .add_cmd(
+            Command::new("ceremony-history")
+                .description("list all past ceremonies of an account")
+                .options(|app| {
+                    app.setting(AppSettings::ColoredHelp).arg(
+                        Arg::with_name("accountid")
+                            .takes_value(true)
+                            .required(true)
+                            .value_name("SS58")
+                            .help("AccountId in ss58check format"),
+                    )
+                })
+                .runner(move |_args: &str, matches: &ArgMatches<'_>| {
+                    let arg_who = matches.value_of("accountid").unwrap();
+                    let who = get_pair_from_str(matches, arg_who);
+                    let history = get_ceremony_history(perform_operation, matches, &who);
+                    println!("cindex participant meetup attended rewarded reputation");
+                    for r in history.iter() {
+                        println!(
+                            "{} {} {} {} {} {:?}",
+                            r.cindex,
+                            r.participant_index,
+                            r.meetup_index,
+                            r.attended,
+                            r.rewarded,
+                            r.reputation
+                        );
+                    }
+                    Ok(())
+                }),
+        )
         .add_cmd(
             Command::new("new-claim")
                 .description("create a fresh claim of attendance for account")
@@ -531,27 +1774,27 @@ pub fn cmd<'a>(
                         .unwrap();
 
                     let (_mrenclave, shard) = get_identifiers(matches);
-                    let (mindex, mlocation) =
-                        match get_meetup_index_and_location(perform_operation, matches) {
-                            Ok((m, l)) => (m, l),
-                            Err(e) => panic!(e),
-                        };
+                    let assignment = match get_meetup_assignment(perform_operation, matches) {
+                        Ok(a) => a,
+                        Err(e) => panic!(e),
+                    };
+                    info!("meetup time: {:?}", assignment.time);
 
                     let api = get_chain_api(matches);
-                    let mtime = get_meetup_time(&api, mlocation);
-                    info!("meetup time: {:?}", mtime);
                     let cindex = api
                         .get_storage_value("EncointerScheduler", "CurrentCeremonyIndex", None)
                         .unwrap();
 
                     let claim = ClaimOfAttendance::<AccountId, Moment> {
-                        claimant_public: who.public().into(),
+                        claimant_public: who.account(),
                         currency_identifier: shard,
                         ceremony_index: cindex,
                         // ceremony_index: Default::default(),
-                        meetup_index: mindex,
-                        location: mlocation,
-                        timestamp: mtime.unwrap(),
+                        meetup_index: assignment.meetup_index,
+                        location: assignment.location,
+                        timestamp: assignment
+                            .time
+                            .expect("meetup time is only known while ASSIGNING or ATTESTING"),
                         number_of_participants_confirmed: n_participants,
                     };
                     debug!("claim: {:?}", claim);
@@ -587,6 +1830,28 @@ pub fn cmd<'a>(
         .into_cmd("trusted")
 }
 
+/// decodes the event with the types of the node runtime, which shares the pallets with the
+/// enclave. Falls back to hex if it doesn't decode
+fn decode_event(e: &StfEvent) -> String {
+    let input = &mut e.event.as_slice();
+    let decoded = match e.pallet {
+        EventPallet::System => system::Event::<Runtime>::decode(input).map(|e| format!("{:?}", e)),
+        EventPallet::EncointerScheduler => {
+            encointer_scheduler::Event::decode(input).map(|e| format!("{:?}", e))
+        }
+        EventPallet::EncointerCeremonies => {
+            encointer_ceremonies::Event::<Runtime>::decode(input).map(|e| format!("{:?}", e))
+        }
+        EventPallet::EncointerCurrencies => {
+            encointer_currencies::Event::<Runtime>::decode(input).map(|e| format!("{:?}", e))
+        }
+        EventPallet::EncointerBalances => {
+            encointer_balances::Event::<Runtime>::decode(input).map(|e| format!("{:?}", e))
+        }
+    };
+    decoded.unwrap_or_else(|_| format!("{:?} 0x{}", e.pallet, hex::encode(&e.event)))
+}
+
 fn get_keystore_path(matches: &ArgMatches<'_>) -> PathBuf {
     let (_mrenclave, shard) = get_identifiers(matches);
     PathBuf::from(&format!("{}/{}", KEYSTORE_PATH, shard.encode().to_base58()))
@@ -617,40 +1882,151 @@ pub fn get_identifiers(matches: &ArgMatches<'_>) -> ([u8; 32], ShardIdentifier)
 // TODO this function is redundant with client::main
 fn get_accountid_from_str(account: &str) -> AccountId {
     match &account[..2] {
-        "//" => sr25519::Pair::from_string(account, None)
+        "//" => sr25519_core::Pair::from_string(account, None)
             .unwrap()
             .public()
-            .into_account(),
-        _ => sr25519::Public::from_ss58check(account)
-            .unwrap()
-            .into_account(),
+            .into(),
+        _ => AccountId::from_ss58check(account).unwrap(),
     }
 }
 
 // TODO this function is redundant with client::main
 // get a pair either form keyring (well known keys) or from the store
-fn get_pair_from_str(matches: &ArgMatches<'_>, account: &str) -> sr25519::AppPair {
+fn get_pair_from_str(matches: &ArgMatches<'_>, account: &str) -> KeyPair {
     info!("getting pair for {}", account);
     match &account[..2] {
-        "//" => sr25519::AppPair::from_string(account, None).unwrap(),
+        "//" => sr25519_core::Pair::from_string(account, None)
+            .unwrap()
+            .into(),
         _ => {
             info!("fetching from keystore at {}", &KEYSTORE_PATH);
             // open store without password protection
             let store =
                 LocalKeystore::open(get_keystore_path(matches), None).expect("store should exist");
             info!("store opened");
-            let _pair = store
-                .key_pair::<sr25519::AppPair>(
-                    &sr25519::Public::from_ss58check(account).unwrap().into(),
-                )
-                .unwrap();
+            let pair = find_key_pair(&store, &get_accountid_from_str(account))
+                .expect("account should be in keystore");
             info!("key pair fetched");
             drop(store);
-            _pair
+            pair
+        }
+    }
+}
+
+/// the store is indexed by public key. For ecdsa the account is a hash of it, so we have to
+/// compare against every key of each scheme
+fn find_key_pair(store: &LocalKeystore, account: &AccountId) -> Option<KeyPair> {
+    if let Some(public) = store
+        .public_keys::<sr25519::AppPublic>()
+        .ok()?
+        .into_iter()
+        .find(|p| {
+            &MultiSigner::from(sr25519_core::Public::from(p.clone())).into_account() == account
+        })
+    {
+        let pair = store.key_pair::<sr25519::AppPair>(&public).ok()?;
+        return Some(sr25519_core::Pair::from(pair).into());
+    }
+    if let Some(public) = store
+        .public_keys::<ed25519::AppPublic>()
+        .ok()?
+        .into_iter()
+        .find(|p| {
+            &MultiSigner::from(ed25519_core::Public::from(p.clone())).into_account() == account
+        })
+    {
+        let pair = store.key_pair::<ed25519::AppPair>(&public).ok()?;
+        return Some(ed25519_core::Pair::from(pair).into());
+    }
+    let public = store
+        .public_keys::<ecdsa::AppPublic>()
+        .ok()?
+        .into_iter()
+        .find(|p| {
+            &MultiSigner::from(ecdsa_core::Public::from(p.clone())).into_account() == account
+        })?;
+    let pair = store.key_pair::<ecdsa::AppPair>(&public).ok()?;
+    Some(ecdsa_core::Pair::from(pair).into())
+}
+
+/// query the next nonce of an incognito account from the shard state
+fn get_trusted_nonce(
+    perform_operation: &dyn Fn(&ArgMatches<'_>, &TrustedOperation) -> Option<Vec<u8>>,
+    matches: &ArgMatches<'_>,
+    who: &KeyPair,
+) -> u32 {
+    let top = sign_getter(matches, TrustedGetter::nonce(who.account()), who);
+    let nonce = if let Some(v) = perform_operation(matches, &top) {
+        if let Ok(nonce) = u32::decode(&mut v.as_slice()) {
+            nonce
+        } else {
+            info!("could not decode nonce. maybe hasn't been set? {:x?}", v);
+            0
+        }
+    } else {
+        0
+    };
+    debug!("trusted nonce of {} is {}", who.account(), nonce);
+    nonce
+}
+
+/// sign a getter for the mrenclave and shard given on the command line. It is valid for
+/// GETTER_VALIDITY_PERIOD blocks around the latest block of the node.
+fn sign_getter(matches: &ArgMatches<'_>, getter: TrustedGetter, who: &KeyPair) -> TrustedOperation {
+    let (mrenclave, shard) = get_identifiers(matches);
+    let now = get_block_number(&get_chain_api(matches));
+    let getter = if matches.is_present("prove") {
+        TrustedGetter::with_proof(Box::new(getter))
+    } else {
+        getter
+    };
+    getter
+        .sign(
+            who,
+            &mrenclave,
+            &shard,
+            now.saturating_sub(GETTER_VALIDITY_PERIOD),
+            now + GETTER_VALIDITY_PERIOD,
+        )
+        .into()
+}
+
+/// signs `getter` with the key of the account it queries or, if `--viewer` is given, with that
+/// viewing key
+fn sign_viewable_getter(
+    matches: &ArgMatches<'_>,
+    getter: TrustedGetter,
+    arg_who: &str,
+) -> TrustedOperation {
+    match matches.value_of("viewer") {
+        Some(arg_viewer) => {
+            let viewer = get_pair_from_str(matches, arg_viewer);
+            sign_getter(
+                matches,
+                TrustedGetter::viewed(viewer.account(), Box::new(getter)),
+                &viewer,
+            )
         }
+        None => sign_getter(matches, getter, &get_pair_from_str(matches, arg_who)),
     }
 }
 
+fn get_ceremony_history(
+    perform_operation: &dyn Fn(&ArgMatches<'_>, &TrustedOperation) -> Option<Vec<u8>>,
+    matches: &ArgMatches<'_>,
+    who: &KeyPair,
+) -> Vec<CeremonyRecord> {
+    let (_mrenclave, shard) = get_identifiers(matches);
+    let top = sign_getter(
+        matches,
+        TrustedGetter::ceremony_history(who.account(), shard),
+        who,
+    );
+    perform_operation(matches, &top)
+        .and_then(|v| Decode::decode(&mut v.as_slice()).ok())
+        .unwrap_or_default()
+}
+
 fn get_chain_api(matches: &ArgMatches<'_>) -> Api<sr25519::Pair> {
     let url = format!(
         "{}:{}",
@@ -668,7 +2044,7 @@ fn get_block_number(api: &Api<sr25519::Pair>) -> BlockNumber {
     hdr.number
 }
 
-fn get_demurrage_per_block(api: &Api<sr25519::Pair>, cid: CurrencyIdentifier) -> BalanceType {
+pub fn get_demurrage_per_block(api: &Api<sr25519::Pair>, cid: CurrencyIdentifier) -> BalanceType {
     let cp: CurrencyPropertiesType = api
         .get_storage_map("EncointerCurrencies", "CurrencyProperties", cid, None)
         .expect("unknown currency");
@@ -676,7 +2052,7 @@ fn get_demurrage_per_block(api: &Api<sr25519::Pair>, cid: CurrencyIdentifier) ->
     cp.demurrage_per_block
 }
 
-fn apply_demurrage(
+pub fn apply_demurrage(
     entry: BalanceEntry<BlockNumber>,
     current_block: BlockNumber,
     demurrage_per_block: BalanceType,
@@ -693,89 +2069,32 @@ fn apply_demurrage(
     entry.principal.checked_mul(exp_result).unwrap()
 }
 
-fn get_current_phase(api: &Api<sr25519::Pair>) -> CeremonyPhaseType {
-    api.get_storage_value("EncointerScheduler", "CurrentPhase", None)
-        .or_else(|| Some(CeremonyPhaseType::default()))
-        .unwrap()
-}
-
-fn get_meetup_index_and_location<'a>(
-    perform_operation: &'a dyn Fn(&ArgMatches<'_>, &TrustedOperation) -> Option<Vec<u8>>,
+fn get_meetup_assignment(
+    perform_operation: &dyn Fn(&ArgMatches<'_>, &TrustedOperation) -> Option<Vec<u8>>,
     matches: &ArgMatches<'_>,
-) -> Result<(MeetupIndexType, Location), String> {
+) -> Result<MeetupAssignment, String> {
     let arg_who = matches.value_of("accountid").unwrap();
-    // println!("arg_who = {:?}", arg_who);
     let who = get_pair_from_str(matches, arg_who);
 
     let (_mrenclave, shard) = get_identifiers(matches);
-    let top: TrustedOperation = TrustedGetter::meetup_index(who.public().into(), shard)
-        .sign(&sr25519_core::Pair::from(who))
-        .into();
+    let top = sign_getter(
+        matches,
+        TrustedGetter::meetup_assignment(who.account(), shard),
+        &who,
+    );
 
     let res = perform_operation(matches, &top).unwrap();
-    let m_index: MeetupIndexType = Decode::decode(&mut res.as_slice()).unwrap();
-    if m_index == 0 {
-        return Err(format!(
-            "participant {} has not been assigned to a meetup. Meetup Index is 0",
+    let assignment: Option<MeetupAssignment> = Decode::decode(&mut res.as_slice()).unwrap();
+    match assignment {
+        Some(a) => {
+            info!("got meetup assignment {:?}", a);
+            Ok(a)
+        }
+        None => Err(format!(
+            "participant {} has not been assigned to a meetup",
             arg_who
-        ));
+        )),
     }
-
-    let api = get_chain_api(matches);
-    let m_location = get_meetup_location(&api, m_index, shard);
-
-    if m_location.is_none() {
-        return Err(format!(
-            "participant {} has not been assigned to a meetup. Location is None",
-            arg_who
-        ));
-    };
-    info!("got index {}", m_index);
-    info!("got location: {:?}", m_location);
-    Ok((m_index, m_location.unwrap()))
-}
-
-fn get_meetup_location(
-    api: &Api<sr25519::Pair>,
-    m_index: MeetupIndexType,
-    cid: CurrencyIdentifier,
-) -> Option<Location> {
-    api.get_storage_map("EncointerCurrencies", "Locations", cid, None)
-        .map(|locs: Vec<Location>| locs[(m_index - 1) as usize])
-}
-
-fn get_meetup_time(api: &Api<sr25519::Pair>, mlocation: Location) -> Option<Moment> {
-    let mlon: f64 = mlocation.lon.lossy_into();
-    // as long as the runtime pallet is rounding lon, we should do so too
-    let mlon = mlon.round();
-    debug!("meetup longitude: {}", mlon);
-    let next_phase_timestamp: Moment = api
-        .get_storage_value("EncointerScheduler", "NextPhaseTimestamp", None)
-        .unwrap();
-    debug!("next phase timestamp: {}", next_phase_timestamp);
-
-    let attesting_start = match get_current_phase(api) {
-        CeremonyPhaseType::ASSIGNING => next_phase_timestamp, // - next_phase_timestamp.rem(ONE_DAY),
-        CeremonyPhaseType::ATTESTING => {
-            let attesting_duration: Moment = api
-                .get_storage_map(
-                    "EncointerScheduler",
-                    "PhaseDurations",
-                    CeremonyPhaseType::ATTESTING,
-                    None,
-                )
-                .unwrap();
-            next_phase_timestamp - attesting_duration //- next_phase_timestamp.rem(ONE_DAY)
-        }
-        CeremonyPhaseType::REGISTERING => {
-            panic!("ceremony phase must be ASSIGNING or ATTESTING to request meetup location.")
-        }
-    };
-    debug!("attesting start at: {}", attesting_start);
-    let mtime = ((attesting_start + ONE_DAY / 2) as i64 - (mlon * (ONE_DAY as f64) / 360.0) as i64)
-        as Moment;
-    debug!("meetup time at lon {}: {:?}", mlon, mtime);
-    Some(mtime)
 }
 
 fn sign_claim(
@@ -785,37 +2104,31 @@ fn sign_claim(
 ) -> Attestation<Signature, AccountId, Moment> {
     let pair = get_pair_from_str(matches, account_str);
     let accountid = get_accountid_from_str(account_str);
+    let signature = pair.sign(&claim.encode());
     Attestation {
         claim,
-        signature: Signature::from(sr25519_core::Signature::from(pair.sign(&claim.encode()))),
+        signature,
         public: accountid,
     }
 }
 
-fn get_ceremony_index(api: &Api<sr25519::Pair>) -> CeremonyIndexType {
-    api.get_storage_value("EncointerScheduler", "CurrentCeremonyIndex", None)
-        .unwrap()
-}
-
 fn prove_attendance(
     prover: &AccountId,
     cid: CurrencyIdentifier,
     cindex: CeremonyIndexType,
-    attendee: &sr25519::AppPair,
+    attendee: &KeyPair,
 ) -> ProofOfAttendance<Signature, AccountId32> {
-    let msg = (*prover, cindex);
+    let msg = (prover.clone(), cindex);
     debug!(
         "generating proof of attendance for {} and cindex: {}",
         prover, cindex
     );
     debug!("signature payload is {:x?}", msg.encode());
     ProofOfAttendance {
-        prover_public: AccountId32::from(*prover),
+        prover_public: prover.clone(),
         currency_identifier: cid,
         ceremony_index: cindex,
-        attendee_public: AccountId32::from(sr25519_core::Public::from(attendee.public())),
-        attendee_signature: Signature::from(sr25519_core::Signature::from(
-            attendee.sign(&msg.encode()),
-        )),
+        attendee_public: attendee.account(),
+        attendee_signature: attendee.sign(&msg.encode()),
     }
 }
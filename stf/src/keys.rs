@@ -0,0 +1,141 @@
+/*
+    Copyright 2019 Supercomputing Systems AG
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+
+*/
+
+//! Storage keys of the shard state which are needed outside the enclave too, to check the
+//! entries of a `StateProof`
+
+use crate::{AccountId, CurrencyIdentifier, TrustedGetter, PROVEN_EVENTS_PAGE_SIZE};
+use alloc::vec::Vec;
+use codec::Encode;
+use metadata::StorageHasher;
+
+pub fn storage_value_key(module_prefix: &str, storage_prefix: &str) -> Vec<u8> {
+    let mut bytes = sp_core::twox_128(module_prefix.as_bytes()).to_vec();
+    bytes.extend(&sp_core::twox_128(storage_prefix.as_bytes())[..]);
+    bytes
+}
+
+pub fn storage_map_key<K: Encode>(
+    module_prefix: &str,
+    storage_prefix: &str,
+    mapkey1: &K,
+    hasher1: &StorageHasher,
+) -> Vec<u8> {
+    let mut bytes = sp_core::twox_128(module_prefix.as_bytes()).to_vec();
+    bytes.extend(&sp_core::twox_128(storage_prefix.as_bytes())[..]);
+    bytes.extend(key_hash(mapkey1, hasher1));
+    bytes
+}
+
+pub fn storage_double_map_key<K: Encode, Q: Encode>(
+    module_prefix: &str,
+    storage_prefix: &str,
+    mapkey1: &K,
+    hasher1: &StorageHasher,
+    mapkey2: &Q,
+    hasher2: &StorageHasher,
+) -> Vec<u8> {
+    let mut bytes = sp_core::twox_128(module_prefix.as_bytes()).to_vec();
+    bytes.extend(&sp_core::twox_128(storage_prefix.as_bytes())[..]);
+    bytes.extend(key_hash(mapkey1, hasher1));
+    bytes.extend(key_hash(mapkey2, hasher2));
+    bytes
+}
+
+/// generates the key's hash depending on the StorageHasher selected
+fn key_hash<K: Encode>(key: &K, hasher: &StorageHasher) -> Vec<u8> {
+    let encoded_key = key.encode();
+    match hasher {
+        StorageHasher::Identity => encoded_key.to_vec(),
+        StorageHasher::Blake2_128 => sp_core::blake2_128(&encoded_key).to_vec(),
+        StorageHasher::Blake2_128Concat => {
+            // copied from substrate Blake2_128Concat::hash since StorageHasher is not public
+            let x: &[u8] = encoded_key.as_slice();
+            sp_core::blake2_128(x)
+                .iter()
+                .chain(x.iter())
+                .cloned()
+                .collect::<Vec<_>>()
+        }
+        StorageHasher::Blake2_256 => sp_core::blake2_256(&encoded_key).to_vec(),
+        StorageHasher::Twox128 => sp_core::twox_128(&encoded_key).to_vec(),
+        StorageHasher::Twox256 => sp_core::twox_256(&encoded_key).to_vec(),
+        StorageHasher::Twox64Concat => sp_core::twox_64(&encoded_key).to_vec(),
+    }
+}
+
+/// storage of the stf itself. There is no such pallet on chain, so it is never synced
+pub(crate) const STF_MODULE_PREFIX: &str = "SubstrateeStf";
+
+// get the AccountInfo key where the nonce is stored.
+// for incognito accounts this key must never be updated from the chain state
+pub fn nonce_key_hash(account: &AccountId) -> Vec<u8> {
+    storage_map_key(
+        "System",
+        "Account",
+        account,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+/// the `BalanceEntry` of `who`. Demurrage is applied to it when it is read
+pub fn balance_key(who: &AccountId, cid: &CurrencyIdentifier) -> Vec<u8> {
+    storage_double_map_key(
+        "EncointerBalances",
+        "Balance",
+        cid,
+        &StorageHasher::Blake2_128Concat,
+        who,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn proxies_key(owner: &AccountId) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "Proxies",
+        owner,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn event_key(who: &AccountId, index: u32) -> Vec<u8> {
+    storage_double_map_key(
+        STF_MODULE_PREFIX,
+        "Events",
+        who,
+        &StorageHasher::Blake2_128Concat,
+        &index,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+/// the storage keys whose values or absence `TrustedGetter::with_proof` proves for `getter`.
+/// Clients rebuild them to find the proven values. Getters of other accounts are never
+/// proven, the proof would reveal their raw storage
+pub fn proof_keys(getter: &TrustedGetter) -> Vec<Vec<u8>> {
+    match getter {
+        TrustedGetter::balance(who, cid) => alloc::vec![balance_key(who, cid)],
+        TrustedGetter::nonce(who) => alloc::vec![nonce_key_hash(who)],
+        TrustedGetter::proxies(who, _) => alloc::vec![proxies_key(who)],
+        TrustedGetter::events(who, _, from, limit) => {
+            let to = from.saturating_add((*limit).min(PROVEN_EVENTS_PAGE_SIZE));
+            (*from..to).map(|i| event_key(who, i)).collect()
+        }
+        _ => Vec::new(),
+    }
+}
@@ -28,36 +28,127 @@ extern crate alloc;
 #[cfg(feature = "std")]
 extern crate clap;
 
+use alloc::boxed::Box;
 use codec::{Compact, Decode, Encode};
-use sp_core::{sr25519, Pair, H256};
-use sp_runtime::{traits::Verify, AccountId32, AnySignature, MultiSignature};
+use derive_more::Display;
+use sp_core::{blake2_256, ecdsa, ed25519, sr25519, Pair, H256};
+use sp_runtime::{
+    traits::{IdentifyAccount, Verify},
+    AccountId32, MultiSignature, MultiSigner,
+};
 pub type ShardIdentifier = H256;
 pub use encointer_ceremonies::Attestation;
 pub use encointer_ceremonies::ProofOfAttendance;
-pub use encointer_currencies::CurrencyIdentifier;
+pub use encointer_ceremonies::{MeetupIndexType, ParticipantIndexType, Reputation};
+pub use encointer_currencies::{CurrencyIdentifier, Location};
+pub use encointer_scheduler::CeremonyIndexType;
 
+pub mod keys;
+pub mod merkle;
 #[cfg(feature = "sgx")]
 pub mod sgx;
+#[cfg(feature = "sgx")]
+pub mod sgx_tests;
 
 #[cfg(feature = "sgx")]
 use sgx_tstd as std;
+use std::string::String;
 use std::vec::Vec;
 
 #[cfg(feature = "std")]
 pub mod cli;
 
-pub type Signature = AnySignature;
+pub type Signature = MultiSignature;
 pub type AuthorityId = <Signature as Verify>::Signer;
-pub type AccountId = <Signature as Verify>::Signer;
+pub type AccountId = <AuthorityId as IdentifyAccount>::AccountId;
 pub type Hash = sp_core::H256;
+pub type BlockNumber = u32;
+
+/// Key of an incognito account. Trusted calls and getters can be signed with any of them
+#[derive(Clone)]
+pub enum KeyPair {
+    Sr25519(sr25519::Pair),
+    Ed25519(ed25519::Pair),
+    Ecdsa(ecdsa::Pair),
+}
+
+impl KeyPair {
+    pub fn sign(&self, payload: &[u8]) -> Signature {
+        match self {
+            KeyPair::Sr25519(pair) => pair.sign(payload).into(),
+            KeyPair::Ed25519(pair) => pair.sign(payload).into(),
+            KeyPair::Ecdsa(pair) => pair.sign(payload).into(),
+        }
+    }
+
+    /// for ecdsa this is the blake2 hash of the public key
+    pub fn account(&self) -> AccountId {
+        match self {
+            KeyPair::Sr25519(pair) => MultiSigner::from(pair.public()).into_account(),
+            KeyPair::Ed25519(pair) => MultiSigner::from(pair.public()).into_account(),
+            KeyPair::Ecdsa(pair) => MultiSigner::from(pair.public()).into_account(),
+        }
+    }
+}
+
+impl From<sr25519::Pair> for KeyPair {
+    fn from(pair: sr25519::Pair) -> Self {
+        KeyPair::Sr25519(pair)
+    }
+}
+
+impl From<ed25519::Pair> for KeyPair {
+    fn from(pair: ed25519::Pair) -> Self {
+        KeyPair::Ed25519(pair)
+    }
+}
+
+impl From<ecdsa::Pair> for KeyPair {
+    fn from(pair: ecdsa::Pair) -> Self {
+        KeyPair::Ecdsa(pair)
+    }
+}
+
+/// number of blocks a signed getter stays valid, counted in both directions from the
+/// block the client considers current. Accounts for a lagging enclave chain relay.
+pub const GETTER_VALIDITY_PERIOD: BlockNumber = 10;
 pub use encointer_balances::BalanceType;
 pub type BalanceTransferFn = ([u8; 2], AccountId, Compact<u128>);
 pub static BALANCE_MODULE: u8 = 4u8;
 pub static BALANCE_TRANSFER: u8 = 0u8;
 pub static SUBSRATEE_REGISTRY_MODULE: u8 = 8u8;
+// `unshield_funds(public_account: AccountId, amount: u128, shard: ShardIdentifier)` of the
+// registry of encointer-node v0.3.8-tee, which deposits `UnshieldedFunds`
 pub static UNSHIELD: u8 = 5u8;
 pub static CALL_CONFIRMED: u8 = 3u8;
 
+/// native units the registry locks per unit of community currency when funds are shielded, and
+/// releases when they are unshielded. The native token has 12 decimals
+pub const NATIVE_UNITS_PER_BALANCE: u128 = 1_000_000_000_000;
+
+/// The community currency credited for `amount` native units shielded on chain. Rounds down, so
+/// the shard never holds more than the registry has locked. `None` if out of range
+pub fn balance_from_native(amount: u128) -> Option<BalanceType> {
+    let units = BalanceType::checked_from_num(amount / NATIVE_UNITS_PER_BALANCE)?;
+    let fraction = BalanceType::from_num(amount % NATIVE_UNITS_PER_BALANCE)
+        / BalanceType::from_num(NATIVE_UNITS_PER_BALANCE);
+    units.checked_add(fraction)
+}
+
+/// The native units released on chain for unshielding `balance`. Rounds down. `None` if
+/// `balance` is negative
+pub fn balance_to_native(balance: BalanceType) -> Option<u128> {
+    if balance < BalanceType::from_num(0) {
+        return None;
+    }
+    let units: u128 = balance.int().checked_to_num()?;
+    let fraction: u128 =
+        (balance.frac() * BalanceType::from_num(NATIVE_UNITS_PER_BALANCE)).checked_to_num()?;
+    units
+        .checked_mul(NATIVE_UNITS_PER_BALANCE)?
+        .checked_add(fraction)
+}
+
 #[cfg(feature = "sgx")]
 pub type State = sp_io::SgxExternalities;
 
@@ -121,6 +212,26 @@ pub enum PublicGetter {
     location_tolerance(CurrencyIdentifier),
     time_tolerance(CurrencyIdentifier),
     scheduler_state(CurrencyIdentifier),
+    /// (currency, index of the first change, max number of changes)
+    parameter_changes(CurrencyIdentifier, u32, u32),
+    /// aggregated result of a poll. Only available once the poll is closed
+    poll_result(CurrencyIdentifier, PollIndexType),
+}
+
+impl PublicGetter {
+    pub fn currency(&self) -> &CurrencyIdentifier {
+        match self {
+            PublicGetter::total_issuance(cid)
+            | PublicGetter::participant_count(cid)
+            | PublicGetter::meetup_count(cid)
+            | PublicGetter::ceremony_reward(cid)
+            | PublicGetter::location_tolerance(cid)
+            | PublicGetter::time_tolerance(cid)
+            | PublicGetter::scheduler_state(cid)
+            | PublicGetter::parameter_changes(cid, _, _)
+            | PublicGetter::poll_result(cid, _) => cid,
+        }
+    }
 }
 
 #[derive(Encode, Decode, Clone, Debug)]
@@ -137,21 +248,365 @@ pub enum TrustedCall {
         Vec<Attestation<MultiSignature, AccountId32, u64>>,
     ),
     ceremonies_grant_reputation(AccountId, CurrencyIdentifier, AccountId32),
+    /// change a ceremony parameter of the shard. Needs to be signed by a bootstrapper or the admin
+    ceremonies_set_parameter(AccountId, CurrencyIdentifier, CeremonyParameter),
+    /// burn funds of an incognito account and release them to an on-chain account
+    unshield(AccountId, AccountId, CurrencyIdentifier, BalanceType),
+    /// transfer with a memo of at most `MAX_MEMO_LEN` bytes, e.g. an invoice reference.
+    /// The memo is only visible to sender and recipient through their transfer history
+    balance_transfer_with_memo(
+        AccountId,
+        AccountId,
+        CurrencyIdentifier,
+        BalanceType,
+        Vec<u8>,
+    ),
+    /// standing order: transfer `amount` at `start_block` and then every `period` blocks,
+    /// `count` times in total
+    schedule_transfer(
+        AccountId,
+        AccountId,
+        CurrencyIdentifier,
+        BalanceType,
+        BlockNumber,
+        BlockNumber,
+        u32,
+    ),
+    /// cancel a standing order by its id. Only the owner can cancel it
+    cancel_scheduled_transfer(AccountId, CurrencyIdentifier, u32),
+    /// open a poll with `options` choices. Needs to be signed by the ceremony master or a
+    /// bootstrapper
+    polls_create(AccountId, CurrencyIdentifier, Vec<u8>, u8),
+    /// vote for an option of an open poll. Weighted by the number of ceremonies the voter has
+    /// verified reputation for. Each account can vote once
+    polls_vote(AccountId, CurrencyIdentifier, PollIndexType, u8),
+    /// close a poll and publish its result. Same privileges as `polls_create`
+    polls_close(AccountId, CurrencyIdentifier, PollIndexType),
+    /// allow `viewer` to sign getters of the given scopes on behalf of the account. Replaces
+    /// the scopes of an already registered viewing key
+    register_viewing_key(AccountId, CurrencyIdentifier, AccountId, Vec<ViewingScope>),
+    /// revoke a viewing key
+    revoke_viewing_key(AccountId, CurrencyIdentifier, AccountId),
+    /// allow `delegate` to sign calls of the given kinds on behalf of the account until block
+    /// `expiry`. Replaces the permissions of an already registered proxy
+    add_proxy(
+        AccountId,
+        CurrencyIdentifier,
+        AccountId,
+        Vec<ProxyPermission>,
+        BlockNumber,
+    ),
+    remove_proxy(AccountId, CurrencyIdentifier, AccountId),
+    /// a call of the account given second, signed by one of its proxies
+    proxied(AccountId, AccountId, Box<TrustedCall>),
+    /// register the multisig account of `members` with `threshold`. The signer must be a member
+    multisig_create(AccountId, CurrencyIdentifier, Vec<AccountId>, u16),
+    /// approve a transfer from a multisig account. The transfer is dispatched with the
+    /// approval which reaches the threshold
+    multisig_approve(AccountId, CurrencyIdentifier, AccountId, Box<TrustedCall>),
+    /// dispatch all calls or none of them. All calls must belong to the signing account
+    batch(AccountId, Vec<TrustedCall>),
+}
+
+/// max size of an encoded `TrustedReturnValue`, the buffer the worker passes to the enclave.
+/// A proof of an absent key carries the two entries next to it
+pub const STATE_VALUE_MAX_SIZE: usize = 8192;
+
+/// max number of records returned by `TrustedGetter::transfer_history`. A full page of
+/// records with memos of `MAX_MEMO_LEN` must fit into `STATE_VALUE_MAX_SIZE`
+pub const TRANSFER_HISTORY_PAGE_SIZE: u32 = 16;
+
+/// max length of a transfer memo in bytes
+pub const MAX_MEMO_LEN: usize = 64;
+
+/// max number of events kept per account. The oldest are dropped first
+pub const MAX_EVENTS_PER_ACCOUNT: u32 = 64;
+
+/// max length of an encoded runtime event which is stored. The events of the sgx runtime are
+/// much shorter
+pub const MAX_EVENT_LEN: usize = 256;
+
+/// max number of events returned by `TrustedGetter::events`. A full page of events of
+/// `MAX_EVENT_LEN` must fit into `STATE_VALUE_MAX_SIZE`
+pub const EVENTS_PAGE_SIZE: u32 = 12;
+
+/// max number of events proven by `TrustedGetter::with_proof(events)`. Each of them comes with
+/// its storage key and merkle path
+pub const PROVEN_EVENTS_PAGE_SIZE: u32 = 2;
+
+/// The pallet which deposited an `StfEvent`. The variant indices of `sgx_runtime::Event` are
+/// not known outside the enclave, so events are stored per pallet
+#[derive(Encode, Decode, Copy, Clone, Debug, PartialEq)]
+pub enum EventPallet {
+    System,
+    EncointerScheduler,
+    EncointerCeremonies,
+    EncointerCurrencies,
+    EncointerBalances,
+}
+
+/// A runtime event deposited while executing a call of the account it is stored for
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct StfEvent {
+    pub block_number: BlockNumber,
+    pub pallet: EventPallet,
+    /// the encoded event of `pallet`
+    pub event: Vec<u8>,
+}
+
+/// max number of standing orders an account can have per shard
+pub const MAX_SCHEDULED_TRANSFERS_PER_ACCOUNT: usize = 16;
+
+/// deposit in units of the order's currency which is burned when a standing order is created
+/// and issued back when it is cancelled or completed. Incognito accounts are free, so it is
+/// what bounds the number of orders executed on block import
+pub const SCHEDULED_TRANSFER_DEPOSIT: u32 = 1;
+
+/// A standing order created by `TrustedCall::schedule_transfer`. Executed by the enclave
+/// on block import
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct ScheduledTransfer {
+    pub id: u32,
+    pub from: AccountId,
+    pub to: AccountId,
+    pub cid: CurrencyIdentifier,
+    pub amount: BalanceType,
+    pub next_block: BlockNumber,
+    pub period: BlockNumber,
+    pub remaining: u32,
+    /// returned to `from` when the order is removed
+    pub deposit: BalanceType,
+}
+
+pub type PollIndexType = u32;
+
+/// max length of a poll proposal in bytes
+pub const MAX_PROPOSAL_LEN: usize = 256;
+
+/// A poll of the currency community. Stored in the shard state only. Individual votes are
+/// never stored, only the weighted tallies
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct Poll {
+    pub proposal: Vec<u8>,
+    pub created_by: AccountId,
+    /// accumulated vote weight per option
+    pub tallies: Vec<u64>,
+    pub voters: u32,
+    pub closed: bool,
+}
+
+/// Aggregated result of a closed poll, as returned by `PublicGetter::poll_result`
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct PollResult {
+    pub proposal: Vec<u8>,
+    pub tallies: Vec<u64>,
+    pub voters: u32,
+}
+
+/// max number of proxies an account can have per shard
+pub const MAX_PROXIES_PER_ACCOUNT: usize = 8;
+
+/// Kinds of calls a proxy may sign on behalf of an account
+#[derive(Encode, Decode, Copy, Clone, Debug, PartialEq)]
+pub enum ProxyPermission {
+    Transfer,
+    Ceremonies,
+}
+
+impl ProxyPermission {
+    pub fn allows(&self, call: &TrustedCall) -> bool {
+        match self {
+            ProxyPermission::Transfer => matches!(
+                call,
+                TrustedCall::balance_transfer(_, _, _, _)
+                    | TrustedCall::balance_transfer_with_memo(_, _, _, _, _)
+            ),
+            ProxyPermission::Ceremonies => matches!(
+                call,
+                TrustedCall::ceremonies_register_participant(_, _, _)
+                    | TrustedCall::ceremonies_register_attestations(_, _)
+            ),
+        }
+    }
+}
+
+/// A key which may sign some calls on behalf of an account, e.g. a session key on a phone
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct Proxy {
+    pub delegate: AccountId,
+    pub permissions: Vec<ProxyPermission>,
+    /// last block at which the proxy is valid
+    pub expiry: BlockNumber,
+}
+
+/// max number of members of a multisig account
+pub const MAX_MULTISIG_MEMBERS: usize = 16;
+
+/// A k-of-n account. Nobody holds its key, its id is derived from members and threshold
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct Multisig {
+    /// sorted
+    pub members: Vec<AccountId>,
+    pub threshold: u16,
+}
+
+impl Multisig {
+    pub fn new(mut members: Vec<AccountId>, threshold: u16) -> Self {
+        members.sort();
+        members.dedup();
+        Multisig { members, threshold }
+    }
+
+    /// the account id of the multisig. Independent of the order of the members
+    pub fn account(&self) -> AccountId {
+        AccountId::from(blake2_256(&(b"multisig", self).encode()))
+    }
+}
+
+/// Getters a viewing key grants read access to
+#[derive(Encode, Decode, Copy, Clone, Debug, PartialEq)]
+pub enum ViewingScope {
+    Balance,
+    TransferHistory,
+}
+
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub enum TransferDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// A transfer affecting an account. Stored in the shard state, so it is encrypted at rest
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct TransferRecord {
+    pub direction: TransferDirection,
+    pub counterparty: AccountId,
+    pub amount: BalanceType,
+    pub cid: CurrencyIdentifier,
+    pub block_number: BlockNumber,
+    pub memo: Option<Vec<u8>>,
+}
+
+/// Ceremony parameters which can be changed per shard
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub enum CeremonyParameter {
+    Reward(BalanceType),
+    /// [ms]
+    TimeTolerance(u64),
+    /// [m]
+    LocationTolerance(u32),
+}
+
+/// max number of entries returned by `PublicGetter::parameter_changes`
+pub const PARAMETER_CHANGES_PAGE_SIZE: u32 = 32;
+
+/// Audit log entry of a `TrustedCall::ceremonies_set_parameter`
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct ParameterChange {
+    pub parameter: CeremonyParameter,
+    pub changed_by: AccountId,
+    pub block_number: BlockNumber,
+}
+
+/// Privileges of accounts, mirrored from verified chain storage
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub enum Role {
+    /// `EncointerScheduler::CeremonyMaster`
+    CeremonyMaster,
+    /// listed in `EncointerCurrencies::Bootstrappers` of the currency
+    Bootstrapper(CurrencyIdentifier),
+    /// `Sudo::Key`
+    ShardAdmin,
 }
 
 impl TrustedCall {
-    fn account(&self) -> &AccountId {
+    pub fn account(&self) -> &AccountId {
         match self {
             TrustedCall::balance_transfer(account, _, _, _) => account,
+            TrustedCall::balance_transfer_with_memo(account, _, _, _, _) => account,
             TrustedCall::ceremonies_register_participant(account, _, _) => account,
             TrustedCall::ceremonies_register_attestations(account, _) => account,
             TrustedCall::ceremonies_grant_reputation(account, _, _) => account,
+            TrustedCall::ceremonies_set_parameter(account, _, _) => account,
+            TrustedCall::unshield(account, _, _, _) => account,
+            TrustedCall::schedule_transfer(account, _, _, _, _, _, _) => account,
+            TrustedCall::cancel_scheduled_transfer(account, _, _) => account,
+            TrustedCall::polls_create(account, _, _, _) => account,
+            TrustedCall::polls_vote(account, _, _, _) => account,
+            TrustedCall::polls_close(account, _, _) => account,
+            TrustedCall::register_viewing_key(account, _, _, _) => account,
+            TrustedCall::revoke_viewing_key(account, _, _) => account,
+            TrustedCall::add_proxy(account, _, _, _, _) => account,
+            TrustedCall::remove_proxy(account, _, _) => account,
+            TrustedCall::proxied(delegate, _, _) => delegate,
+            TrustedCall::multisig_create(account, _, _, _) => account,
+            TrustedCall::multisig_approve(account, _, _, _) => account,
+            TrustedCall::batch(account, _) => account,
+        }
+    }
+
+    /// for encointer we assume that every currency has its own shard. so shard == cid.
+    /// A call must not touch any other currency than the one of the shard it is executed on
+    pub fn is_bound_to(&self, shard: &ShardIdentifier) -> bool {
+        match self {
+            TrustedCall::balance_transfer(_, _, cid, _)
+            | TrustedCall::balance_transfer_with_memo(_, _, cid, _, _)
+            | TrustedCall::ceremonies_register_participant(_, cid, _)
+            | TrustedCall::ceremonies_grant_reputation(_, cid, _)
+            | TrustedCall::ceremonies_set_parameter(_, cid, _)
+            | TrustedCall::unshield(_, _, cid, _)
+            | TrustedCall::schedule_transfer(_, _, cid, _, _, _, _)
+            | TrustedCall::cancel_scheduled_transfer(_, cid, _)
+            | TrustedCall::polls_create(_, cid, _, _)
+            | TrustedCall::polls_vote(_, cid, _, _)
+            | TrustedCall::polls_close(_, cid, _)
+            | TrustedCall::register_viewing_key(_, cid, _, _)
+            | TrustedCall::revoke_viewing_key(_, cid, _)
+            | TrustedCall::add_proxy(_, cid, _, _, _)
+            | TrustedCall::remove_proxy(_, cid, _)
+            | TrustedCall::multisig_create(_, cid, _, _) => cid == shard,
+            TrustedCall::proxied(_, _, call) => call.is_bound_to(shard),
+            TrustedCall::multisig_approve(_, cid, _, call) => {
+                cid == shard && call.is_bound_to(shard)
+            }
+            TrustedCall::ceremonies_register_attestations(_, _) => true,
+            TrustedCall::batch(_, batch) => batch.iter().all(|c| c.is_bound_to(shard)),
+        }
+    }
+
+    /// the signer needs at least one of these roles. Empty for unprivileged calls
+    pub fn required_roles(&self) -> Vec<Role> {
+        match self {
+            TrustedCall::ceremonies_grant_reputation(_, _, _) => alloc::vec![Role::CeremonyMaster],
+            TrustedCall::ceremonies_set_parameter(_, cid, _) => {
+                alloc::vec![Role::Bootstrapper(*cid), Role::ShardAdmin]
+            }
+            TrustedCall::polls_create(_, cid, _, _) | TrustedCall::polls_close(_, cid, _) => {
+                alloc::vec![Role::CeremonyMaster, Role::Bootstrapper(*cid)]
+            }
+            TrustedCall::balance_transfer(_, _, _, _)
+            | TrustedCall::balance_transfer_with_memo(_, _, _, _, _)
+            | TrustedCall::ceremonies_register_participant(_, _, _)
+            | TrustedCall::ceremonies_register_attestations(_, _)
+            | TrustedCall::unshield(_, _, _, _)
+            | TrustedCall::schedule_transfer(_, _, _, _, _, _, _)
+            | TrustedCall::cancel_scheduled_transfer(_, _, _)
+            | TrustedCall::polls_vote(_, _, _, _)
+            | TrustedCall::register_viewing_key(_, _, _, _)
+            | TrustedCall::revoke_viewing_key(_, _, _)
+            | TrustedCall::add_proxy(_, _, _, _, _)
+            | TrustedCall::remove_proxy(_, _, _)
+            | TrustedCall::multisig_create(_, _, _, _) => Vec::new(),
+            // the wrapped calls are checked on their own when they are dispatched
+            TrustedCall::proxied(_, _, _)
+            | TrustedCall::multisig_approve(_, _, _, _)
+            | TrustedCall::batch(_, _) => Vec::new(),
         }
     }
 
     pub fn sign(
         &self,
-        pair: &sr25519::Pair,
+        pair: &KeyPair,
         nonce: u32,
         mrenclave: &[u8; 32],
         shard: &ShardIdentifier,
@@ -164,7 +619,7 @@ impl TrustedCall {
         TrustedCallSigned {
             call: self.clone(),
             nonce,
-            signature: pair.sign(payload.as_slice()).into(),
+            signature: pair.sign(payload.as_slice()),
         }
     }
 }
@@ -177,6 +632,31 @@ pub enum TrustedGetter {
     meetup_index(AccountId, CurrencyIdentifier),
     attestations(AccountId, CurrencyIdentifier),
     meetup_registry(AccountId, CurrencyIdentifier),
+    nonce(AccountId),
+    reputation(AccountId, CurrencyIdentifier, CeremonyIndexType),
+    ceremony_history(AccountId, CurrencyIdentifier),
+    meetup_assignment(AccountId, CurrencyIdentifier),
+    /// `limit` records of the transfer history, starting at index `from`
+    transfer_history(AccountId, CurrencyIdentifier, u32, u32),
+    /// standing orders owned by the account
+    scheduled_transfers(AccountId, CurrencyIdentifier),
+    /// proxies registered by the account
+    proxies(AccountId, CurrencyIdentifier),
+    /// balance of a multisig account, signed by one of its members
+    multisig_balance(AccountId, CurrencyIdentifier, AccountId),
+    /// evaluate a getter of another account, signed by a viewing key registered by that account
+    viewed(AccountId, Box<TrustedGetter>),
+    /// the receipt of the call with the given hash, as submitted to the enclave
+    call_receipt(AccountId, H256),
+    /// runtime events deposited by the calls of the account, oldest first. (account, currency,
+    /// index of the first event, max number of events). Only the latest
+    /// `MAX_EVENTS_PER_ACCOUNT` events are kept
+    events(AccountId, CurrencyIdentifier, u32, u32),
+    /// calls of the account waiting in the enclave's operation pool, ordered by nonce
+    pending_calls(AccountId, CurrencyIdentifier),
+    /// prove the storage a getter reads against the state root of the latest committed
+    /// sidechain block. The client derives the value from the proven entries
+    with_proof(Box<TrustedGetter>),
 }
 
 impl TrustedGetter {
@@ -187,14 +667,61 @@ impl TrustedGetter {
             TrustedGetter::meetup_index(account, _) => account,
             TrustedGetter::attestations(account, _) => account,
             TrustedGetter::meetup_registry(account, _) => account,
+            TrustedGetter::nonce(account) => account,
+            TrustedGetter::reputation(account, _, _) => account,
+            TrustedGetter::ceremony_history(account, _) => account,
+            TrustedGetter::meetup_assignment(account, _) => account,
+            TrustedGetter::transfer_history(account, _, _, _) => account,
+            TrustedGetter::scheduled_transfers(account, _) => account,
+            TrustedGetter::proxies(account, _) => account,
+            TrustedGetter::multisig_balance(account, _, _) => account,
+            TrustedGetter::viewed(viewer, _) => viewer,
+            TrustedGetter::call_receipt(account, _) => account,
+            TrustedGetter::events(account, _, _, _) => account,
+            TrustedGetter::pending_calls(account, _) => account,
+            TrustedGetter::with_proof(getter) => getter.account(),
+        }
+    }
+
+    /// the getter whose value is returned, i.e. without a `with_proof` wrapper
+    pub fn evaluated(&self) -> &TrustedGetter {
+        match self {
+            TrustedGetter::with_proof(getter) => getter.as_ref(),
+            getter => getter,
+        }
+    }
+
+    /// the scope a viewing key needs to evaluate this getter. `None` if the getter can only be
+    /// signed by the account itself
+    pub fn viewing_scope(&self) -> Option<ViewingScope> {
+        match self {
+            TrustedGetter::balance(_, _) => Some(ViewingScope::Balance),
+            TrustedGetter::transfer_history(_, _, _, _) => Some(ViewingScope::TransferHistory),
+            _ => None,
         }
     }
 
-    pub fn sign(&self, pair: &sr25519::Pair) -> TrustedGetterSigned {
-        let signature = pair.sign(self.encode().as_slice()).into();
+    /// sign the getter for `mrenclave` and `shard`. The enclave only evaluates it while its
+    /// latest known block is within `valid_from..=valid_until`.
+    pub fn sign(
+        &self,
+        pair: &KeyPair,
+        mrenclave: &[u8; 32],
+        shard: &ShardIdentifier,
+        valid_from: BlockNumber,
+        valid_until: BlockNumber,
+    ) -> TrustedGetterSigned {
+        let mut payload = self.encode();
+        payload.append(&mut mrenclave.encode());
+        payload.append(&mut shard.encode());
+        payload.append(&mut valid_from.encode());
+        payload.append(&mut valid_until.encode());
+
         TrustedGetterSigned {
             getter: self.clone(),
-            signature,
+            valid_from,
+            valid_until,
+            signature: pair.sign(payload.as_slice()),
         }
     }
 }
@@ -202,17 +729,38 @@ impl TrustedGetter {
 #[derive(Encode, Decode, Clone, Debug)]
 pub struct TrustedGetterSigned {
     pub getter: TrustedGetter,
-    pub signature: AnySignature,
+    pub valid_from: BlockNumber,
+    pub valid_until: BlockNumber,
+    pub signature: Signature,
 }
 
 impl TrustedGetterSigned {
-    pub fn new(getter: TrustedGetter, signature: AnySignature) -> Self {
-        TrustedGetterSigned { getter, signature }
+    pub fn new(
+        getter: TrustedGetter,
+        valid_from: BlockNumber,
+        valid_until: BlockNumber,
+        signature: Signature,
+    ) -> Self {
+        TrustedGetterSigned {
+            getter,
+            valid_from,
+            valid_until,
+            signature,
+        }
     }
 
-    pub fn verify_signature(&self) -> bool {
+    pub fn verify_signature(&self, mrenclave: &[u8; 32], shard: &ShardIdentifier) -> bool {
+        let mut payload = self.getter.encode();
+        payload.append(&mut mrenclave.encode());
+        payload.append(&mut shard.encode());
+        payload.append(&mut self.valid_from.encode());
+        payload.append(&mut self.valid_until.encode());
         self.signature
-            .verify(self.getter.encode().as_slice(), self.getter.account())
+            .verify(payload.as_slice(), self.getter.account())
+    }
+
+    pub fn is_valid_at(&self, block_number: BlockNumber) -> bool {
+        self.valid_from <= block_number && block_number <= self.valid_until
     }
 }
 
@@ -220,11 +768,11 @@ impl TrustedGetterSigned {
 pub struct TrustedCallSigned {
     pub call: TrustedCall,
     pub nonce: u32,
-    pub signature: AnySignature,
+    pub signature: Signature,
 }
 
 impl TrustedCallSigned {
-    pub fn new(call: TrustedCall, nonce: u32, signature: AnySignature) -> Self {
+    pub fn new(call: TrustedCall, nonce: u32, signature: Signature) -> Self {
         TrustedCallSigned {
             call,
             nonce,
@@ -242,15 +790,237 @@ impl TrustedCallSigned {
     }
 }
 
-// TODO: #91 signed return value
-/*
-pub struct TrustedReturnValue<T> {
-    pub value: T,
-    pub signer: AccountId
+/// What happened to a participant in one ceremony, as returned by `TrustedGetter::ceremony_history`.
+/// Indices are 0 if the participant wasn't registered or the registry has been purged since.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct CeremonyRecord {
+    pub cindex: CeremonyIndexType,
+    pub participant_index: ParticipantIndexType,
+    pub meetup_index: MeetupIndexType,
+    /// the participant has registered attestations of its meetup
+    pub attended: bool,
+    /// the ceremony reward has been issued, which verifies the reputation
+    pub rewarded: bool,
+    pub reputation: Reputation,
 }
 
-impl TrustedReturnValue
-*/
+/// Everything a participant needs to know about its meetup in the current ceremony
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct MeetupAssignment {
+    pub meetup_index: MeetupIndexType,
+    pub location: Location,
+    /// unix timestamp [ms]. `None` unless the ceremony is ASSIGNING or ATTESTING
+    pub time: Option<u64>,
+    pub participants: Vec<AccountId32>,
+}
+
+/// The outcome of a single call of a `TrustedCall::batch`, recorded in the receipt of a failed
+/// batch
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub enum BatchItemResult {
+    Ok,
+    Failed,
+    NotExecuted,
+}
+
+/// The payload of `CALL_CONFIRMED`, the same for every kind of call the enclave confirms
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct CallConfirmation {
+    /// hash of the shard state after the call
+    pub state_hash: H256,
+    /// whether the call was applied, why it failed is only in its receipt
+    pub success: bool,
+    /// one result per call of a batch, `None` for any other call
+    pub batch_results: Option<Vec<BatchItemResult>>,
+}
+
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Display)]
+pub enum StfError {
+    #[display(fmt = "Insufficient privileges {:?}, are you sure you are root?", _0)]
+    MissingPrivileges(AccountId),
+    #[display(fmt = "Error dispatching runtime call")]
+    Dispatch(String),
+    #[display(fmt = "Not enough funds to perform operation")]
+    MissingFunds,
+    #[display(fmt = "Account does not exist {:?}", _0)]
+    InexistentAccount(AccountId),
+    #[display(fmt = "Invalid nonce {}, expected {}", _0, _1)]
+    InvalidNonce(u32, u32),
+    #[display(fmt = "Batch failed and has been reverted: {:?}", _0)]
+    BatchFailed(Vec<BatchItemResult>),
+    #[display(fmt = "Memo of {} bytes exceeds the maximum length", _0)]
+    MemoTooLong(u32),
+    #[display(fmt = "Request could not be decoded as a signed trusted call")]
+    UndecodableCall,
+    #[display(fmt = "Bad signature")]
+    BadSignature,
+    #[display(fmt = "Call touches another currency than its shard")]
+    ForeignShard,
+    #[display(fmt = "Call has not been executed before it expired in the operation pool")]
+    Expired,
+    #[display(fmt = "Call has been evicted from the full operation pool")]
+    Evicted,
+}
+
+/// number of blocks a `CallReceipt` is kept after the block it has been recorded in
+pub const CALL_RECEIPT_RETENTION_PERIOD: BlockNumber = 14_400;
+
+/// Record of the execution of a call submitted to the enclave, stored under the blake2 hash of
+/// the encoded `TrustedCallSigned`
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct CallReceipt {
+    /// `None` if the request could not be attributed to a signer. Such receipts can be queried
+    /// by anyone who knows the call hash
+    pub signer: Option<AccountId>,
+    pub result: Result<(), StfError>,
+    pub block_number: BlockNumber,
+    /// hash of the shard state after the call, before the receipt itself was recorded
+    pub state_hash: H256,
+}
+
+/// Storage entries of a shard state with their inclusion proofs against the state root, which
+/// is committed on chain as state hash. Getters are proven against the state of the latest
+/// confirmation of the shard
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct StateProof {
+    pub state_root: Hash,
+    /// (key, value, proof) of the keys which are in the state
+    pub entries: Vec<(Vec<u8>, Vec<u8>, merkle::MerkleProof)>,
+    /// (key, proof) of the keys which are not in the state
+    pub absent: Vec<(Vec<u8>, merkle::AbsenceProof)>,
+}
+
+impl StateProof {
+    pub fn verify(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|(key, value, proof)| proof.verify(key, value, &self.state_root))
+            && self
+                .absent
+                .iter()
+                .all(|(key, proof)| proof.verify(key, &self.state_root))
+    }
+
+    /// the proven value of `key`. `Some(None)` if it is proven to be absent, `None` if the
+    /// proof doesn't cover `key`
+    pub fn value(&self, key: &[u8]) -> Option<Option<&Vec<u8>>> {
+        if let Some((_, value, _)) = self.entries.iter().find(|(k, _, _)| k.as_slice() == key) {
+            return Some(Some(value));
+        }
+        self.absent
+            .iter()
+            .find(|(k, _)| k.as_slice() == key)
+            .map(|_| None)
+    }
+}
+
+/// The result of a getter, signed by the enclave which evaluated it.
+#[derive(Encode, Decode, Clone, Debug)]
+pub struct TrustedReturnValue {
+    pub value: Option<Vec<u8>>,
+    pub shard: ShardIdentifier,
+    pub block_number: BlockNumber,
+    pub block_hash: Hash,
+    pub getter_hash: Hash,
+    /// only present for `TrustedGetter::with_proof`. `value` is `None` then, it is derived
+    /// from the proven entries
+    pub proof: Option<StateProof>,
+    pub signature: ed25519::Signature,
+}
+
+impl TrustedReturnValue {
+    pub fn sign(
+        pair: &ed25519::Pair,
+        value: Option<Vec<u8>>,
+        shard: ShardIdentifier,
+        block_number: BlockNumber,
+        block_hash: Hash,
+        getter: &Getter,
+        proof: Option<StateProof>,
+    ) -> Self {
+        let getter_hash: Hash = blake2_256(&getter.encode()).into();
+        let payload = (&value, shard, block_number, block_hash, getter_hash, &proof).encode();
+        TrustedReturnValue {
+            value,
+            shard,
+            block_number,
+            block_hash,
+            getter_hash,
+            proof,
+            signature: pair.sign(payload.as_slice()),
+        }
+    }
+
+    pub fn verify_signature(&self, signer: &ed25519::Public) -> bool {
+        let payload = (
+            &self.value,
+            self.shard,
+            self.block_number,
+            self.block_hash,
+            self.getter_hash,
+            &self.proof,
+        )
+            .encode();
+        self.signature.verify(payload.as_slice(), signer)
+    }
+
+    /// checks that the value has been signed by `signer` and answers `getter` on `shard`
+    pub fn verify(
+        &self,
+        signer: &ed25519::Public,
+        shard: &ShardIdentifier,
+        getter: &Getter,
+    ) -> bool {
+        self.shard == *shard
+            && self.getter_hash == Hash::from(blake2_256(&getter.encode()))
+            && self.verify_signature(signer)
+    }
+}
+
+/// A block of trusted calls executed by a worker between parentchain blocks. The blocks of a
+/// shard are chained through `parent_hash`
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct SidechainBlock {
+    pub shard: ShardIdentifier,
+    /// the first block of a shard has number 1 and the default parent hash
+    pub block_number: u64,
+    pub parent_hash: Hash,
+    /// the latest parentchain block the calls were executed on top of
+    pub parentchain_block_number: BlockNumber,
+    /// blake2 hashes of the executed `TrustedCallSigned`, in order of execution. Failed calls
+    /// are included, their outcome is in the call receipts
+    pub call_hashes: Vec<Hash>,
+    pub state_hash: Hash,
+    /// the enclave which produced the block
+    pub signer: ed25519::Public,
+}
+
+impl SidechainBlock {
+    pub fn hash(&self) -> Hash {
+        blake2_256(&self.encode()).into()
+    }
+
+    pub fn sign(self, pair: &ed25519::Pair) -> SignedSidechainBlock {
+        let signature = pair.sign(self.encode().as_slice());
+        SignedSidechainBlock {
+            block: self,
+            signature,
+        }
+    }
+}
+
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct SignedSidechainBlock {
+    pub block: SidechainBlock,
+    pub signature: ed25519::Signature,
+}
+
+impl SignedSidechainBlock {
+    pub fn verify_signature(&self) -> bool {
+        self.signature
+            .verify(self.block.encode().as_slice(), &self.block.signer)
+    }
+}
 
 #[cfg(feature = "sgx")]
 pub struct Stf {}
@@ -258,7 +1028,15 @@ pub struct Stf {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use sp_keyring::AccountKeyring;
+    use sp_keyring::{AccountKeyring, Ed25519Keyring};
+
+    fn key_pairs() -> Vec<KeyPair> {
+        alloc::vec![
+            AccountKeyring::Alice.pair().into(),
+            Ed25519Keyring::Alice.pair().into(),
+            ecdsa::Pair::from_seed(&[1u8; 32]).into(),
+        ]
+    }
 
     #[test]
     fn verify_signature_works() {
@@ -266,14 +1044,181 @@ mod tests {
         let mrenclave = [0u8; 32];
         let shard = ShardIdentifier::default();
 
-        let call = TrustedCall::balance_set_balance(
-            AccountKeyring::Alice.public(),
-            AccountKeyring::Alice.public(),
-            42,
-            42,
+        for pair in key_pairs() {
+            let call = TrustedCall::balance_transfer(
+                pair.account(),
+                AccountKeyring::Bob.to_account_id(),
+                shard,
+                BalanceType::from_num(42),
+            );
+            let signed_call = call.sign(&pair, nonce, &mrenclave, &shard);
+
+            assert!(signed_call.verify_signature(&mrenclave, &shard));
+            assert!(!signed_call.verify_signature(&mrenclave, &H256::from([1u8; 32])));
+        }
+    }
+
+    #[test]
+    fn verify_getter_signature_works() {
+        let mrenclave = [0u8; 32];
+        let shard = ShardIdentifier::default();
+
+        for pair in key_pairs() {
+            let getter = TrustedGetter::balance(pair.account(), shard);
+            let signed_getter = getter.sign(&pair, &mrenclave, &shard, 1, 10);
+
+            assert!(signed_getter.verify_signature(&mrenclave, &shard));
+            assert!(!signed_getter.verify_signature(&[1u8; 32], &shard));
+        }
+    }
+
+    #[test]
+    fn signature_of_other_account_is_rejected() {
+        let mrenclave = [0u8; 32];
+        let shard = ShardIdentifier::default();
+        let pair: KeyPair = Ed25519Keyring::Alice.pair().into();
+
+        let call = TrustedCall::balance_transfer(
+            AccountKeyring::Bob.to_account_id(),
+            pair.account(),
+            shard,
+            BalanceType::from_num(42),
         );
-        let signed_call = call.sign(&AccountKeyring::Alice.pair(), nonce, &mrenclave, &shard);
+        assert!(!call
+            .sign(&pair, 0, &mrenclave, &shard)
+            .verify_signature(&mrenclave, &shard));
+    }
+
+    #[test]
+    fn sidechain_block_signature_covers_its_content() {
+        let pair = Ed25519Keyring::Alice.pair();
+        let block = SidechainBlock {
+            shard: ShardIdentifier::default(),
+            block_number: 1,
+            parent_hash: Hash::default(),
+            parentchain_block_number: 5,
+            call_hashes: alloc::vec![Hash::from([1u8; 32])],
+            state_hash: Hash::from([2u8; 32]),
+            signer: pair.public(),
+        };
+        let mut signed = block.sign(&pair);
+        assert!(signed.verify_signature());
+
+        signed.block.call_hashes.clear();
+        assert!(!signed.verify_signature());
+    }
 
-        assert!(signed_call.verify_signature(&mrenclave, &shard));
+    #[test]
+    fn native_conversion_never_credits_more_than_is_locked() {
+        let units = BalanceType::from_num(444);
+        let native = balance_to_native(units).unwrap();
+        assert_eq!(native, 444 * NATIVE_UNITS_PER_BALANCE);
+        assert_eq!(balance_from_native(native), Some(units));
+
+        for amount in &[1u128, 7, NATIVE_UNITS_PER_BALANCE + 3, 123_456_789_012_345] {
+            let balance = balance_from_native(*amount).unwrap();
+            assert!(balance_to_native(balance).unwrap() <= *amount);
+        }
+        assert_eq!(balance_from_native(u128::max_value()), None);
+        assert_eq!(balance_to_native(BalanceType::from_num(-1)), None);
+    }
+
+    fn signed_return_value(getter: TrustedGetter, value: Vec<u8>) -> TrustedReturnValue {
+        let pair: KeyPair = AccountKeyring::Alice.pair().into();
+        let shard = ShardIdentifier::default();
+        let getter = Getter::trusted(getter.sign(&pair, &[0u8; 32], &shard, 1, 10));
+        TrustedReturnValue::sign(
+            &Ed25519Keyring::Alice.pair(),
+            Some(value),
+            shard,
+            BlockNumber::max_value(),
+            Hash::default(),
+            &getter,
+            None,
+        )
+    }
+
+    #[test]
+    fn full_transfer_history_page_fits_into_state_value() {
+        let who = AccountKeyring::Alice.to_account_id();
+        let record = TransferRecord {
+            direction: TransferDirection::Incoming,
+            counterparty: AccountKeyring::Bob.to_account_id(),
+            amount: BalanceType::from_num(42),
+            cid: CurrencyIdentifier::default(),
+            block_number: BlockNumber::max_value(),
+            memo: Some(alloc::vec![0xffu8; MAX_MEMO_LEN]),
+        };
+        let page = alloc::vec![record; TRANSFER_HISTORY_PAGE_SIZE as usize];
+        let getter = TrustedGetter::transfer_history(
+            who,
+            CurrencyIdentifier::default(),
+            0,
+            TRANSFER_HISTORY_PAGE_SIZE,
+        );
+        let value = signed_return_value(getter, page.encode());
+        assert!(value.encode().len() <= STATE_VALUE_MAX_SIZE);
+    }
+
+    #[test]
+    fn full_proven_events_page_fits_into_state_value() {
+        let who = AccountKeyring::Alice.to_account_id();
+        let event = StfEvent {
+            block_number: BlockNumber::max_value(),
+            pallet: EventPallet::EncointerBalances,
+            event: alloc::vec![0xffu8; MAX_EVENT_LEN],
+        };
+        // module and storage prefixes, then the blake2_128_concat account and index
+        let key = alloc::vec![0xffu8; 16 + 16 + 16 + 32 + 16 + 4];
+        // the path of a tree with u32::MAX leaves
+        let proof = merkle::MerkleProof {
+            leaf_index: u32::max_value() - 1,
+            leaf_count: u32::max_value(),
+            siblings: alloc::vec![Hash::default(); 32],
+        };
+        let getter = TrustedGetter::with_proof(Box::new(TrustedGetter::events(
+            who,
+            CurrencyIdentifier::default(),
+            0,
+            PROVEN_EVENTS_PAGE_SIZE,
+        )));
+        let mut value = signed_return_value(getter, Vec::new());
+        value.value = None;
+        value.proof = Some(StateProof {
+            state_root: Hash::default(),
+            entries: alloc::vec![
+                (key.clone(), event.encode(), proof.clone());
+                PROVEN_EVENTS_PAGE_SIZE as usize
+            ],
+            absent: Vec::new(),
+        });
+        assert!(value.encode().len() <= STATE_VALUE_MAX_SIZE);
+
+        // the events after the last one are proven absent by two neighbouring events each
+        let neighbour = Some((key.clone(), event.encode(), proof));
+        let absence = merkle::AbsenceProof {
+            lower: neighbour.clone(),
+            upper: neighbour,
+        };
+        value.proof = Some(StateProof {
+            state_root: Hash::default(),
+            entries: Vec::new(),
+            absent: alloc::vec![(key, absence); PROVEN_EVENTS_PAGE_SIZE as usize],
+        });
+        assert!(value.encode().len() <= STATE_VALUE_MAX_SIZE);
+    }
+
+    #[test]
+    fn full_events_page_fits_into_state_value() {
+        let who = AccountKeyring::Alice.to_account_id();
+        let event = StfEvent {
+            block_number: BlockNumber::max_value(),
+            pallet: EventPallet::EncointerBalances,
+            event: alloc::vec![0xffu8; MAX_EVENT_LEN],
+        };
+        let page = alloc::vec![event; EVENTS_PAGE_SIZE as usize];
+        let getter = TrustedGetter::events(who, CurrencyIdentifier::default(), 0, EVENTS_PAGE_SIZE);
+        let value = signed_return_value(getter, page.encode());
+        assert!(value.encode().len() <= STATE_VALUE_MAX_SIZE);
     }
 }
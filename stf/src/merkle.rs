@@ -0,0 +1,251 @@
+/*
+    Copyright 2019 Supercomputing Systems AG
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+
+*/
+
+//! Binary Merkle tree over the entries of a shard state, sorted by key. Its root is what the
+//! enclave commits as state hash, so clients can check getter results against it.
+
+use crate::Hash;
+use alloc::vec::Vec;
+use codec::{Decode, Encode};
+use sp_core::blake2_256;
+
+// leaves and inner nodes are hashed with different prefixes, so a leaf can't pass as a node
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+pub fn leaf_hash(key: &[u8], value: &[u8]) -> Hash {
+    blake2_256(&(LEAF_PREFIX, key, value).encode()).into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    blake2_256(&(NODE_PREFIX, left, right).encode()).into()
+}
+
+/// an unpaired node is moved up a level unchanged
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| {
+            if pair.len() == 2 {
+                node_hash(&pair[0], &pair[1])
+            } else {
+                pair[0]
+            }
+        })
+        .collect()
+}
+
+/// root of the tree over `leaves`. The root of an empty tree is the default hash
+pub fn root(leaves: &[Hash]) -> Hash {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.first().cloned().unwrap_or_default()
+}
+
+/// proves that the leaf at `leaf_index` is part of the tree over `leaves`
+pub fn prove(leaves: &[Hash], leaf_index: usize) -> Option<MerkleProof> {
+    if leaf_index >= leaves.len() {
+        return None;
+    }
+    let mut siblings = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut index = leaf_index;
+    while level.len() > 1 {
+        if let Some(sibling) = level.get(index ^ 1) {
+            siblings.push(*sibling);
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    Some(MerkleProof {
+        leaf_index: leaf_index as u32,
+        leaf_count: leaves.len() as u32,
+        siblings,
+    })
+}
+
+/// proves that no entry of `entries`, sorted by key, has `key`. `None` if one has
+pub fn prove_absence<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+    entries: &[(K, V)],
+    leaves: &[Hash],
+    key: &[u8],
+) -> Option<AbsenceProof> {
+    let index = match entries.binary_search_by(|(k, _)| k.as_ref().cmp(key)) {
+        Ok(_) => return None,
+        Err(index) => index,
+    };
+    let neighbour = |i: usize| {
+        let (k, v) = &entries[i];
+        prove(leaves, i).map(|proof| (k.as_ref().to_vec(), v.as_ref().to_vec(), proof))
+    };
+    Some(AbsenceProof {
+        lower: index.checked_sub(1).and_then(neighbour),
+        upper: if index < entries.len() {
+            neighbour(index)
+        } else {
+            None
+        },
+    })
+}
+
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct MerkleProof {
+    pub leaf_index: u32,
+    pub leaf_count: u32,
+    /// hashes of the sibling nodes from the leaf up to the root
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// the root of the tree which has `leaf` at the proven position. `None` if the proof is
+    /// malformed
+    pub fn root(&self, leaf: Hash) -> Option<Hash> {
+        if self.leaf_index >= self.leaf_count {
+            return None;
+        }
+        let mut siblings = self.siblings.iter();
+        let mut hash = leaf;
+        let mut index = self.leaf_index as usize;
+        let mut count = self.leaf_count as usize;
+        while count > 1 {
+            if (index ^ 1) < count {
+                let sibling = siblings.next()?;
+                hash = if index % 2 == 0 {
+                    node_hash(&hash, sibling)
+                } else {
+                    node_hash(sibling, &hash)
+                };
+            }
+            index /= 2;
+            count = (count + 1) / 2;
+        }
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(hash)
+    }
+
+    pub fn verify(&self, key: &[u8], value: &[u8], root: &Hash) -> bool {
+        self.root(leaf_hash(key, value)) == Some(*root)
+    }
+}
+
+/// The (key, value, proof) of the entries next to where an absent key would be in the sorted
+/// entries. There is no lower or upper one at the ends of the tree
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct AbsenceProof {
+    pub lower: Option<(Vec<u8>, Vec<u8>, MerkleProof)>,
+    pub upper: Option<(Vec<u8>, Vec<u8>, MerkleProof)>,
+}
+
+impl AbsenceProof {
+    /// checks that the neighbours are adjacent leaves of the tree with `root` and that `key`
+    /// is between their keys
+    pub fn verify(&self, key: &[u8], root: &Hash) -> bool {
+        let lower = match &self.lower {
+            Some((k, v, proof)) if k.as_slice() < key && proof.verify(k, v, root) => Some(proof),
+            Some(_) => return false,
+            None => None,
+        };
+        let upper = match &self.upper {
+            Some((k, v, proof)) if k.as_slice() > key && proof.verify(k, v, root) => Some(proof),
+            Some(_) => return false,
+            None => None,
+        };
+        match (lower, upper) {
+            (Some(l), Some(u)) => l.leaf_count == u.leaf_count && l.leaf_index + 1 == u.leaf_index,
+            (Some(l), None) => l.leaf_index + 1 == l.leaf_count,
+            (None, Some(u)) => u.leaf_index == 0,
+            (None, None) => *root == Hash::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(n: u8) -> Vec<(Vec<u8>, Vec<u8>)> {
+        (0..n)
+            .map(|i| (alloc::vec![i], alloc::vec![i, i]))
+            .collect()
+    }
+
+    #[test]
+    fn proofs_verify_for_all_tree_sizes() {
+        for n in 1..=9 {
+            let entries = entries(n);
+            let leaves: Vec<Hash> = entries.iter().map(|(k, v)| leaf_hash(k, v)).collect();
+            let root = root(&leaves);
+            for (i, (key, value)) in entries.iter().enumerate() {
+                let proof = prove(&leaves, i).unwrap();
+                assert!(proof.verify(key, value, &root));
+                assert!(!proof.verify(key, &[42u8], &root));
+            }
+        }
+    }
+
+    #[test]
+    fn proof_of_other_position_is_rejected() {
+        let entries = entries(5);
+        let leaves: Vec<Hash> = entries.iter().map(|(k, v)| leaf_hash(k, v)).collect();
+        let root = root(&leaves);
+        let mut proof = prove(&leaves, 1).unwrap();
+        proof.leaf_index = 0;
+        assert!(!proof.verify(&entries[1].0, &entries[1].1, &root));
+        assert!(prove(&leaves, 5).is_none());
+    }
+
+    #[test]
+    fn absence_proofs_verify_for_all_tree_sizes() {
+        for n in 0..=9 {
+            let entries = entries(n);
+            let leaves: Vec<Hash> = entries.iter().map(|(k, v)| leaf_hash(k, v)).collect();
+            let root = root(&leaves);
+            // before, between and after the keys
+            let mut absent: Vec<Vec<u8>> = (0..n).map(|i| alloc::vec![i, 0]).collect();
+            absent.push(Vec::new());
+            absent.push(alloc::vec![n]);
+            for key in absent.iter() {
+                let proof = prove_absence(&entries, &leaves, key).unwrap();
+                assert!(proof.verify(key, &root));
+            }
+            for (key, _) in entries.iter() {
+                assert!(prove_absence(&entries, &leaves, key).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn absence_proof_of_present_key_is_rejected() {
+        let entries = entries(5);
+        let leaves: Vec<Hash> = entries.iter().map(|(k, v)| leaf_hash(k, v)).collect();
+        let root = root(&leaves);
+        // the neighbours of [2, 0] don't enclose [2] or [3]
+        let proof = prove_absence(&entries, &leaves, &[2, 0]).unwrap();
+        assert!(!proof.verify(&[2], &root));
+        assert!(!proof.verify(&[3], &root));
+        // leaving out a neighbour hides the entries between
+        let mut proof = prove_absence(&entries, &leaves, &[1, 0]).unwrap();
+        proof.upper = prove_absence(&entries, &leaves, &[3, 0]).unwrap().upper;
+        assert!(!proof.verify(&[2], &root));
+        proof.upper = None;
+        assert!(!proof.verify(&[2], &root));
+    }
+}
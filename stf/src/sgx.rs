@@ -4,22 +4,34 @@ use std::format;
 use std::prelude::v1::*;
 
 use codec::{Decode, Encode};
-use derive_more::Display;
 use encointer_balances::{BalanceEntry, BalanceType};
-use encointer_ceremonies::{MeetupIndexType, ParticipantIndexType};
+use encointer_ceremonies::{MeetupIndexType, ParticipantIndexType, Reputation};
 use encointer_currencies::CurrencyIdentifier;
 use encointer_scheduler::{CeremonyIndexType, CeremonyPhaseType, OnCeremonyPhaseChange};
 use log_sgx::*;
 use metadata::StorageHasher;
 use sgx_runtime::{BlockNumber, Moment, Runtime};
-use sp_core::crypto::AccountId32;
+use sp_core::{blake2_256, crypto::AccountId32, H256};
 use sp_io::SgxExternalitiesTrait;
 use sp_runtime::MultiAddress;
 use support::traits::UnfilteredDispatchable;
 
+use crate::keys::STF_MODULE_PREFIX;
+pub use crate::keys::{
+    balance_key, event_key, nonce_key_hash, proxies_key, storage_double_map_key, storage_map_key,
+    storage_value_key,
+};
+use crate::merkle;
 use crate::{
-    AccountId, Getter, PublicGetter, ShardIdentifier, State, Stf, TrustedCall, TrustedCallSigned,
-    TrustedGetter,
+    balance_from_native, balance_to_native, AccountId, BatchItemResult, CallReceipt,
+    CeremonyParameter, CeremonyRecord, EventPallet, Getter, Location, MeetupAssignment, Multisig,
+    ParameterChange, Poll, PollIndexType, PollResult, Proxy, PublicGetter, Role, ScheduledTransfer,
+    ShardIdentifier, State, StateProof, Stf, StfError, StfEvent, TransferDirection, TransferRecord,
+    TrustedCall, TrustedCallSigned, TrustedGetter, TrustedGetterSigned, ViewingScope,
+    CALL_RECEIPT_RETENTION_PERIOD, EVENTS_PAGE_SIZE, MAX_EVENTS_PER_ACCOUNT, MAX_EVENT_LEN,
+    MAX_MEMO_LEN, MAX_MULTISIG_MEMBERS, MAX_PROPOSAL_LEN, MAX_PROXIES_PER_ACCOUNT,
+    MAX_SCHEDULED_TRANSFERS_PER_ACCOUNT, PARAMETER_CHANGES_PAGE_SIZE, SCHEDULED_TRANSFER_DEPOSIT,
+    SUBSRATEE_REGISTRY_MODULE, TRANSFER_HISTORY_PAGE_SIZE, UNSHIELD,
 };
 
 /// Simple blob that holds a call in encoded format
@@ -32,6 +44,93 @@ impl Encode for OpaqueCall {
     }
 }
 
+/// A snapshot of the state, taken before a state transition
+pub struct StateCheckpoint(State);
+
+/// Explicit transaction boundaries for state transitions
+pub trait StateTransaction {
+    fn checkpoint(&self) -> StateCheckpoint;
+    /// keep all changes made since the checkpoint was taken
+    fn commit(&mut self, checkpoint: StateCheckpoint);
+    /// discard all changes made since the checkpoint was taken
+    fn rollback(&mut self, checkpoint: StateCheckpoint);
+}
+
+impl StateTransaction for State {
+    fn checkpoint(&self) -> StateCheckpoint {
+        StateCheckpoint(self.clone())
+    }
+
+    fn commit(&mut self, _checkpoint: StateCheckpoint) {}
+
+    fn rollback(&mut self, checkpoint: StateCheckpoint) {
+        *self = checkpoint.0;
+    }
+}
+
+/// deterministic encoding of the state: SCALE encoded entries, sorted by key.
+/// Workers with the same state produce the same bytes (and state hash)
+pub fn encode_state(state: &State) -> Vec<u8> {
+    sorted_entries(state).encode()
+}
+
+fn sorted_entries(state: &State) -> Vec<(&Vec<u8>, &Vec<u8>)> {
+    let mut entries: Vec<(&Vec<u8>, &Vec<u8>)> = state.iter().collect();
+    entries.sort();
+    entries
+}
+
+/// Merkle root over the entries of the state, sorted by key
+pub fn state_root(state: &State) -> H256 {
+    let leaves: Vec<H256> = sorted_entries(state)
+        .iter()
+        .map(|(key, value)| merkle::leaf_hash(key, value))
+        .collect();
+    merkle::root(&leaves)
+}
+
+/// proves the values or the absence of `keys` against the state root
+pub fn prove_state(state: &State, keys: &[Vec<u8>]) -> StateProof {
+    let entries = sorted_entries(state);
+    let leaves: Vec<H256> = entries
+        .iter()
+        .map(|(key, value)| merkle::leaf_hash(key, value))
+        .collect();
+    let mut proven = Vec::new();
+    let mut absent = Vec::new();
+    for key in keys.iter() {
+        match entries.binary_search_by(|(k, _)| k.as_slice().cmp(key.as_slice())) {
+            Ok(index) => {
+                if let Some(proof) = merkle::prove(&leaves, index) {
+                    proven.push((key.clone(), entries[index].1.clone(), proof));
+                }
+            }
+            Err(_) => {
+                if let Some(proof) = merkle::prove_absence(&entries, &leaves, key) {
+                    absent.push((key.clone(), proof));
+                }
+            }
+        }
+    }
+    StateProof {
+        state_root: merkle::root(&leaves),
+        entries: proven,
+        absent,
+    }
+}
+
+/// decodes a state encoded with `encode_state`. Fails unless all bytes are consumed
+pub fn decode_state(mut bytes: &[u8]) -> Option<State> {
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = Decode::decode(&mut bytes).ok()?;
+    if !bytes.is_empty() {
+        return None;
+    }
+    Some(entries.into_iter().collect())
+}
+
+/// [ms]
+const ONE_DAY: Moment = 86_400_000;
+
 const ALICE_ENCODED: [u8; 32] = [
     212, 53, 147, 199, 21, 253, 211, 28, 97, 20, 26, 189, 4, 169, 159, 214, 130, 44, 133, 88, 133,
     76, 205, 227, 154, 86, 132, 231, 165, 109, 162, 125,
@@ -91,6 +190,26 @@ impl Stf {
         });
     }
 
+    /// credits funds which have been shielded on chain to an incognito account
+    pub fn shield_funds(
+        ext: &mut State,
+        account: AccountId,
+        cid: CurrencyIdentifier,
+        amount: u128,
+    ) -> Result<(), StfError> {
+        let amount = balance_from_native(amount)
+            .ok_or_else(|| StfError::Dispatch("amount out of range".to_string()))?;
+        if amount <= BalanceType::from_num(0) {
+            return Err(StfError::Dispatch(
+                "shielded amount must be positive".to_string(),
+            ));
+        }
+        ext.execute_with(|| {
+            encointer_balances::Module::<Runtime>::issue(cid, &account, amount)
+                .map_err(|_| StfError::Dispatch("shield_funds".to_string()))
+        })
+    }
+
     pub fn update_block_number(ext: &mut State, number: BlockNumber) {
         ext.execute_with(|| {
             let key = storage_value_key("System", "Number");
@@ -98,12 +217,55 @@ impl Stf {
         });
     }
 
+    /// executes a call. A call with an invalid nonce leaves the state untouched. Any other call
+    /// uses up its nonce, also if it fails, so its published ciphertext can't be replayed. Apart
+    /// from that, a failed call leaves the state exactly as it was before. Returns the result of
+    /// every call of a batch, `None` for any other call
     pub fn execute(
         ext: &mut State,
         call: TrustedCallSigned,
-        _calls: &mut Vec<OpaqueCall>,
-    ) -> Result<(), StfError> {
+        calls: &mut Vec<OpaqueCall>,
+    ) -> Result<Option<Vec<BatchItemResult>>, StfError> {
+        Self::execute_with_storage(ext, call, &HashMap::new(), calls)
+    }
+
+    /// like `execute`, but first applies the chain storage `update_map` the call needs. A failed
+    /// call reverts the storage update too
+    pub fn execute_with_storage(
+        ext: &mut State,
+        call: TrustedCallSigned,
+        update_map: &HashMap<Vec<u8>, Option<Vec<u8>>>,
+        calls: &mut Vec<OpaqueCall>,
+    ) -> Result<Option<Vec<BatchItemResult>>, StfError> {
+        let sender = call.call.account().clone();
+        let expected_nonce = ext.execute_with(|| Self::account_nonce(&sender));
+        if call.nonce != expected_nonce {
+            return Err(StfError::InvalidNonce(call.nonce, expected_nonce));
+        }
+
+        let checkpoint = ext.checkpoint();
+        let calls_len = calls.len();
+        Self::update_storage(ext, update_map);
+        let result = Self::execute_unchecked(ext, &sender, call, calls);
+        if result.is_ok() {
+            ext.commit(checkpoint);
+        } else {
+            ext.rollback(checkpoint);
+            calls.truncate(calls_len);
+        }
+        ext.execute_with(|| Self::increment_nonce(&sender));
+        result
+    }
+
+    fn execute_unchecked(
+        ext: &mut State,
+        sender: &AccountId,
+        call: TrustedCallSigned,
+        calls: &mut Vec<OpaqueCall>,
+    ) -> Result<Option<Vec<BatchItemResult>>, StfError> {
         ext.execute_with(|| {
+            // events deposited outside of trusted calls don't belong to the sender
+            system::Module::<Runtime>::reset_events();
             debug!(
                 "CurrentPhase {:?}",
                 sp_io::storage::get(&storage_value_key("EncointerScheduler", "CurrentPhase"))
@@ -117,127 +279,724 @@ impl Stf {
                 ))
                 .map(|v| { CeremonyIndexType::decode(&mut &v.as_slice()[..]) })
             );
+        });
 
-            match call.call {
-                TrustedCall::balance_transfer(from, to, cid, value) => {
-                    let origin = sgx_runtime::Origin::signed(AccountId32::from(from));
-                    sgx_runtime::EncointerBalancesCall::<Runtime>::transfer(
-                        MultiAddress::Id(AccountId32::from(to)),
-                        cid,
-                        value,
-                    )
-                    .dispatch_bypass_filter(origin)
-                    .map_err(|_| StfError::Dispatch("balance_transfer".to_string()))?;
-                    Ok(())
-                }
-                TrustedCall::ceremonies_register_participant(from, cid, proof) => {
-                    let origin = sgx_runtime::Origin::signed(AccountId32::from(from));
-
-                    if encointer_scheduler::Module::<sgx_runtime::Runtime>::current_phase()
-                        != CeremonyPhaseType::REGISTERING
-                    {
-                        return Err(StfError::Dispatch(
-                            "registering participants can only be done during REGISTERING phase"
-                                .to_string(),
-                        ));
-                    }
+        let batch_results = match call.call {
+            TrustedCall::batch(_, batch) => Some(Self::execute_batch(ext, sender, batch, calls)?),
+            single => {
+                ext.execute_with(|| Self::dispatch(single, calls))?;
+                None
+            }
+        };
+        ext.execute_with(|| Self::store_events(sender));
+        Ok(batch_results)
+    }
+
+    /// dispatches the calls of a batch in order. If one of them fails, the result of
+    /// every call is returned. Reverting the state is up to `execute`
+    fn execute_batch(
+        ext: &mut State,
+        sender: &AccountId,
+        batch: Vec<TrustedCall>,
+        calls: &mut Vec<OpaqueCall>,
+    ) -> Result<Vec<BatchItemResult>, StfError> {
+        if batch.is_empty() {
+            return Err(StfError::Dispatch("empty batch".to_string()));
+        }
+        if batch.iter().any(|c| c.account() != sender) {
+            return Err(StfError::Dispatch(
+                "all calls of a batch must belong to its signer".to_string(),
+            ));
+        }
+
+        let mut results: Vec<BatchItemResult> =
+            batch.iter().map(|_| BatchItemResult::NotExecuted).collect();
+        let failed = ext.execute_with(|| {
+            for (i, c) in batch.into_iter().enumerate() {
+                if let Err(e) = Self::dispatch(c, calls) {
+                    error!("call {} of batch failed: {}", i, e);
+                    results[i] = BatchItemResult::Failed;
+                    return true;
+                }
+                results[i] = BatchItemResult::Ok;
+            }
+            false
+        });
+        if failed {
+            return Err(StfError::BatchFailed(results));
+        }
+        Ok(results)
+    }
 
-                    sgx_runtime::EncointerCeremoniesCall::<Runtime>::register_participant(
+    fn transfer(
+        from: AccountId,
+        to: AccountId,
+        cid: CurrencyIdentifier,
+        value: BalanceType,
+        memo: Option<Vec<u8>>,
+    ) -> Result<(), StfError> {
+        let origin = sgx_runtime::Origin::signed(from.clone());
+        sgx_runtime::EncointerBalancesCall::<Runtime>::transfer(
+            MultiAddress::Id(to.clone()),
+            cid,
+            value,
+        )
+        .dispatch_bypass_filter(origin)
+        .map_err(|_| StfError::Dispatch("balance_transfer".to_string()))?;
+        let block_number = system::Module::<Runtime>::block_number();
+        append_transfer_record(
+            &from,
+            TransferRecord {
+                direction: TransferDirection::Outgoing,
+                counterparty: to.clone(),
+                amount: value,
+                cid,
+                block_number,
+                memo: memo.clone(),
+            },
+        );
+        append_transfer_record(
+            &to,
+            TransferRecord {
+                direction: TransferDirection::Incoming,
+                counterparty: from,
+                amount: value,
+                cid,
+                block_number,
+                memo,
+            },
+        );
+        Ok(())
+    }
+
+    fn dispatch(call: TrustedCall, calls: &mut Vec<OpaqueCall>) -> Result<(), StfError> {
+        Self::ensure_any_role(call.account(), &call.required_roles())?;
+        match call {
+            TrustedCall::balance_transfer(from, to, cid, value) => {
+                Self::transfer(from, to, cid, value, None)
+            }
+            TrustedCall::balance_transfer_with_memo(from, to, cid, value, memo) => {
+                if memo.len() > MAX_MEMO_LEN {
+                    return Err(StfError::MemoTooLong(memo.len() as u32));
+                }
+                Self::transfer(from, to, cid, value, Some(memo))
+            }
+            TrustedCall::ceremonies_register_participant(from, cid, proof) => {
+                let origin = sgx_runtime::Origin::signed(from.clone());
+
+                if encointer_scheduler::Module::<sgx_runtime::Runtime>::current_phase()
+                    != CeremonyPhaseType::REGISTERING
+                {
+                    return Err(StfError::Dispatch(
+                        "registering participants can only be done during REGISTERING phase"
+                            .to_string(),
+                    ));
+                }
+
+                sgx_runtime::EncointerCeremoniesCall::<Runtime>::register_participant(
+                    cid,
+                    proof.clone(),
+                )
+                .dispatch_bypass_filter(origin)
+                .map_err(|_| {
+                    StfError::Dispatch(format!(
+                        "ceremonies_register_participant failed for {:?} cid {} proof {:?}",
+                        from, cid, proof
+                    ))
+                })?;
+                Ok(())
+            }
+            TrustedCall::ceremonies_register_attestations(from, attestations) => {
+                debug!("Attestations {:?}", attestations);
+                debug!(
+                    "NextPhaseTimestamp {:?}",
+                    sp_io::storage::get(&storage_value_key(
+                        "EncointerScheduler",
+                        "NextPhaseTimestamp"
+                    ))
+                    .map(|v| { u64::decode(&mut &v.as_slice()[..]) })
+                );
+                debug!(
+                    "PhaseDurations for ATTESTING {:?}",
+                    sp_io::storage::get(&storage_map_key(
+                        "EncointerScheduler",
+                        "PhaseDurations",
+                        &CeremonyPhaseType::ATTESTING,
+                        &StorageHasher::Blake2_128Concat
+                    ))
+                    .map(|v| { u64::decode(&mut &v.as_slice()[..]) })
+                );
+                if encointer_scheduler::Module::<sgx_runtime::Runtime>::current_phase()
+                    != CeremonyPhaseType::ATTESTING
+                {
+                    return Err(StfError::Dispatch(
+                        "registering attestations can only be done during ATTESTING phase"
+                            .to_string(),
+                    ));
+                }
+
+                let origin = sgx_runtime::Origin::signed(from);
+                sgx_runtime::EncointerCeremoniesCall::<Runtime>::register_attestations(
+                    attestations,
+                )
+                .dispatch_bypass_filter(origin)
+                .map_err(|_| StfError::Dispatch("ceremonies_register_attestations".to_string()))?;
+                Ok(())
+            }
+            TrustedCall::ceremonies_grant_reputation(ceremony_master, cid, reputable) => {
+                let origin = sgx_runtime::Origin::signed(ceremony_master);
+                sgx_runtime::EncointerCeremoniesCall::<Runtime>::grant_reputation(cid, reputable)
+                    .dispatch_bypass_filter(origin)
+                    .map_err(|_| StfError::Dispatch("ceremonies_grant_reputation".to_string()))?;
+                Ok(())
+            }
+            TrustedCall::ceremonies_set_parameter(who, _cid, parameter) => {
+                Self::set_ceremony_parameter(&parameter)?;
+                append_parameter_change(ParameterChange {
+                    parameter,
+                    changed_by: who,
+                    block_number: system::Module::<Runtime>::block_number(),
+                });
+                Ok(())
+            }
+            TrustedCall::unshield(from, to, cid, amount) => {
+                if amount <= BalanceType::from_num(0) {
+                    return Err(StfError::Dispatch(
+                        "unshield amount must be positive".to_string(),
+                    ));
+                }
+                if encointer_balances::Module::<Runtime>::balance(cid, &from) < amount {
+                    return Err(StfError::MissingFunds);
+                }
+                let native = balance_to_native(amount)
+                    .ok_or_else(|| StfError::Dispatch("amount out of range".to_string()))?;
+                encointer_balances::Module::<Runtime>::burn(cid, &from, amount)
+                    .map_err(|_| StfError::Dispatch("unshield".to_string()))?;
+                calls.push(OpaqueCall(
+                    ([SUBSRATEE_REGISTRY_MODULE, UNSHIELD], to, native, cid).encode(),
+                ));
+                Ok(())
+            }
+            TrustedCall::schedule_transfer(from, to, cid, amount, start_block, period, count) => {
+                if amount <= BalanceType::from_num(0) {
+                    return Err(StfError::Dispatch(
+                        "scheduled amount must be positive".to_string(),
+                    ));
+                }
+                if count == 0 || (count > 1 && period == 0) {
+                    return Err(StfError::Dispatch(
+                        "recurring transfers need a count and a period".to_string(),
+                    ));
+                }
+                let deposit = BalanceType::from_num(SCHEDULED_TRANSFER_DEPOSIT);
+                if encointer_balances::Module::<Runtime>::balance(cid, &from) < amount + deposit {
+                    return Err(StfError::MissingFunds);
+                }
+                let mut ids: Vec<u32> =
+                    get_stf_storage(&scheduled_transfer_ids_key(&from)).unwrap_or_default();
+                if ids.len() >= MAX_SCHEDULED_TRANSFERS_PER_ACCOUNT {
+                    return Err(StfError::Dispatch(
+                        "too many scheduled transfers".to_string(),
+                    ));
+                }
+                encointer_balances::Module::<Runtime>::burn(cid, &from, deposit)
+                    .map_err(|_| StfError::MissingFunds)?;
+                let id: u32 = get_stf_storage(&next_scheduled_transfer_id_key()).unwrap_or(0);
+                // standing orders are executed on block import, the next one is the earliest
+                let next_block =
+                    start_block.max(system::Module::<Runtime>::block_number().saturating_add(1));
+                set_stf_storage(
+                    &scheduled_transfer_key(id),
+                    &ScheduledTransfer {
+                        id,
+                        from: from.clone(),
+                        to,
                         cid,
-                        proof.clone(),
+                        amount,
+                        next_block,
+                        period,
+                        remaining: count,
+                        deposit,
+                    },
+                );
+                schedule_at(next_block, id);
+                ids.push(id);
+                set_stf_storage(&scheduled_transfer_ids_key(&from), &ids);
+                set_stf_storage(&next_scheduled_transfer_id_key(), &id.wrapping_add(1));
+                Ok(())
+            }
+            TrustedCall::cancel_scheduled_transfer(who, _cid, id) => {
+                let scheduled =
+                    match get_stf_storage::<ScheduledTransfer>(&scheduled_transfer_key(id)) {
+                        Some(t) if t.from == who => t,
+                        _ => {
+                            return Err(StfError::Dispatch(
+                                "no such scheduled transfer".to_string(),
+                            ))
+                        }
+                    };
+                unschedule_at(scheduled.next_block, id);
+                remove_scheduled_transfer(&scheduled);
+                Ok(())
+            }
+            TrustedCall::polls_create(who, _cid, proposal, options) => {
+                if proposal.len() > MAX_PROPOSAL_LEN || options < 2 {
+                    return Err(StfError::Dispatch("invalid poll".to_string()));
+                }
+                let index: PollIndexType = get_stf_storage(&poll_count_key()).unwrap_or(0);
+                set_stf_storage(
+                    &poll_key(index),
+                    &Poll {
+                        proposal,
+                        created_by: who,
+                        tallies: alloc::vec![0; options as usize],
+                        voters: 0,
+                        closed: false,
+                    },
+                );
+                set_stf_storage(&poll_count_key(), &(index + 1));
+                Ok(())
+            }
+            TrustedCall::polls_vote(who, cid, index, option) => {
+                let mut poll: Poll = get_stf_storage(&poll_key(index))
+                    .ok_or_else(|| StfError::Dispatch("no such poll".to_string()))?;
+                if poll.closed {
+                    return Err(StfError::Dispatch("poll is closed".to_string()));
+                }
+                if option as usize >= poll.tallies.len() {
+                    return Err(StfError::Dispatch("no such option".to_string()));
+                }
+                if sp_io::storage::exists(&poll_voter_key(index, &who)) {
+                    return Err(StfError::Dispatch("already voted".to_string()));
+                }
+                let weight = Self::vote_weight(&who, cid);
+                if weight == 0 {
+                    return Err(StfError::MissingPrivileges(who));
+                }
+                poll.tallies[option as usize] += weight;
+                poll.voters += 1;
+                set_stf_storage(&poll_key(index), &poll);
+                // only remember that `who` voted, not what
+                set_stf_storage(&poll_voter_key(index, &who), &true);
+                Ok(())
+            }
+            TrustedCall::polls_close(_, _cid, index) => {
+                let mut poll: Poll = get_stf_storage(&poll_key(index))
+                    .ok_or_else(|| StfError::Dispatch("no such poll".to_string()))?;
+                if poll.closed {
+                    return Err(StfError::Dispatch("poll is closed".to_string()));
+                }
+                poll.closed = true;
+                set_stf_storage(&poll_key(index), &poll);
+                Ok(())
+            }
+            TrustedCall::register_viewing_key(owner, _cid, viewer, scopes) => {
+                if scopes.is_empty() || owner == viewer {
+                    return Err(StfError::Dispatch("invalid viewing key".to_string()));
+                }
+                set_stf_storage(&viewing_key_key(&owner, &viewer), &scopes);
+                Ok(())
+            }
+            TrustedCall::revoke_viewing_key(owner, _cid, viewer) => {
+                let key = viewing_key_key(&owner, &viewer);
+                if !sp_io::storage::exists(&key) {
+                    return Err(StfError::Dispatch("no such viewing key".to_string()));
+                }
+                sp_io::storage::clear(&key);
+                Ok(())
+            }
+            TrustedCall::add_proxy(owner, _cid, delegate, permissions, expiry) => {
+                if permissions.is_empty() || owner == delegate {
+                    return Err(StfError::Dispatch("invalid proxy".to_string()));
+                }
+                let mut proxies: Vec<Proxy> =
+                    get_stf_storage(&proxies_key(&owner)).unwrap_or_default();
+                proxies.retain(|p| p.delegate != delegate);
+                if proxies.len() >= MAX_PROXIES_PER_ACCOUNT {
+                    return Err(StfError::Dispatch("too many proxies".to_string()));
+                }
+                proxies.push(Proxy {
+                    delegate,
+                    permissions,
+                    expiry,
+                });
+                set_stf_storage(&proxies_key(&owner), &proxies);
+                Ok(())
+            }
+            TrustedCall::remove_proxy(owner, _cid, delegate) => {
+                let mut proxies: Vec<Proxy> =
+                    get_stf_storage(&proxies_key(&owner)).unwrap_or_default();
+                let len = proxies.len();
+                proxies.retain(|p| p.delegate != delegate);
+                if proxies.len() == len {
+                    return Err(StfError::Dispatch("no such proxy".to_string()));
+                }
+                set_stf_storage(&proxies_key(&owner), &proxies);
+                Ok(())
+            }
+            TrustedCall::proxied(delegate, owner, call) => {
+                if call.account() != &owner {
+                    return Err(StfError::Dispatch(
+                        "proxied call must belong to the proxied account".to_string(),
+                    ));
+                }
+                let block_number = system::Module::<Runtime>::block_number();
+                let allowed = get_stf_storage::<Vec<Proxy>>(&proxies_key(&owner))
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|p| {
+                        p.delegate == delegate
+                            && block_number <= p.expiry
+                            && p.permissions.iter().any(|perm| perm.allows(&call))
+                    });
+                if !allowed {
+                    return Err(StfError::MissingPrivileges(delegate));
+                }
+                Self::dispatch(*call, calls)
+            }
+            TrustedCall::multisig_create(who, _cid, members, threshold) => {
+                let multisig = Multisig::new(members, threshold);
+                if threshold == 0
+                    || threshold as usize > multisig.members.len()
+                    || multisig.members.len() > MAX_MULTISIG_MEMBERS
+                    || !multisig.members.contains(&who)
+                {
+                    return Err(StfError::Dispatch("invalid multisig".to_string()));
+                }
+                set_stf_storage(&multisig_key(&multisig.account()), &multisig);
+                Ok(())
+            }
+            TrustedCall::multisig_approve(member, _cid, account, call) => {
+                let multisig: Multisig = get_stf_storage(&multisig_key(&account))
+                    .ok_or_else(|| StfError::InexistentAccount(account.clone()))?;
+                if !multisig.members.contains(&member) {
+                    return Err(StfError::MissingPrivileges(member));
+                }
+                if call.account() != &account
+                    || !matches!(
+                        *call,
+                        TrustedCall::balance_transfer(_, _, _, _)
+                            | TrustedCall::balance_transfer_with_memo(_, _, _, _, _)
                     )
-                    .dispatch_bypass_filter(origin)
-                    .map_err(|_| {
-                        StfError::Dispatch(format!(
-                            "ceremonies_register_participant failed for {:?} cid {} proof {:?}",
-                            from, cid, proof
-                        ))
-                    })?;
-                    Ok(())
-                }
-                TrustedCall::ceremonies_register_attestations(from, attestations) => {
-                    debug!("Attestations {:?}", attestations);
-                    debug!(
-                        "NextPhaseTimestamp {:?}",
-                        sp_io::storage::get(&storage_value_key(
-                            "EncointerScheduler",
-                            "NextPhaseTimestamp"
-                        ))
-                        .map(|v| { u64::decode(&mut &v.as_slice()[..]) })
-                    );
-                    debug!(
-                        "PhaseDurations for ATTESTING {:?}",
-                        sp_io::storage::get(&storage_map_key(
-                            "EncointerScheduler",
-                            "PhaseDurations",
-                            &CeremonyPhaseType::ATTESTING,
-                            &StorageHasher::Blake2_128Concat
-                        ))
-                        .map(|v| { u64::decode(&mut &v.as_slice()[..]) })
+                {
+                    return Err(StfError::Dispatch(
+                        "only transfers from the multisig can be approved".to_string(),
+                    ));
+                }
+                let key = multisig_approvals_key(&account, &blake2_256(&call.encode()));
+                let mut approvals: Vec<AccountId> = get_stf_storage(&key).unwrap_or_default();
+                if approvals.contains(&member) {
+                    return Err(StfError::Dispatch("already approved".to_string()));
+                }
+                approvals.push(member);
+                if approvals.len() < multisig.threshold as usize {
+                    set_stf_storage(&key, &approvals);
+                    return Ok(());
+                }
+                sp_io::storage::clear(&key);
+                Self::dispatch(*call, calls)
+            }
+            TrustedCall::batch(_, _) => {
+                Err(StfError::Dispatch("batches can not be nested".to_string()))
+            }
+        }
+    }
+
+    /// replaces a `TrustedGetter::viewed` by the getter it wraps if the viewing key is allowed
+    /// to evaluate it. `None` if it isn't.
+    fn resolve_viewing_key(ext: &mut State, getter: TrustedGetter) -> Option<TrustedGetter> {
+        match getter {
+            TrustedGetter::viewed(viewer, inner) => {
+                let scope = inner.viewing_scope()?;
+                let owner = inner.account().clone();
+                let scopes: Vec<ViewingScope> = ext
+                    .execute_with(|| get_stf_storage(&viewing_key_key(&owner, &viewer)))
+                    .unwrap_or_default();
+                if !scopes.contains(&scope) {
+                    warn!(
+                        "viewing key {:?} may not access {:?} of {:?}",
+                        viewer, scope, owner
                     );
-                    if encointer_scheduler::Module::<sgx_runtime::Runtime>::current_phase()
-                        != CeremonyPhaseType::ATTESTING
-                    {
-                        return Err(StfError::Dispatch(
-                            "registering attestations can only be done during ATTESTING phase"
-                                .to_string(),
-                        ));
-                    }
+                    return None;
+                }
+                Some(*inner)
+            }
+            getter => Some(getter),
+        }
+    }
 
-                    let origin = sgx_runtime::Origin::signed(AccountId32::from(from));
-                    sgx_runtime::EncointerCeremoniesCall::<Runtime>::register_attestations(
-                        attestations,
-                    )
-                    .dispatch_bypass_filter(origin)
-                    .map_err(|_| {
-                        StfError::Dispatch("ceremonies_register_attestations".to_string())
-                    })?;
-                    Ok(())
-                }
-                TrustedCall::ceremonies_grant_reputation(ceremony_master, cid, reputable) => {
-                    Self::ensure_ceremony_master(ceremony_master)?;
-                    let origin = sgx_runtime::Origin::signed(AccountId32::from(ceremony_master));
-                    sgx_runtime::EncointerCeremoniesCall::<Runtime>::grant_reputation(
-                        cid, reputable,
-                    )
-                    .dispatch_bypass_filter(origin)
-                    .map_err(|_| StfError::Dispatch("ceremonies_grant_reputation".to_string()))?;
-                    Ok(())
+    /// number of ceremonies of `cid` in which `who` gained verified reputation
+    fn vote_weight(who: &AccountId, cid: CurrencyIdentifier) -> u64 {
+        let current = encointer_scheduler::Module::<Runtime>::current_ceremony_index();
+        (1..=current)
+            .filter(|cindex| {
+                matches!(
+                    encointer_ceremonies::Module::<Runtime>::participant_reputation(
+                        (cid, *cindex),
+                        who
+                    ),
+                    Reputation::VerifiedUnlinked | Reputation::VerifiedLinked
+                )
+            })
+            .count() as u64
+    }
+
+    /// the nonce the next call of `account` must have
+    pub fn nonce(ext: &mut State, account: &AccountId) -> u32 {
+        ext.execute_with(|| Self::account_nonce(account))
+    }
+
+    /// whether `account` holds any funds in currency `cid`
+    pub fn holds_funds(ext: &mut State, account: &AccountId, cid: CurrencyIdentifier) -> bool {
+        ext.execute_with(|| {
+            encointer_balances::Module::<Runtime>::balance(cid, account) > BalanceType::from_num(0)
+        })
+    }
+
+    /// stores the receipt of the call with `call_hash`, queryable by its signer through
+    /// `TrustedGetter::call_receipt` for `CALL_RECEIPT_RETENTION_PERIOD` blocks
+    pub fn record_call_receipt(ext: &mut State, call_hash: H256, receipt: CallReceipt) {
+        ext.execute_with(|| {
+            let recorded_key = call_receipts_recorded_at_key(receipt.block_number);
+            let mut recorded: Vec<H256> = get_stf_storage(&recorded_key).unwrap_or_default();
+            recorded.push(call_hash);
+            set_stf_storage(&recorded_key, &recorded);
+            set_stf_storage(&call_receipt_key(&call_hash), &receipt);
+        });
+    }
+
+    /// drops the receipts which have expired at block `number`. Blocks are imported one by
+    /// one, so each block's receipts are pruned exactly once. Returns whether there were any
+    pub fn prune_call_receipts(ext: &mut State, number: BlockNumber) -> bool {
+        let expired = match number.checked_sub(CALL_RECEIPT_RETENTION_PERIOD) {
+            Some(expired) => expired,
+            None => return false,
+        };
+        ext.execute_with(|| {
+            let recorded_key = call_receipts_recorded_at_key(expired);
+            let recorded: Vec<H256> = get_stf_storage(&recorded_key).unwrap_or_default();
+            for call_hash in recorded.iter() {
+                let key = call_receipt_key(call_hash);
+                // a call which has been submitted again keeps its newer receipt
+                if get_stf_storage::<CallReceipt>(&key).map_or(false, |r| r.block_number == expired)
+                {
+                    sp_io::storage::clear(&key);
                 }
             }
+            sp_io::storage::clear(&recorded_key);
+            !recorded.is_empty()
         })
     }
 
+    /// moves the events the runtime deposited since they were last reset to the event log
+    /// of `who`
+    fn store_events(who: &AccountId) {
+        let records = system::Module::<Runtime>::events();
+        system::Module::<Runtime>::reset_events();
+        if records.is_empty() {
+            return;
+        }
+        let block_number = system::Module::<Runtime>::block_number();
+        let count_key = event_count_key(who);
+        let mut count: u32 = get_stf_storage(&count_key).unwrap_or(0);
+        for record in records.into_iter() {
+            let (pallet, event) = match record.event {
+                sgx_runtime::Event::system(e) => (EventPallet::System, e.encode()),
+                sgx_runtime::Event::encointer_scheduler(e) => {
+                    (EventPallet::EncointerScheduler, e.encode())
+                }
+                sgx_runtime::Event::encointer_ceremonies(e) => {
+                    (EventPallet::EncointerCeremonies, e.encode())
+                }
+                sgx_runtime::Event::encointer_currencies(e) => {
+                    (EventPallet::EncointerCurrencies, e.encode())
+                }
+                sgx_runtime::Event::encointer_balances(e) => {
+                    (EventPallet::EncointerBalances, e.encode())
+                }
+                _ => {
+                    debug!("event of another pallet is not stored");
+                    continue;
+                }
+            };
+            if event.len() > MAX_EVENT_LEN {
+                warn!("event of {} bytes is not stored", event.len());
+                continue;
+            }
+            set_stf_storage(
+                &event_key(who, count),
+                &StfEvent {
+                    block_number,
+                    pallet,
+                    event,
+                },
+            );
+            if let Some(dropped) = count.checked_sub(MAX_EVENTS_PER_ACCOUNT) {
+                sp_io::storage::clear(&event_key(who, dropped));
+            }
+            count += 1;
+        }
+        set_stf_storage(&count_key, &count);
+    }
+
+    /// executes the standing orders which are due at block `number`. Blocks are imported one
+    /// by one, so only the orders indexed at `number` are touched. Each transfer is executed
+    /// on its own, so a failing one doesn't affect the others. A failed occurrence is
+    /// skipped, not retried. Returns whether any order was due
+    pub fn execute_scheduled_transfers(ext: &mut State, number: BlockNumber) -> bool {
+        let due: Vec<ScheduledTransfer> = ext.execute_with(|| {
+            let key = scheduled_transfers_due_key(number);
+            let ids: Vec<u32> = get_stf_storage(&key).unwrap_or_default();
+            sp_io::storage::clear(&key);
+            ids.into_iter()
+                .filter_map(|id| get_stf_storage(&scheduled_transfer_key(id)))
+                .collect()
+        });
+        if due.is_empty() {
+            return false;
+        }
+        for t in due.iter() {
+            let checkpoint = ext.checkpoint();
+            match ext.execute_with(|| {
+                system::Module::<Runtime>::reset_events();
+                Self::transfer(t.from.clone(), t.to.clone(), t.cid, t.amount, None)?;
+                Self::store_events(&t.from);
+                Ok::<(), StfError>(())
+            }) {
+                Ok(()) => ext.commit(checkpoint),
+                Err(e) => {
+                    warn!("scheduled transfer {} failed: {}", t.id, e);
+                    ext.rollback(checkpoint);
+                }
+            }
+        }
+        ext.execute_with(|| {
+            for mut t in due {
+                t.remaining -= 1;
+                if t.remaining == 0 {
+                    remove_scheduled_transfer(&t);
+                    continue;
+                }
+                t.next_block = number.saturating_add(t.period);
+                schedule_at(t.next_block, t.id);
+                set_stf_storage(&scheduled_transfer_key(t.id), &t);
+            }
+        });
+        true
+    }
+
     pub fn get_state(ext: &mut State, getter: Getter) -> Option<Vec<u8>> {
+        let getter = match getter {
+            Getter::trusted(g) => Getter::trusted(TrustedGetterSigned {
+                getter: Self::resolve_viewing_key(ext, g.getter.evaluated().clone())?,
+                ..g
+            }),
+            getter => getter,
+        };
         ext.execute_with(||
             match getter {
                 Getter::trusted(g) => match g.getter {
                     TrustedGetter::balance(who, cid) => {
-                        let balance: BalanceEntry<BlockNumber> = encointer_balances::Module::<sgx_runtime::Runtime>::balance_entry(cid, AccountId32::from(who));
+                        // demurrage is applied up to the block number of the state
+                        let balance: BalanceType = encointer_balances::Module::<sgx_runtime::Runtime>::balance(cid, &who);
                         Some(balance.encode())
                     },
                     TrustedGetter::participant_index(who, cid) => {
                         let c_index = encointer_scheduler::Module::<sgx_runtime::Runtime>::current_ceremony_index();
-                        let part: ParticipantIndexType = encointer_ceremonies::Module::<sgx_runtime::Runtime>::participant_index((cid, c_index), AccountId32::from(who));
+                        let part: ParticipantIndexType = encointer_ceremonies::Module::<sgx_runtime::Runtime>::participant_index((cid, c_index), &who);
                         Some(part.encode())
                     }
                     TrustedGetter::meetup_index(who, cid) => {
                         let c_index = encointer_scheduler::Module::<sgx_runtime::Runtime>::current_ceremony_index();
-                        let meetup_index: MeetupIndexType = encointer_ceremonies::Module::<sgx_runtime::Runtime>::meetup_index((cid, c_index), AccountId32::from(who));
+                        let meetup_index: MeetupIndexType = encointer_ceremonies::Module::<sgx_runtime::Runtime>::meetup_index((cid, c_index), &who);
                         Some(meetup_index.encode())
                     }
                     TrustedGetter::attestations(who, cid) => {
                         let c_index = encointer_scheduler::Module::<sgx_runtime::Runtime>::current_ceremony_index();
-                        let attestation_index = encointer_ceremonies::Module::<sgx_runtime::Runtime>::attestation_index((cid, c_index), AccountId32::from(who));
+                        let attestation_index = encointer_ceremonies::Module::<sgx_runtime::Runtime>::attestation_index((cid, c_index), &who);
                         let attestations = encointer_ceremonies::Module::<sgx_runtime::Runtime>::attestation_registry((cid, c_index), attestation_index);
                         Some(attestations.encode())
                     }
                     TrustedGetter::meetup_registry(who, cid) => {
                         let c_index = encointer_scheduler::Module::<sgx_runtime::Runtime>::current_ceremony_index();
-                        let meetup_index: MeetupIndexType = encointer_ceremonies::Module::<sgx_runtime::Runtime>::meetup_index((cid, c_index), AccountId32::from(who));
+                        let meetup_index: MeetupIndexType = encointer_ceremonies::Module::<sgx_runtime::Runtime>::meetup_index((cid, c_index), &who);
                         let registry: Vec<AccountId32> = encointer_ceremonies::Module::<sgx_runtime::Runtime>::meetup_registry((cid, c_index), meetup_index);
                         Some(registry.encode())
                     }
+                    TrustedGetter::nonce(who) => {
+                        let nonce = Self::account_nonce(&who);
+                        Some(nonce.encode())
+                    }
+                    TrustedGetter::reputation(who, cid, cindex) => {
+                        let reputation = encointer_ceremonies::Module::<sgx_runtime::Runtime>::participant_reputation((cid, cindex), &who);
+                        Some(reputation.encode())
+                    }
+                    TrustedGetter::meetup_assignment(who, cid) => {
+                        Some(Self::meetup_assignment(&who, cid).encode())
+                    }
+                    TrustedGetter::transfer_history(who, cid, from, limit) => {
+                        let count: u32 = get_stf_storage(&transfer_history_count_key(&who, &cid))
+                            .unwrap_or(0);
+                        let to = from
+                            .saturating_add(limit.min(TRANSFER_HISTORY_PAGE_SIZE))
+                            .min(count);
+                        let records: Vec<TransferRecord> = (from..to)
+                            .filter_map(|i| get_stf_storage(&transfer_history_key(&who, &cid, i)))
+                            .collect();
+                        Some(records.encode())
+                    }
+                    TrustedGetter::scheduled_transfers(who, _cid) => {
+                        let ids: Vec<u32> = get_stf_storage(&scheduled_transfer_ids_key(&who))
+                            .unwrap_or_default();
+                        let scheduled: Vec<ScheduledTransfer> = ids
+                            .into_iter()
+                            .filter_map(|id| get_stf_storage(&scheduled_transfer_key(id)))
+                            .collect();
+                        Some(scheduled.encode())
+                    }
+                    TrustedGetter::proxies(who, _cid) => {
+                        let proxies: Vec<Proxy> = get_stf_storage(&proxies_key(&who)).unwrap_or_default();
+                        Some(proxies.encode())
+                    }
+                    TrustedGetter::multisig_balance(who, cid, account) => {
+                        match get_stf_storage::<Multisig>(&multisig_key(&account)) {
+                            Some(multisig) if multisig.members.contains(&who) => {
+                                let balance: BalanceType = encointer_balances::Module::<sgx_runtime::Runtime>::balance(cid, &account);
+                                Some(balance.encode())
+                            }
+                            _ => None,
+                        }
+                    }
+                    TrustedGetter::call_receipt(who, call_hash) => {
+                        match get_stf_storage::<CallReceipt>(&call_receipt_key(&call_hash)) {
+                            Some(receipt) if receipt.signer.as_ref().map_or(true, |s| s == &who) => {
+                                Some(receipt.encode())
+                            }
+                            _ => None,
+                        }
+                    }
+                    TrustedGetter::events(who, _cid, from, limit) => {
+                        let count: u32 = get_stf_storage(&event_count_key(&who)).unwrap_or(0);
+                        let to = from
+                            .saturating_add(limit.min(EVENTS_PAGE_SIZE))
+                            .min(count);
+                        // dropped events are skipped
+                        let events: Vec<StfEvent> = (from..to)
+                            .filter_map(|i| get_stf_storage(&event_key(&who, i)))
+                            .collect();
+                        Some(events.encode())
+                    }
+                    // answered by the enclave from its operation pool
+                    TrustedGetter::pending_calls(_, _) => None,
+                    // only one level of `with_proof` is unwrapped
+                    TrustedGetter::with_proof(_) => None,
+                    TrustedGetter::ceremony_history(who, cid) => {
+                        let current = encointer_scheduler::Module::<sgx_runtime::Runtime>::current_ceremony_index();
+                        let history: Vec<CeremonyRecord> = (1..=current)
+                            .filter_map(|cindex| Self::ceremony_record(&who, cid, cindex))
+                            .collect();
+                        Some(history.encode())
+                    }
                 },
                 Getter::public(g) => match g {
                     PublicGetter::total_issuance(cid) => {
@@ -262,6 +1021,26 @@ impl Stf {
                         let count = encointer_ceremonies::Module::<sgx_runtime::Runtime>::meetup_count((cid, c_index));
                         Some(count.encode())
                     },
+                    PublicGetter::parameter_changes(_cid, from, limit) => {
+                        let count: u32 = get_stf_storage(&parameter_change_count_key()).unwrap_or(0);
+                        let to = from
+                            .saturating_add(limit.min(PARAMETER_CHANGES_PAGE_SIZE))
+                            .min(count);
+                        let changes: Vec<ParameterChange> = (from..to)
+                            .filter_map(|i| get_stf_storage(&parameter_change_key(i)))
+                            .collect();
+                        Some(changes.encode())
+                    },
+                    PublicGetter::poll_result(_cid, index) => {
+                        match get_stf_storage::<Poll>(&poll_key(index)) {
+                            Some(poll) if poll.closed => Some(PollResult {
+                                proposal: poll.proposal,
+                                tallies: poll.tallies,
+                                voters: poll.voters,
+                            }.encode()),
+                            _ => None,
+                        }
+                    },
                     PublicGetter::ceremony_reward(_cid) => {
                         let reward = encointer_ceremonies::Module::<sgx_runtime::Runtime>::ceremony_reward();
                         Some(reward.encode())
@@ -290,23 +1069,146 @@ impl Stf {
         )
     }
 
-    fn ensure_ceremony_master(account: AccountId) -> Result<(), StfError> {
-        if sp_io::storage::get(&storage_value_key("EncointerScheduler", "CeremonyMaster")).unwrap()
-            == account.encode()
-        {
+    /// `None` if `who` hasn't been assigned to a meetup in the current ceremony
+    fn meetup_assignment(who: &AccountId, cid: CurrencyIdentifier) -> Option<MeetupAssignment> {
+        let cindex = encointer_scheduler::Module::<Runtime>::current_ceremony_index();
+        let meetup_index =
+            encointer_ceremonies::Module::<Runtime>::meetup_index((cid, cindex), who);
+        if meetup_index == 0 {
+            return None;
+        }
+        let location = *encointer_currencies::Module::<Runtime>::locations(cid)
+            .get((meetup_index - 1) as usize)?;
+        let participants =
+            encointer_ceremonies::Module::<Runtime>::meetup_registry((cid, cindex), meetup_index);
+        Some(MeetupAssignment {
+            meetup_index,
+            location,
+            time: Self::meetup_time(&location),
+            participants,
+        })
+    }
+
+    /// meetups take place at high noon local (solar) time on the attesting day
+    fn meetup_time(location: &Location) -> Option<Moment> {
+        // as long as the runtime pallet is rounding lon, we should do so too
+        let lon: i64 = location.lon.round().to_num();
+        let next_phase_timestamp = encointer_scheduler::Module::<Runtime>::next_phase_timestamp();
+        let attesting_start = match encointer_scheduler::Module::<Runtime>::current_phase() {
+            CeremonyPhaseType::ASSIGNING => next_phase_timestamp,
+            CeremonyPhaseType::ATTESTING => next_phase_timestamp.checked_sub(
+                encointer_scheduler::Module::<Runtime>::phase_durations(
+                    CeremonyPhaseType::ATTESTING,
+                ),
+            )?,
+            CeremonyPhaseType::REGISTERING => return None,
+        };
+        let offset = lon * ONE_DAY as i64 / 360;
+        Some((attesting_start as i64 + (ONE_DAY / 2) as i64 - offset) as Moment)
+    }
+
+    /// `None` if `who` has neither registered nor gained reputation in that ceremony
+    fn ceremony_record(
+        who: &AccountId,
+        cid: CurrencyIdentifier,
+        cindex: CeremonyIndexType,
+    ) -> Option<CeremonyRecord> {
+        let participant_index =
+            encointer_ceremonies::Module::<Runtime>::participant_index((cid, cindex), who);
+        let reputation =
+            encointer_ceremonies::Module::<Runtime>::participant_reputation((cid, cindex), who);
+        if participant_index == 0 && reputation == Reputation::Unverified {
+            return None;
+        }
+        let rewarded = matches!(
+            reputation,
+            Reputation::VerifiedUnlinked | Reputation::VerifiedLinked
+        );
+        Some(CeremonyRecord {
+            cindex,
+            participant_index,
+            meetup_index: encointer_ceremonies::Module::<Runtime>::meetup_index((cid, cindex), who),
+            attended: encointer_ceremonies::Module::<Runtime>::attestation_index(
+                (cid, cindex),
+                who,
+            ) != 0,
+            rewarded,
+            reputation,
+        })
+    }
+
+    /// the nonce of incognito accounts lives in the shard, it is never synced from chain
+    fn account_nonce(account: &AccountId) -> u32 {
+        system::Module::<Runtime>::account_nonce(account)
+    }
+
+    fn increment_nonce(account: &AccountId) {
+        system::Module::<Runtime>::inc_account_nonce(account);
+    }
+
+    /// overrides the genesis value of `init_state`
+    fn set_ceremony_parameter(parameter: &CeremonyParameter) -> Result<(), StfError> {
+        match parameter {
+            CeremonyParameter::Reward(reward) => {
+                if *reward < BalanceType::from_num(0) {
+                    return Err(StfError::Dispatch(
+                        "ceremony reward must not be negative".to_string(),
+                    ));
+                }
+                sp_io::storage::set(
+                    &storage_value_key("EncointerCeremonies", "CeremonyReward"),
+                    &reward.encode(),
+                );
+            }
+            CeremonyParameter::TimeTolerance(tolerance) => sp_io::storage::set(
+                &storage_value_key("EncointerCeremonies", "TimeTolerance"),
+                &tolerance.encode(),
+            ),
+            CeremonyParameter::LocationTolerance(tolerance) => sp_io::storage::set(
+                &storage_value_key("EncointerCeremonies", "LocationTolerance"),
+                &tolerance.encode(),
+            ),
+        };
+        Ok(())
+    }
+
+    /// roles which have never been synced from chain are not held by anyone
+    pub fn has_role(account: &AccountId, role: &Role) -> bool {
+        match role {
+            Role::CeremonyMaster => {
+                sp_io::storage::get(&storage_value_key("EncointerScheduler", "CeremonyMaster"))
+                    == Some(account.encode())
+            }
+            Role::Bootstrapper(cid) => {
+                encointer_currencies::Module::<Runtime>::bootstrappers(cid).contains(account)
+            }
+            Role::ShardAdmin => {
+                sp_io::storage::get(&storage_value_key("Sudo", "Key")) == Some(account.encode())
+            }
+        }
+    }
+
+    fn ensure_any_role(account: &AccountId, roles: &[Role]) -> Result<(), StfError> {
+        if roles.is_empty() || roles.iter().any(|r| Self::has_role(account, r)) {
             Ok(())
         } else {
-            Err(StfError::MissingPrivileges(account))
+            Err(StfError::MissingPrivileges(account.clone()))
         }
     }
 
     // only add key hashes that are not already updated on block
     pub fn get_storage_hashes_to_update(call: &TrustedCallSigned) -> Vec<Vec<u8>> {
         let mut key_hashes = Vec::new();
-        match call.call {
-            TrustedCall::balance_transfer(account, _, _, _) => {
-                key_hashes.push(nonce_key_hash(&account))
-            }
+        Self::collect_storage_hashes_to_update(&call.call, &mut key_hashes);
+        key_hashes.sort();
+        key_hashes.dedup();
+        key_hashes
+    }
+
+    fn collect_storage_hashes_to_update(call: &TrustedCall, key_hashes: &mut Vec<Vec<u8>>) {
+        match call {
+            TrustedCall::balance_transfer(_, _, _, _) => {}
+            TrustedCall::balance_transfer_with_memo(_, _, _, _, _) => {}
             TrustedCall::ceremonies_register_participant(_, _, _) => {
                 key_hashes.push(storage_value_key(
                     "EncointerCurrencies",
@@ -319,19 +1221,50 @@ impl Stf {
                     "CurrencyIdentifiers",
                 ));
             }
-            TrustedCall::ceremonies_grant_reputation(_, _, _) => {
-                key_hashes.push(storage_value_key("EncointerScheduler", "CeremonyMaster"));
+            TrustedCall::ceremonies_grant_reputation(_, _, _) => {}
+            TrustedCall::ceremonies_set_parameter(_, _, _) => {}
+            TrustedCall::unshield(_, _, _, _) => {}
+            TrustedCall::schedule_transfer(_, _, _, _, _, _, _) => {}
+            TrustedCall::cancel_scheduled_transfer(_, _, _) => {}
+            TrustedCall::polls_create(_, _, _, _) => {}
+            // reputation is part of the shard state
+            TrustedCall::polls_vote(_, _, _, _) => {}
+            TrustedCall::polls_close(_, _, _) => {}
+            TrustedCall::register_viewing_key(_, _, _, _) => {}
+            TrustedCall::revoke_viewing_key(_, _, _) => {}
+            TrustedCall::add_proxy(_, _, _, _, _) => {}
+            TrustedCall::remove_proxy(_, _, _) => {}
+            TrustedCall::proxied(_, _, call) => {
+                Self::collect_storage_hashes_to_update(call, key_hashes)
+            }
+            TrustedCall::multisig_create(_, _, _, _) => {}
+            TrustedCall::multisig_approve(_, _, _, call) => {
+                Self::collect_storage_hashes_to_update(call, key_hashes)
+            }
+            TrustedCall::batch(_, batch) => {
+                batch
+                    .iter()
+                    .for_each(|c| Self::collect_storage_hashes_to_update(c, key_hashes));
             }
         };
-        key_hashes
     }
 
     pub fn get_storage_hashes_to_update_for_getter(getter: &Getter) -> Vec<Vec<u8>> {
+        let mut key_hashes = Self::storage_hashes_to_update_on_block();
+        if let Getter::trusted(g) = getter {
+            let getter = match g.getter.evaluated() {
+                TrustedGetter::viewed(_, inner) => inner.as_ref(),
+                getter => getter,
+            };
+            if let TrustedGetter::balance(_, cid) = getter {
+                key_hashes.push(currency_properties_key_hash(cid));
+            }
+        }
         debug!(
-            "No specific storage updates needed for getter. Returning those for on block: {:?}",
-            getter
+            "storage updates needed for getter {:?}: {:?}",
+            getter, key_hashes
         );
-        Self::storage_hashes_to_update_on_block()
+        key_hashes
     }
 
     pub fn storage_hashes_to_update_on_block() -> Vec<Vec<u8>> {
@@ -356,6 +1289,10 @@ impl Stf {
             &StorageHasher::Blake2_128Concat,
         ));
 
+        // roles, see `has_role`. Bootstrappers are synced per shard
+        key_hashes.push(storage_value_key("EncointerScheduler", "CeremonyMaster"));
+        key_hashes.push(storage_value_key("Sudo", "Key"));
+
         key_hashes
     }
 }
@@ -366,6 +1303,7 @@ pub fn storage_hashes_to_update_per_shard(shard: &ShardIdentifier) -> Vec<Vec<u8
     // for encointer CID == ShardIdentifier
     key_hashes.push(bootstrapper_key_hash(shard));
     key_hashes.push(location_key_hash(shard));
+    key_hashes.push(currency_properties_key_hash(shard));
 
     key_hashes
 }
@@ -387,83 +1325,229 @@ pub fn location_key_hash(cid: &CurrencyIdentifier) -> Vec<u8> {
     )
 }
 
+/// holds the demurrage rate of the currency
+pub fn currency_properties_key_hash(cid: &CurrencyIdentifier) -> Vec<u8> {
+    storage_map_key(
+        "EncointerCurrencies",
+        "CurrencyProperties",
+        cid,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
 pub fn shards_key_hash() -> Vec<u8> {
     storage_value_key("EncointerCurrencies", "CurrencyIdentifiers")
 }
 
-// get the AccountInfo key where the nonce is stored
-pub fn nonce_key_hash(account: &AccountId) -> Vec<u8> {
+pub fn parameter_change_count_key() -> Vec<u8> {
+    storage_value_key(STF_MODULE_PREFIX, "ParameterChangeCount")
+}
+
+pub fn parameter_change_key(index: u32) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "ParameterChanges",
+        &index,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn transfer_history_count_key(who: &AccountId, cid: &CurrencyIdentifier) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "TransferHistoryCount",
+        &(cid, who),
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn transfer_history_key(who: &AccountId, cid: &CurrencyIdentifier, index: u32) -> Vec<u8> {
+    storage_double_map_key(
+        STF_MODULE_PREFIX,
+        "TransferHistory",
+        &(cid, who),
+        &StorageHasher::Blake2_128Concat,
+        &index,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn scheduled_transfer_key(id: u32) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "ScheduledTransfers",
+        &id,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+/// ids of the standing orders which are due at block `number`
+pub fn scheduled_transfers_due_key(number: BlockNumber) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "ScheduledTransfersDue",
+        &number,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+/// ids of the standing orders of `who`
+pub fn scheduled_transfer_ids_key(who: &AccountId) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "ScheduledTransferIds",
+        who,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn next_scheduled_transfer_id_key() -> Vec<u8> {
+    storage_value_key(STF_MODULE_PREFIX, "NextScheduledTransferId")
+}
+
+pub fn poll_count_key() -> Vec<u8> {
+    storage_value_key(STF_MODULE_PREFIX, "PollCount")
+}
+
+pub fn poll_key(index: PollIndexType) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "Polls",
+        &index,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn poll_voter_key(index: PollIndexType, who: &AccountId) -> Vec<u8> {
+    storage_double_map_key(
+        STF_MODULE_PREFIX,
+        "PollVoters",
+        &index,
+        &StorageHasher::Blake2_128Concat,
+        who,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn multisig_key(account: &AccountId) -> Vec<u8> {
     storage_map_key(
-        "System",
-        "Account",
+        STF_MODULE_PREFIX,
+        "Multisigs",
         account,
         &StorageHasher::Blake2_128Concat,
     )
 }
 
-pub fn storage_value_key(module_prefix: &str, storage_prefix: &str) -> Vec<u8> {
-    let mut bytes = sp_core::twox_128(module_prefix.as_bytes()).to_vec();
-    bytes.extend(&sp_core::twox_128(storage_prefix.as_bytes())[..]);
-    bytes
-}
-
-pub fn storage_map_key<K: Encode>(
-    module_prefix: &str,
-    storage_prefix: &str,
-    mapkey1: &K,
-    hasher1: &StorageHasher,
-) -> Vec<u8> {
-    let mut bytes = sp_core::twox_128(module_prefix.as_bytes()).to_vec();
-    bytes.extend(&sp_core::twox_128(storage_prefix.as_bytes())[..]);
-    bytes.extend(key_hash(mapkey1, hasher1));
-    bytes
-}
-
-pub fn storage_double_map_key<K: Encode, Q: Encode>(
-    module_prefix: &str,
-    storage_prefix: &str,
-    mapkey1: &K,
-    hasher1: &StorageHasher,
-    mapkey2: &Q,
-    hasher2: &StorageHasher,
-) -> Vec<u8> {
-    let mut bytes = sp_core::twox_128(module_prefix.as_bytes()).to_vec();
-    bytes.extend(&sp_core::twox_128(storage_prefix.as_bytes())[..]);
-    bytes.extend(key_hash(mapkey1, hasher1));
-    bytes.extend(key_hash(mapkey2, hasher2));
-    bytes
-}
-
-/// generates the key's hash depending on the StorageHasher selected
-fn key_hash<K: Encode>(key: &K, hasher: &StorageHasher) -> Vec<u8> {
-    let encoded_key = key.encode();
-    match hasher {
-        StorageHasher::Identity => encoded_key.to_vec(),
-        StorageHasher::Blake2_128 => sp_core::blake2_128(&encoded_key).to_vec(),
-        StorageHasher::Blake2_128Concat => {
-            // copied from substrate Blake2_128Concat::hash since StorageHasher is not public
-            let x: &[u8] = encoded_key.as_slice();
-            sp_core::blake2_128(x)
-                .iter()
-                .chain(x.iter())
-                .cloned()
-                .collect::<Vec<_>>()
-        }
-        StorageHasher::Blake2_256 => sp_core::blake2_256(&encoded_key).to_vec(),
-        StorageHasher::Twox128 => sp_core::twox_128(&encoded_key).to_vec(),
-        StorageHasher::Twox256 => sp_core::twox_256(&encoded_key).to_vec(),
-        StorageHasher::Twox64Concat => sp_core::twox_64(&encoded_key).to_vec(),
-    }
-}
-
-#[derive(Debug, Display)]
-pub enum StfError {
-    #[display(fmt = "Insufficient privileges {:?}, are you sure you are root?", _0)]
-    MissingPrivileges(AccountId),
-    #[display(fmt = "Error dispatching runtime call")]
-    Dispatch(String),
-    #[display(fmt = "Not enough funds to perform operation")]
-    MissingFunds,
-    #[display(fmt = "Account does not exist {:?}", _0)]
-    InexistentAccount(AccountId),
+pub fn multisig_approvals_key(account: &AccountId, call_hash: &[u8; 32]) -> Vec<u8> {
+    storage_double_map_key(
+        STF_MODULE_PREFIX,
+        "MultisigApprovals",
+        account,
+        &StorageHasher::Blake2_128Concat,
+        call_hash,
+        &StorageHasher::Identity,
+    )
+}
+
+pub fn call_receipt_key(call_hash: &H256) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "CallReceipts",
+        call_hash,
+        &StorageHasher::Identity,
+    )
+}
+
+/// hashes of the calls whose receipts have been recorded in block `number`
+pub fn call_receipts_recorded_at_key(number: BlockNumber) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "CallReceiptsRecordedAt",
+        &number,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn event_count_key(who: &AccountId) -> Vec<u8> {
+    storage_map_key(
+        STF_MODULE_PREFIX,
+        "EventCount",
+        who,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+pub fn viewing_key_key(owner: &AccountId, viewer: &AccountId) -> Vec<u8> {
+    storage_double_map_key(
+        STF_MODULE_PREFIX,
+        "ViewingKeys",
+        owner,
+        &StorageHasher::Blake2_128Concat,
+        viewer,
+        &StorageHasher::Blake2_128Concat,
+    )
+}
+
+fn append_transfer_record(who: &AccountId, record: TransferRecord) {
+    let count_key = transfer_history_count_key(who, &record.cid);
+    let count: u32 = get_stf_storage(&count_key).unwrap_or(0);
+    set_stf_storage(&transfer_history_key(who, &record.cid, count), &record);
+    set_stf_storage(&count_key, &(count + 1));
+}
+
+fn append_parameter_change(change: ParameterChange) {
+    let count: u32 = get_stf_storage(&parameter_change_count_key()).unwrap_or(0);
+    set_stf_storage(&parameter_change_key(count), &change);
+    set_stf_storage(&parameter_change_count_key(), &(count + 1));
+}
+
+fn schedule_at(number: BlockNumber, id: u32) {
+    let key = scheduled_transfers_due_key(number);
+    let mut ids: Vec<u32> = get_stf_storage(&key).unwrap_or_default();
+    ids.push(id);
+    set_stf_storage(&key, &ids);
+}
+
+fn unschedule_at(number: BlockNumber, id: u32) {
+    let key = scheduled_transfers_due_key(number);
+    let mut ids: Vec<u32> = get_stf_storage(&key).unwrap_or_default();
+    ids.retain(|i| *i != id);
+    if ids.is_empty() {
+        sp_io::storage::clear(&key);
+    } else {
+        set_stf_storage(&key, &ids);
+    }
+}
+
+/// removes a standing order which is not due at any block anymore and returns its deposit
+fn remove_scheduled_transfer(scheduled: &ScheduledTransfer) {
+    sp_io::storage::clear(&scheduled_transfer_key(scheduled.id));
+    let ids_key = scheduled_transfer_ids_key(&scheduled.from);
+    let mut ids: Vec<u32> = get_stf_storage(&ids_key).unwrap_or_default();
+    ids.retain(|i| *i != scheduled.id);
+    if ids.is_empty() {
+        sp_io::storage::clear(&ids_key);
+    } else {
+        set_stf_storage(&ids_key, &ids);
+    }
+    if encointer_balances::Module::<Runtime>::issue(
+        scheduled.cid,
+        &scheduled.from,
+        scheduled.deposit,
+    )
+    .is_err()
+    {
+        warn!(
+            "failed to return the deposit of scheduled transfer {}",
+            scheduled.id
+        );
+    }
+}
+
+fn get_stf_storage<V: Decode>(key: &[u8]) -> Option<V> {
+    sp_io::storage::get(key).and_then(|v| V::decode(&mut v.as_slice()).ok())
+}
+
+fn set_stf_storage<V: Encode>(key: &[u8], value: &V) {
+    sp_io::storage::set(key, &value.encode())
 }
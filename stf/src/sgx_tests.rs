@@ -0,0 +1,681 @@
+/*
+    Copyright 2019 Supercomputing Systems AG
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+
+*/
+
+//! Stf tests which need the sgx runtime. They are run inside the enclave by `test_main_entrance`
+
+use sgx_tstd as std;
+use std::prelude::v1::*;
+
+use codec::{Decode, Encode};
+use encointer_balances::BalanceType;
+use encointer_scheduler::CeremonyPhaseType;
+use sgx_runtime::Runtime;
+use sp_core::{sr25519, Pair, H256};
+use sp_io::SgxExternalitiesTrait;
+use sp_runtime::MultiSignature;
+
+use crate::keys::proof_keys;
+use crate::sgx::{encode_state, prove_state, state_root, storage_value_key};
+use crate::{
+    AccountId, BatchItemResult, CallReceipt, CeremonyParameter, CurrencyIdentifier, Getter,
+    KeyPair, Multisig, ParameterChange, PollResult, ProxyPermission, PublicGetter, State, Stf,
+    StfError, StfEvent, TransferDirection, TransferRecord, TrustedCall, TrustedCallSigned,
+    TrustedGetter, TrustedGetterSigned, ViewingScope, CALL_RECEIPT_RETENTION_PERIOD,
+    EVENTS_PAGE_SIZE, MAX_MEMO_LEN,
+};
+
+fn account(seed: u8) -> AccountId {
+    AccountId::from([seed; 32])
+}
+
+fn cid() -> CurrencyIdentifier {
+    H256::from([7u8; 32])
+}
+
+// execute does not verify signatures, that's done by the enclave before
+fn unsigned(call: TrustedCall, nonce: u32) -> TrustedCallSigned {
+    TrustedCallSigned::new(
+        call,
+        nonce,
+        MultiSignature::from(sr25519::Signature::from_raw([0u8; 64])),
+    )
+}
+
+fn set_phase(state: &mut State, phase: CeremonyPhaseType) {
+    state.execute_with(|| {
+        sp_io::storage::set(
+            &storage_value_key("EncointerScheduler", "CurrentPhase"),
+            &phase.encode(),
+        )
+    });
+}
+
+fn issue(state: &mut State, who: &AccountId, amount: i32) {
+    state.execute_with(|| {
+        encointer_balances::Module::<Runtime>::issue(cid(), who, BalanceType::from_num(amount))
+            .unwrap()
+    });
+}
+
+/// executes a call which is expected to fail and checks that it has only used up the sender's
+/// nonce. A call with an invalid nonce must leave the state unchanged
+fn assert_rolled_back(mut state: State, call: TrustedCallSigned) -> StfError {
+    let mut expected = state.clone();
+    let sender = call.call.account().clone();
+    let err = Stf::execute(&mut state, call, &mut Vec::new()).unwrap_err();
+    if !matches!(err, StfError::InvalidNonce(_, _)) {
+        expected.execute_with(|| system::Module::<Runtime>::inc_account_nonce(&sender));
+    }
+    assert_eq!(encode_state(&state), encode_state(&expected));
+    err
+}
+
+pub fn test_failed_balance_transfer_is_rolled_back() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 1);
+    let call =
+        TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(2));
+    assert_rolled_back(state, unsigned(call, 0));
+}
+
+pub fn test_failed_register_participant_is_rolled_back() {
+    let mut state = Stf::init_state();
+    set_phase(&mut state, CeremonyPhaseType::ASSIGNING);
+    let call = TrustedCall::ceremonies_register_participant(account(1), cid(), None);
+    assert_rolled_back(state, unsigned(call, 0));
+}
+
+pub fn test_failed_register_attestations_is_rolled_back() {
+    let mut state = Stf::init_state();
+    set_phase(&mut state, CeremonyPhaseType::REGISTERING);
+    let call = TrustedCall::ceremonies_register_attestations(account(1), alloc::vec![]);
+    assert_rolled_back(state, unsigned(call, 0));
+}
+
+pub fn test_failed_grant_reputation_is_rolled_back() {
+    let mut state = Stf::init_state();
+    state.execute_with(|| {
+        sp_io::storage::set(
+            &storage_value_key("EncointerScheduler", "CeremonyMaster"),
+            &account(2).encode(),
+        )
+    });
+    let call = TrustedCall::ceremonies_grant_reputation(account(1), cid(), account(3));
+    assert_rolled_back(state, unsigned(call, 0));
+}
+
+pub fn test_unsynced_role_is_missing_privileges() {
+    let state = Stf::init_state();
+    let call = TrustedCall::ceremonies_grant_reputation(account(1), cid(), account(3));
+    match assert_rolled_back(state, unsigned(call, 0)) {
+        StfError::MissingPrivileges(who) => assert_eq!(who, account(1)),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+pub fn test_failed_batch_is_rolled_back() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let call = TrustedCall::batch(
+        account(1),
+        alloc::vec![
+            TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(1)),
+            TrustedCall::balance_transfer(
+                account(1),
+                account(2),
+                cid(),
+                BalanceType::from_num(100)
+            ),
+            TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(1)),
+        ],
+    );
+    match assert_rolled_back(state, unsigned(call, 0)) {
+        StfError::BatchFailed(results) => assert_eq!(
+            results,
+            alloc::vec![
+                BatchItemResult::Ok,
+                BatchItemResult::Failed,
+                BatchItemResult::NotExecuted
+            ]
+        ),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+pub fn test_batch_reports_results() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let call = TrustedCall::batch(
+        account(1),
+        alloc::vec![
+            TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(1)),
+            TrustedCall::balance_transfer(account(1), account(3), cid(), BalanceType::from_num(1)),
+        ],
+    );
+    let results = Stf::execute(&mut state, unsigned(call, 0), &mut Vec::new()).unwrap();
+    assert_eq!(
+        results,
+        Some(alloc::vec![BatchItemResult::Ok, BatchItemResult::Ok])
+    );
+    let transfer =
+        TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(1));
+    let results = Stf::execute(&mut state, unsigned(transfer, 1), &mut Vec::new()).unwrap();
+    assert_eq!(results, None);
+}
+
+pub fn test_call_with_invalid_nonce_is_rolled_back() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let call =
+        TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(1));
+    assert_rolled_back(state, unsigned(call, 1));
+}
+
+pub fn test_failed_call_can_not_be_replayed() {
+    let mut state = Stf::init_state();
+    let call = unsigned(
+        TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(1)),
+        0,
+    );
+    Stf::execute(&mut state, call.clone(), &mut Vec::new()).unwrap_err();
+    // once the sender has funds, the published call must not move them
+    issue(&mut state, &account(1), 10);
+    match assert_rolled_back(state, call) {
+        StfError::InvalidNonce(0, 1) => (),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+pub fn test_encode_state_is_deterministic() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let encoded = encode_state(&state);
+    let decoded = crate::sgx::decode_state(&encoded).unwrap();
+    assert_eq!(decoded, state);
+    assert_eq!(encode_state(&decoded), encoded);
+}
+
+pub fn test_transfer_history_is_recorded_for_both_parties() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    for nonce in 0..3 {
+        let call =
+            TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(1));
+        Stf::execute(&mut state, unsigned(call, nonce), &mut Vec::new()).unwrap();
+    }
+    let history = |state: &mut State, who: AccountId, from: u32, limit: u32| {
+        let getter = Getter::trusted(
+            TrustedGetter::transfer_history(who, cid(), from, limit).sign(
+                &KeyPair::from(sr25519::Pair::from_seed(&[0u8; 32])),
+                &[0u8; 32],
+                &cid(),
+                0,
+                0,
+            ),
+        );
+        let encoded = Stf::get_state(state, getter).unwrap();
+        Vec::<TransferRecord>::decode(&mut encoded.as_slice()).unwrap()
+    };
+    let outgoing = history(&mut state, account(1), 0, 10);
+    assert_eq!(outgoing.len(), 3);
+    assert!(outgoing
+        .iter()
+        .all(|r| r.direction == TransferDirection::Outgoing && r.counterparty == account(2)));
+    let incoming = history(&mut state, account(2), 1, 10);
+    assert_eq!(incoming.len(), 2);
+    assert_eq!(incoming[0].direction, TransferDirection::Incoming);
+    assert_eq!(history(&mut state, account(2), 3, 10).len(), 0);
+}
+
+pub fn test_parameter_changes_are_paged() {
+    let mut state = Stf::init_state();
+    state.execute_with(|| {
+        sp_io::storage::set(&storage_value_key("Sudo", "Key"), &account(1).encode())
+    });
+    for nonce in 0..3 {
+        let call = TrustedCall::ceremonies_set_parameter(
+            account(1),
+            cid(),
+            CeremonyParameter::TimeTolerance(1000 + u64::from(nonce)),
+        );
+        Stf::execute(&mut state, unsigned(call, nonce), &mut Vec::new()).unwrap();
+    }
+    let changes = |state: &mut State, from: u32, limit: u32| {
+        let getter = Getter::public(PublicGetter::parameter_changes(cid(), from, limit));
+        let encoded = Stf::get_state(state, getter).unwrap();
+        Vec::<ParameterChange>::decode(&mut encoded.as_slice()).unwrap()
+    };
+    assert_eq!(changes(&mut state, 0, 10).len(), 3);
+    let page = changes(&mut state, 1, 1);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].parameter, CeremonyParameter::TimeTolerance(1001));
+    assert_eq!(page[0].changed_by, account(1));
+    assert_eq!(changes(&mut state, 3, 10).len(), 0);
+}
+
+pub fn test_transfer_memo_is_recorded() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let memo = b"invoice 42".to_vec();
+    let call = TrustedCall::balance_transfer_with_memo(
+        account(1),
+        account(2),
+        cid(),
+        BalanceType::from_num(1),
+        memo.clone(),
+    );
+    Stf::execute(&mut state, unsigned(call, 0), &mut Vec::new()).unwrap();
+    let getter = Getter::trusted(
+        TrustedGetter::transfer_history(account(2), cid(), 0, 10).sign(
+            &KeyPair::from(sr25519::Pair::from_seed(&[0u8; 32])),
+            &[0u8; 32],
+            &cid(),
+            0,
+            0,
+        ),
+    );
+    let encoded = Stf::get_state(&mut state, getter).unwrap();
+    let records = Vec::<TransferRecord>::decode(&mut encoded.as_slice()).unwrap();
+    assert_eq!(records[0].memo, Some(memo));
+}
+
+pub fn test_transfer_with_too_long_memo_is_rolled_back() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let call = TrustedCall::balance_transfer_with_memo(
+        account(1),
+        account(2),
+        cid(),
+        BalanceType::from_num(1),
+        alloc::vec![0u8; MAX_MEMO_LEN + 1],
+    );
+    match assert_rolled_back(state, unsigned(call, 0)) {
+        StfError::MemoTooLong(len) => assert_eq!(len as usize, MAX_MEMO_LEN + 1),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+fn balance(state: &mut State, who: &AccountId) -> BalanceType {
+    state.execute_with(|| encointer_balances::Module::<Runtime>::balance(cid(), who))
+}
+
+pub fn test_scheduled_transfer_is_executed_on_block() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let call = TrustedCall::schedule_transfer(
+        account(1),
+        account(2),
+        cid(),
+        BalanceType::from_num(1),
+        5,
+        2,
+        2,
+    );
+    Stf::execute(&mut state, unsigned(call, 0), &mut Vec::new()).unwrap();
+    for number in 1..10 {
+        Stf::update_block_number(&mut state, number);
+        Stf::execute_scheduled_transfers(&mut state, number);
+        let expected = match number {
+            0..=4 => 0,
+            5..=6 => 1,
+            _ => 2,
+        };
+        assert_eq!(
+            balance(&mut state, &account(2)),
+            BalanceType::from_num(expected)
+        );
+    }
+    // the deposit is returned once the order is completed
+    assert_eq!(balance(&mut state, &account(1)), BalanceType::from_num(8));
+    assert!(state.execute_with(
+        || sp_io::storage::get(&crate::sgx::scheduled_transfer_ids_key(&account(1))).is_none()
+    ));
+}
+
+pub fn test_scheduled_transfer_needs_funds() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 1);
+    let call = TrustedCall::schedule_transfer(
+        account(1),
+        account(2),
+        cid(),
+        BalanceType::from_num(2),
+        5,
+        2,
+        2,
+    );
+    match assert_rolled_back(state.clone(), unsigned(call, 0)) {
+        StfError::MissingFunds => (),
+        e => panic!("unexpected error {:?}", e),
+    }
+    // the deposit has to be covered too
+    let call = TrustedCall::schedule_transfer(
+        account(1),
+        account(2),
+        cid(),
+        BalanceType::from_num(1),
+        5,
+        2,
+        2,
+    );
+    match assert_rolled_back(state, unsigned(call, 0)) {
+        StfError::MissingFunds => (),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+pub fn test_cancelled_scheduled_transfer_is_not_executed() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let call = TrustedCall::schedule_transfer(
+        account(1),
+        account(2),
+        cid(),
+        BalanceType::from_num(1),
+        1,
+        1,
+        5,
+    );
+    Stf::execute(&mut state, unsigned(call, 0), &mut Vec::new()).unwrap();
+    // only the owner can cancel
+    let call = TrustedCall::cancel_scheduled_transfer(account(2), cid(), 0);
+    assert_rolled_back(state.clone(), unsigned(call, 0));
+    let call = TrustedCall::cancel_scheduled_transfer(account(1), cid(), 0);
+    Stf::execute(&mut state, unsigned(call, 1), &mut Vec::new()).unwrap();
+    Stf::update_block_number(&mut state, 1);
+    Stf::execute_scheduled_transfers(&mut state, 1);
+    assert_eq!(balance(&mut state, &account(2)), BalanceType::from_num(0));
+    assert_eq!(balance(&mut state, &account(1)), BalanceType::from_num(10));
+}
+
+pub fn test_poll_tallies_reputation_weighted_votes_after_close() {
+    let mut state = Stf::init_state();
+    let master = account(9);
+    state.execute_with(|| {
+        sp_io::storage::set(
+            &storage_value_key("EncointerScheduler", "CeremonyMaster"),
+            &master.encode(),
+        );
+        sp_io::storage::set(
+            &storage_value_key("EncointerScheduler", "CurrentCeremonyIndex"),
+            &2u32.encode(),
+        );
+    });
+    let grant = TrustedCall::ceremonies_grant_reputation(master.clone(), cid(), account(1));
+    Stf::execute(&mut state, unsigned(grant, 0), &mut Vec::new()).unwrap();
+
+    // only privileged accounts can open polls
+    let create = TrustedCall::polls_create(account(1), cid(), b"new market day".to_vec(), 2);
+    assert_rolled_back(state.clone(), unsigned(create, 0));
+    let create = TrustedCall::polls_create(master.clone(), cid(), b"new market day".to_vec(), 2);
+    Stf::execute(&mut state, unsigned(create, 1), &mut Vec::new()).unwrap();
+
+    // accounts without reputation can't vote
+    let vote = TrustedCall::polls_vote(account(2), cid(), 0, 0);
+    match assert_rolled_back(state.clone(), unsigned(vote, 0)) {
+        StfError::MissingPrivileges(who) => assert_eq!(who, account(2)),
+        e => panic!("unexpected error {:?}", e),
+    }
+    let vote = TrustedCall::polls_vote(account(1), cid(), 0, 1);
+    Stf::execute(&mut state, unsigned(vote, 0), &mut Vec::new()).unwrap();
+    // and can't vote twice
+    let vote = TrustedCall::polls_vote(account(1), cid(), 0, 0);
+    assert_rolled_back(state.clone(), unsigned(vote, 1));
+
+    let result = |state: &mut State| {
+        Stf::get_state(state, Getter::public(PublicGetter::poll_result(cid(), 0)))
+            .map(|v| PollResult::decode(&mut v.as_slice()).unwrap())
+    };
+    assert!(result(&mut state).is_none());
+    let close = TrustedCall::polls_close(master, cid(), 0);
+    Stf::execute(&mut state, unsigned(close, 2), &mut Vec::new()).unwrap();
+    let result = result(&mut state).unwrap();
+    assert_eq!(result.tallies, alloc::vec![0, 1]);
+    assert_eq!(result.voters, 1);
+}
+
+pub fn test_viewing_key_is_limited_to_its_scopes() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let viewer = account(5);
+    let call = TrustedCall::register_viewing_key(
+        account(1),
+        cid(),
+        viewer.clone(),
+        alloc::vec![ViewingScope::Balance],
+    );
+    Stf::execute(&mut state, unsigned(call, 0), &mut Vec::new()).unwrap();
+    // signatures are verified by the enclave before
+    let viewed = |state: &mut State, getter: TrustedGetter| {
+        let getter = TrustedGetterSigned::new(
+            TrustedGetter::viewed(viewer.clone(), Box::new(getter)),
+            0,
+            0,
+            MultiSignature::from(sr25519::Signature::from_raw([0u8; 64])),
+        );
+        Stf::get_state(state, Getter::trusted(getter))
+    };
+    let balance = viewed(&mut state, TrustedGetter::balance(account(1), cid())).unwrap();
+    assert_eq!(
+        BalanceType::decode(&mut balance.as_slice()).unwrap(),
+        BalanceType::from_num(10)
+    );
+    assert!(viewed(
+        &mut state,
+        TrustedGetter::transfer_history(account(1), cid(), 0, 10)
+    )
+    .is_none());
+    assert!(viewed(&mut state, TrustedGetter::balance(account(2), cid())).is_none());
+
+    let call = TrustedCall::revoke_viewing_key(account(1), cid(), viewer.clone());
+    Stf::execute(&mut state, unsigned(call, 1), &mut Vec::new()).unwrap();
+    assert!(viewed(&mut state, TrustedGetter::balance(account(1), cid())).is_none());
+}
+
+pub fn test_proxy_can_only_sign_permitted_calls_until_expiry() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+    let delegate = account(5);
+    let call = TrustedCall::add_proxy(
+        account(1),
+        cid(),
+        delegate.clone(),
+        alloc::vec![ProxyPermission::Transfer],
+        10,
+    );
+    Stf::execute(&mut state, unsigned(call, 0), &mut Vec::new()).unwrap();
+    let transfer =
+        TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(1));
+    let proxied =
+        |call: TrustedCall| TrustedCall::proxied(delegate.clone(), account(1), Box::new(call));
+
+    // the delegate's nonce is used
+    Stf::execute(
+        &mut state,
+        unsigned(proxied(transfer.clone()), 0),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(balance(&mut state, &account(2)), BalanceType::from_num(1));
+
+    let register = TrustedCall::ceremonies_register_participant(account(1), cid(), None);
+    match assert_rolled_back(state.clone(), unsigned(proxied(register), 1)) {
+        StfError::MissingPrivileges(who) => assert_eq!(who, delegate),
+        e => panic!("unexpected error {:?}", e),
+    }
+    // calls of other accounts can't be proxied
+    let foreign =
+        TrustedCall::balance_transfer(account(2), account(1), cid(), BalanceType::from_num(1));
+    assert_rolled_back(state.clone(), unsigned(proxied(foreign), 1));
+
+    Stf::update_block_number(&mut state, 11);
+    match assert_rolled_back(state, unsigned(proxied(transfer), 1)) {
+        StfError::MissingPrivileges(who) => assert_eq!(who, delegate),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+pub fn test_multisig_transfer_needs_threshold_approvals() {
+    let mut state = Stf::init_state();
+    let members = alloc::vec![account(1), account(2), account(3)];
+    let treasury = Multisig::new(members.clone(), 2).account();
+    let call = TrustedCall::multisig_create(account(1), cid(), members, 2);
+    Stf::execute(&mut state, unsigned(call, 0), &mut Vec::new()).unwrap();
+    issue(&mut state, &treasury, 10);
+
+    let transfer = TrustedCall::balance_transfer(
+        treasury.clone(),
+        account(4),
+        cid(),
+        BalanceType::from_num(3),
+    );
+    let approve = |member: AccountId| {
+        TrustedCall::multisig_approve(member, cid(), treasury.clone(), Box::new(transfer.clone()))
+    };
+    Stf::execute(
+        &mut state,
+        unsigned(approve(account(1)), 1),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(balance(&mut state, &account(4)), BalanceType::from_num(0));
+    // approvals count once per member
+    assert_rolled_back(state.clone(), unsigned(approve(account(1)), 2));
+    match assert_rolled_back(state.clone(), unsigned(approve(account(4)), 0)) {
+        StfError::MissingPrivileges(who) => assert_eq!(who, account(4)),
+        e => panic!("unexpected error {:?}", e),
+    }
+    Stf::execute(
+        &mut state,
+        unsigned(approve(account(2)), 0),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(balance(&mut state, &account(4)), BalanceType::from_num(3));
+}
+
+pub fn test_call_receipt_is_only_visible_to_signer() {
+    let mut state = Stf::init_state();
+    let call_hash = H256::from([1u8; 32]);
+    let receipt = CallReceipt {
+        signer: Some(account(1)),
+        result: Err(StfError::MissingFunds),
+        block_number: 3,
+        state_hash: H256::default(),
+    };
+    Stf::record_call_receipt(&mut state, call_hash, receipt.clone());
+    let undecodable_hash = H256::from([2u8; 32]);
+    let undecodable = CallReceipt {
+        signer: None,
+        result: Err(StfError::UndecodableCall),
+        ..receipt.clone()
+    };
+    Stf::record_call_receipt(&mut state, undecodable_hash, undecodable.clone());
+
+    let query = |state: &mut State, who: AccountId, call_hash: H256| {
+        let getter = TrustedGetterSigned::new(
+            TrustedGetter::call_receipt(who, call_hash),
+            0,
+            0,
+            MultiSignature::from(sr25519::Signature::from_raw([0u8; 64])),
+        );
+        Stf::get_state(state, Getter::trusted(getter))
+            .map(|v| CallReceipt::decode(&mut v.as_slice()).unwrap())
+    };
+    assert_eq!(query(&mut state, account(1), call_hash), Some(receipt));
+    assert_eq!(query(&mut state, account(2), call_hash), None);
+    assert_eq!(
+        query(&mut state, account(2), undecodable_hash),
+        Some(undecodable)
+    );
+    assert_eq!(query(&mut state, account(1), H256::from([3u8; 32])), None);
+}
+
+pub fn test_call_receipt_expires() {
+    let mut state = Stf::init_state();
+    let call_hash = H256::from([1u8; 32]);
+    let receipt = CallReceipt {
+        signer: None,
+        result: Err(StfError::BadSignature),
+        block_number: 3,
+        state_hash: H256::default(),
+    };
+    Stf::record_call_receipt(&mut state, call_hash, receipt);
+    let recorded = |state: &mut State| {
+        state.execute_with(|| sp_io::storage::get(&crate::sgx::call_receipt_key(&call_hash)))
+    };
+    Stf::prune_call_receipts(&mut state, 2 + CALL_RECEIPT_RETENTION_PERIOD);
+    assert!(recorded(&mut state).is_some());
+    Stf::prune_call_receipts(&mut state, 3 + CALL_RECEIPT_RETENTION_PERIOD);
+    assert!(recorded(&mut state).is_none());
+}
+
+pub fn test_events_of_call_are_stored_for_sender() {
+    let mut state = Stf::init_state();
+    // the runtime doesn't deposit events at genesis
+    Stf::update_block_number(&mut state, 1);
+    issue(&mut state, &account(1), 10);
+    let call =
+        TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(1));
+    Stf::execute(&mut state, unsigned(call, 0), &mut Vec::new()).unwrap();
+
+    let events = |state: &mut State, who: AccountId| {
+        let getter = TrustedGetterSigned::new(
+            TrustedGetter::events(who, cid(), 0, EVENTS_PAGE_SIZE),
+            0,
+            0,
+            MultiSignature::from(sr25519::Signature::from_raw([0u8; 64])),
+        );
+        let encoded = Stf::get_state(state, Getter::trusted(getter)).unwrap();
+        Vec::<StfEvent>::decode(&mut encoded.as_slice()).unwrap()
+    };
+    let sent = events(&mut state, account(1));
+    assert!(!sent.is_empty());
+    assert!(sent.iter().all(|e| e.block_number == 1));
+    assert!(events(&mut state, account(2)).is_empty());
+
+    // a failed call leaves no events behind
+    let call =
+        TrustedCall::balance_transfer(account(1), account(2), cid(), BalanceType::from_num(100));
+    Stf::execute(&mut state, unsigned(call, 1), &mut Vec::new()).unwrap_err();
+    assert_eq!(events(&mut state, account(1)), sent);
+}
+
+pub fn test_balance_proof_verifies_against_state_root() {
+    let mut state = Stf::init_state();
+    issue(&mut state, &account(1), 10);
+
+    let keys = proof_keys(&TrustedGetter::balance(account(1), cid()));
+    let proof = prove_state(&state, &keys);
+    assert_eq!(proof.state_root, state_root(&state));
+    // the key is where the runtime stores the balance
+    assert_eq!(proof.entries.len(), 1);
+    assert!(proof.value(&keys[0]).unwrap().is_some());
+    assert!(proof.verify());
+
+    // the balance of accounts without one is proven absent
+    let keys = proof_keys(&TrustedGetter::balance(account(2), cid()));
+    let absence = prove_state(&state, &keys);
+    assert!(absence.entries.is_empty());
+    assert_eq!(absence.value(&keys[0]), Some(None));
+    assert!(absence.verify());
+
+    // the proof doesn't hold for the root of a changed state
+    issue(&mut state, &account(2), 1);
+    let (key, value, merkle_proof) = &proof.entries[0];
+    assert!(!merkle_proof.verify(key, value, &state_root(&state)));
+}
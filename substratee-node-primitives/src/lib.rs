@@ -21,6 +21,58 @@ pub type SubstrateeConfirmCallFn = ([u8; 2], ShardIdentifier, H256, Vec<u8>);
 pub type ShieldFundsFn = ([u8; 2], Vec<u8>, u128, ShardIdentifier);
 pub type CallWorkerFn = ([u8; 2], Request);
 
+/// Whether the `ShieldFunds` extrinsic at `extrinsic_index` of a block has locked its funds in
+/// the registry, judged from the block's encoded `System::Events`. Extrinsics which failed on
+/// chain are included in the block as well, but without the registry's event.
+///
+/// Decoding the events takes the node's runtime, which changes with every upgrade. So the
+/// encoded record of `ShieldFunds(account_encrypted)` deposited in the extrinsic is looked up
+/// instead, leaving the module and event indices open. The only events carrying data of a
+/// caller's choice are the ones echoing the arguments of the block's other `ShieldFunds` and
+/// `CallWorker` extrinsics, given as (extrinsic index, encoded event data) in `echoed`.
+/// A record found within one of them doesn't count.
+pub fn funds_shielded(
+    events: &[u8],
+    extrinsic_index: u32,
+    account_encrypted: &[u8],
+    echoed: &[(u32, Vec<u8>)],
+) -> bool {
+    let forged: Vec<(usize, usize)> = echoed
+        .iter()
+        .filter(|(index, _)| *index != extrinsic_index)
+        .flat_map(|(index, data)| find_event_records(events, *index, data))
+        .collect();
+    find_event_records(events, extrinsic_index, &account_encrypted.encode())
+        .into_iter()
+        .any(|(start, end)| {
+            !forged
+                .iter()
+                .any(|(f_start, f_end)| *f_start <= start && end <= *f_end)
+        })
+}
+
+/// (start, end) of every encoded `EventRecord` in `events` which has been deposited in the
+/// extrinsic at `extrinsic_index`, carries `data` and has no topics, whatever the event is
+fn find_event_records(events: &[u8], extrinsic_index: u32, data: &[u8]) -> Vec<(usize, usize)> {
+    // `Phase::ApplyExtrinsic` is the first variant
+    let mut phase = 0u8.encode();
+    phase.extend(extrinsic_index.encode());
+    // the module and event indices take a byte each
+    let len = phase.len() + 2 + data.len() + 1;
+    if events.len() < len {
+        return Vec::new();
+    }
+    (0..=events.len() - len)
+        .filter(|start| {
+            let record = &events[*start..*start + len];
+            record.starts_with(&phase)
+                && &record[phase.len() + 2..len - 1] == data
+                && record[len - 1] == 0
+        })
+        .map(|start| (start, start + len))
+        .collect()
+}
+
 #[cfg(feature = "std")]
 pub mod calls {
     pub use my_node_runtime::{
@@ -75,6 +127,21 @@ pub mod calls {
         None
     }
 
+    /// the worker producing the sidechain of the workers running `mrenclave`, which is the first
+    /// one registered
+    pub fn get_sidechain_producer<P: Pair>(
+        api: &substrate_api_client::Api<P>,
+        mrenclave: &[u8; 32],
+    ) -> Option<Enclave<AccountId, Vec<u8>>>
+    where
+        MultiSignature: From<P::Signature>,
+    {
+        // the registry starts indexing its map at one
+        (1..=api.get_storage_value("SubstrateeRegistry", "EnclaveCount", None)?)
+            .filter_map(|n| get_worker_info(api, n))
+            .find(|worker| &worker.mr_enclave == mrenclave)
+    }
+
     pub fn get_latest_state<P: Pair>(
         api: &substrate_api_client::Api<P>,
         shard: &ShardIdentifier,
@@ -85,3 +152,70 @@ pub mod calls {
         api.get_storage_map("SubstrateeRegistry", "LatestIPFSHash", shard, None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frame_system::{EventRecord, Phase};
+    use my_node_runtime::{substratee_registry::RawEvent, Event};
+    use sp_runtime::DispatchError;
+
+    fn record(extrinsic_index: u32, event: Event) -> EventRecord<Event, H256> {
+        EventRecord {
+            phase: Phase::ApplyExtrinsic(extrinsic_index),
+            event,
+            topics: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn failed_shield_funds_extrinsic_is_not_credited() {
+        let account_encrypted = vec![1u8; 384];
+        let events = vec![
+            record(
+                1,
+                Event::substratee_registry(RawEvent::ShieldFunds(account_encrypted.clone())),
+            ),
+            record(
+                2,
+                Event::frame_system(frame_system::RawEvent::ExtrinsicFailed(
+                    DispatchError::BadOrigin,
+                    frame_support::weights::DispatchInfo::default(),
+                )),
+            ),
+        ]
+        .encode();
+        assert!(funds_shielded(&events, 1, &account_encrypted, &[]));
+        // the same account shielded in another extrinsic of the block doesn't count
+        assert!(!funds_shielded(&events, 2, &account_encrypted, &[]));
+        assert!(!funds_shielded(&events, 1, &[2u8; 384], &[]));
+    }
+
+    #[test]
+    fn shield_funds_record_can_not_be_forged() {
+        let account_encrypted = vec![1u8; 384];
+        // the record of a ShieldFunds event of extrinsic 2, hidden in the payload of extrinsic 1
+        let forged = record(
+            2,
+            Event::substratee_registry(RawEvent::ShieldFunds(account_encrypted.clone())),
+        )
+        .encode();
+        let events = vec![
+            record(
+                1,
+                Event::substratee_registry(RawEvent::ShieldFunds(forged.clone())),
+            ),
+            record(
+                2,
+                Event::frame_system(frame_system::RawEvent::ExtrinsicFailed(
+                    DispatchError::BadOrigin,
+                    frame_support::weights::DispatchInfo::default(),
+                )),
+            ),
+        ]
+        .encode();
+        let echoed = vec![(1, forged.encode()), (2, account_encrypted.encode())];
+        assert!(!funds_shielded(&events, 2, &account_encrypted, &echoed));
+        assert!(funds_shielded(&events, 1, &forged, &echoed));
+    }
+}
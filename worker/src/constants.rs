@@ -17,6 +17,7 @@
 
 pub static ENCRYPTED_STATE_FILE: &str = "state.bin";
 pub static SHARDS_PATH: &str = "./shards";
+pub static SIDECHAIN_PATH: &str = "./sidechain";
 pub static ENCLAVE_TOKEN: &str = "../bin/enclave.token";
 pub static ENCLAVE_FILE: &str = "../bin/enclave.signed.so";
 pub static SHIELDING_KEY_FILE: &str = "enclave-shielding-pubkey.json";
@@ -35,4 +36,13 @@ pub static RA_API_KEY_FILE: &str = "../bin/key.txt";
 // the maximum size of any extrinsic that the enclave will ever generate in B
 pub static EXTRINSIC_MAX_SIZE: usize = 4196;
 // the maximum size of a value that will be queried from the state in B
-pub static STATE_VALUE_MAX_SIZE: usize = 1024;
+// large enough for a signed getter result with a full meetup assignment or ceremony history
+pub static STATE_VALUE_MAX_SIZE: usize = substratee_stf::STATE_VALUE_MAX_SIZE;
+// the maximum size of the sidechain blocks produced at once in B
+// large enough for a block per shard holding all calls of a full operation pool
+pub static SIDECHAIN_BLOCKS_MAX_SIZE: usize = 65536;
+// the maximum size of the extrinsics the enclave composes at once in B. Calls which don't fit
+// are composed in the next round
+pub static COMPOSED_EXTRINSICS_MAX_SIZE: usize = 65536;
+// the worker asks the enclave for new sidechain blocks in this interval
+pub static SIDECHAIN_BLOCK_INTERVAL_MS: u64 = 300;
@@ -24,9 +24,12 @@ use sgx_crypto_helper::rsa3072::Rsa3072PubKey;
 use sgx_types::*;
 use sgx_urts::SgxEnclave;
 
-use crate::constants::{ENCLAVE_FILE, ENCLAVE_TOKEN, EXTRINSIC_MAX_SIZE, STATE_VALUE_MAX_SIZE};
+use crate::constants::{
+    COMPOSED_EXTRINSICS_MAX_SIZE, ENCLAVE_FILE, ENCLAVE_TOKEN, EXTRINSIC_MAX_SIZE,
+    SIDECHAIN_BLOCKS_MAX_SIZE, STATE_VALUE_MAX_SIZE,
+};
 use codec::{Decode, Encode};
-use my_node_runtime::{Header, SignedBlock};
+use my_node_runtime::{substratee_registry::ShardIdentifier, Header, SignedBlock};
 use sp_core::ed25519;
 use sp_finality_grandpa::VersionedAuthorityList;
 
@@ -44,6 +47,15 @@ extern "C" {
         value_size: u32,
     ) -> sgx_status_t;
 
+    fn submit_trusted_call(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        cyphertext: *const u8,
+        cyphertext_size: u32,
+        shard: *const u8,
+        shard_size: u32,
+    ) -> sgx_status_t;
+
     fn init_chain_relay(
         eid: sgx_enclave_id_t,
         retval: *mut sgx_status_t,
@@ -62,6 +74,26 @@ extern "C" {
         retval: *mut sgx_status_t,
         blocks: *const u8,
         blocks_size: usize,
+    ) -> sgx_status_t;
+
+    fn produce_sidechain_blocks(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        blocks: *mut u8,
+        blocks_size: usize,
+        pending_extrinsics: *mut u32,
+    ) -> sgx_status_t;
+
+    fn confirm_sidechain_blocks(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        confirmed: *const u8,
+        confirmed_size: usize,
+    ) -> sgx_status_t;
+
+    fn compose_extrinsics(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
         nonce: *const u32,
         unchecked_extrinsic: *mut u8,
         unchecked_extrinsic_size: usize,
@@ -231,27 +263,89 @@ pub fn enclave_init_chain_relay(
     Ok(latest)
 }
 
-pub fn enclave_sync_chain_relay(
+/// imports the blocks into the chain relay and executes their relevant extrinsics. The calls
+/// the enclave makes in return are sent with `enclave_compose_extrinsics`
+pub fn enclave_sync_chain_relay(eid: sgx_enclave_id_t, blocks: Vec<SignedBlock>) -> SgxResult<()> {
+    let mut status = sgx_status_t::SGX_SUCCESS;
+
+    let result = unsafe {
+        blocks.using_encoded(|b| sync_chain_relay(eid, &mut status, b.as_ptr(), b.len()))
+    };
+
+    if status != sgx_status_t::SGX_SUCCESS {
+        return Err(status);
+    }
+    if result != sgx_status_t::SGX_SUCCESS {
+        return Err(result);
+    }
+
+    Ok(())
+}
+
+/// execute the pending calls in new sidechain blocks. Returns the encoded signed blocks and the
+/// number of calls waiting to be sent to the chain
+pub fn enclave_produce_sidechain_blocks(eid: sgx_enclave_id_t) -> SgxResult<(Vec<u8>, u32)> {
+    let mut status = sgx_status_t::SGX_SUCCESS;
+
+    let mut blocks: Vec<u8> = vec![0u8; SIDECHAIN_BLOCKS_MAX_SIZE];
+    let mut pending_extrinsics = 0u32;
+
+    let result = unsafe {
+        produce_sidechain_blocks(
+            eid,
+            &mut status,
+            blocks.as_mut_ptr(),
+            SIDECHAIN_BLOCKS_MAX_SIZE,
+            &mut pending_extrinsics,
+        )
+    };
+
+    if status != sgx_status_t::SGX_SUCCESS {
+        return Err(status);
+    }
+    if result != sgx_status_t::SGX_SUCCESS {
+        return Err(result);
+    }
+
+    Ok((blocks, pending_extrinsics))
+}
+
+/// tell the enclave that the sidechain blocks `(shard, block number)` have been stored
+pub fn enclave_confirm_sidechain_blocks(
     eid: sgx_enclave_id_t,
-    blocks: Vec<SignedBlock>,
-    tee_nonce: u32,
-) -> SgxResult<Vec<u8>> {
+    confirmed: Vec<(ShardIdentifier, u64)>,
+) -> SgxResult<()> {
     let mut status = sgx_status_t::SGX_SUCCESS;
 
-    let mut unchecked_extrinsics: Vec<u8> = vec![0u8; EXTRINSIC_MAX_SIZE];
+    let result = unsafe {
+        confirmed.using_encoded(|c| confirm_sidechain_blocks(eid, &mut status, c.as_ptr(), c.len()))
+    };
+
+    if status != sgx_status_t::SGX_SUCCESS {
+        return Err(status);
+    }
+    if result != sgx_status_t::SGX_SUCCESS {
+        return Err(result);
+    }
+
+    Ok(())
+}
+
+/// the encoded extrinsics for the calls the enclave wants to send, as many as fit into
+/// `COMPOSED_EXTRINSICS_MAX_SIZE`. The others are returned by the next call
+pub fn enclave_compose_extrinsics(eid: sgx_enclave_id_t, tee_nonce: u32) -> SgxResult<Vec<u8>> {
+    let mut status = sgx_status_t::SGX_SUCCESS;
+
+    let mut unchecked_extrinsics: Vec<u8> = vec![0u8; COMPOSED_EXTRINSICS_MAX_SIZE];
 
     let result = unsafe {
-        blocks.using_encoded(|b| {
-            sync_chain_relay(
-                eid,
-                &mut status,
-                b.as_ptr(),
-                b.len(),
-                &tee_nonce,
-                unchecked_extrinsics.as_mut_ptr(),
-                EXTRINSIC_MAX_SIZE,
-            )
-        })
+        compose_extrinsics(
+            eid,
+            &mut status,
+            &tee_nonce,
+            unchecked_extrinsics.as_mut_ptr(),
+            COMPOSED_EXTRINSICS_MAX_SIZE,
+        )
     };
 
     if status != sgx_status_t::SGX_SUCCESS {
@@ -332,6 +426,34 @@ pub fn enclave_query_state(
     Ok(value)
 }
 
+/// add an encrypted `TrustedCallSigned` to the enclave's operation pool. It is executed in
+/// one of the next sidechain blocks
+pub fn enclave_submit_trusted_call(
+    eid: sgx_enclave_id_t,
+    cyphertext: Vec<u8>,
+    shard: Vec<u8>,
+) -> SgxResult<()> {
+    let mut status = sgx_status_t::SGX_SUCCESS;
+    let result = unsafe {
+        submit_trusted_call(
+            eid,
+            &mut status,
+            cyphertext.as_ptr(),
+            cyphertext.len() as u32,
+            shard.as_ptr(),
+            shard.len() as u32,
+        )
+    };
+
+    if status != sgx_status_t::SGX_SUCCESS {
+        return Err(status);
+    }
+    if result != sgx_status_t::SGX_SUCCESS {
+        return Err(result);
+    }
+    Ok(())
+}
+
 pub fn enclave_mrenclave(eid: sgx_enclave_id_t) -> SgxResult<[u8; 32]> {
     let mut m = [0u8; 32];
     let mut status = sgx_status_t::SGX_SUCCESS;
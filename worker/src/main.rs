@@ -21,8 +21,9 @@ use std::path::Path;
 use std::slice;
 use std::str;
 use std::sync::{
+    atomic::{AtomicBool, Ordering},
     mpsc::{channel, Sender},
-    Mutex,
+    Arc, Mutex,
 };
 use std::thread;
 
@@ -45,19 +46,27 @@ use sp_core::{
 use sp_keyring::AccountKeyring;
 use substrate_api_client::{utils::hexstr_to_vec, Api, GenericAddress, XtStatus};
 
-use crate::enclave::api::{enclave_init_chain_relay, enclave_sync_chain_relay};
+use crate::constants::SIDECHAIN_BLOCK_INTERVAL_MS;
+use crate::enclave::api::{
+    enclave_compose_extrinsics, enclave_confirm_sidechain_blocks, enclave_init_chain_relay,
+    enclave_produce_sidechain_blocks, enclave_sync_chain_relay,
+};
 use enclave::api::{
     enclave_dump_ra, enclave_init, enclave_mrenclave, enclave_perform_ra, enclave_shielding_key,
     enclave_signing_key,
 };
 use enclave::tls_ra::{enclave_request_key_provisioning, enclave_run_key_provisioning_server};
 use sp_finality_grandpa::{AuthorityList, VersionedAuthorityList, GRANDPA_AUTHORITIES_KEY};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use substratee_node_primitives::calls::get_sidechain_producer;
+use substratee_stf::SignedSidechainBlock;
+use substratee_worker_api::Api as WorkerApi;
 use ws_server::start_ws_server;
 
 mod constants;
 mod enclave;
 mod ipfs;
+mod sidechain;
 mod tests;
 mod ws_server;
 
@@ -318,6 +327,25 @@ fn worker(
         println!("[<] Extrinsic got finalized. Hash: {:?}\n", tx_hash);
     }
 
+    // The workers of an MRENCLAVE don't exchange sidechain blocks, so a directly submitted call
+    // only changes the state of the worker which executes it. To keep them from diverging, only
+    // the first registered worker executes calls and produces the sidechain. The others relay
+    // client requests to it and leave their own state untouched, so they can't take over
+    let producer = match get_sidechain_producer(&api, &mrenclave) {
+        Some(p) if p.pubkey != tee_accountid => {
+            let url = String::from_utf8_lossy(&p.url).to_string();
+            println!("[!] sidechain is produced by {}, forwarding requests", url);
+            Some(WorkerApi::new(url))
+        }
+        _ => None,
+    };
+    if let Some(producer) = producer {
+        while let Ok(req) = ws_receiver.recv() {
+            ws_server::handle_request(req, eid, mu_ra_port.to_string(), Some(&producer)).unwrap()
+        }
+        return;
+    }
+
     let mut latest_head = init_chain_relay(eid, &api);
     println!("*** [+] Finished syncing chain relay\n");
 
@@ -343,7 +371,12 @@ fn worker(
 
     println!("[+] Subscribed to events. waiting...");
 
+    let sending = Arc::new(AtomicBool::new(false));
+    let xt_sender = spawn_extrinsic_sender(api.clone(), sending.clone());
+
     let timeout = Duration::from_millis(10);
+    let sidechain_block_interval = Duration::from_millis(SIDECHAIN_BLOCK_INTERVAL_MS);
+    let mut last_sidechain_block = Instant::now();
     loop {
         if let Ok(msg) = receiver.recv_timeout(timeout) {
             if let Ok(events) = parse_events(msg.clone()) {
@@ -353,7 +386,11 @@ fn worker(
             }
         }
         if let Ok(req) = ws_receiver.recv_timeout(timeout) {
-            ws_server::handle_request(req, eid, mu_ra_port.to_string()).unwrap()
+            ws_server::handle_request(req, eid, mu_ra_port.to_string(), None).unwrap()
+        }
+        if last_sidechain_block.elapsed() >= sidechain_block_interval {
+            produce_sidechain_blocks(eid, &api, &xt_sender, &sending);
+            last_sidechain_block = Instant::now();
         }
     }
 }
@@ -550,30 +587,11 @@ pub fn sync_chain_relay(
     }
     blocks_to_sync.reverse();
 
-    let tee_accountid = enclave_account(eid);
-
-    // only feed BLOCK_SYNC_BATCH_SIZE blocks at a time into the enclave to save enclave state regularly
+    // only feed BLOCK_SYNC_BATCH_SIZE blocks at a time into the enclave to save enclave state regularly.
+    // The extrinsics the enclave wants to send in return are sent with the next sidechain blocks
     let mut i = blocks_to_sync[0].block.header.number as usize;
     for chunk in blocks_to_sync.chunks(BLOCK_SYNC_BATCH_SIZE as usize) {
-        let tee_nonce = get_nonce(&api, &tee_accountid);
-        let xts = enclave_sync_chain_relay(eid, chunk.to_vec(), tee_nonce).unwrap();
-        let extrinsics: Vec<Vec<u8>> = Decode::decode(&mut xts.as_slice()).unwrap();
-
-        if !extrinsics.is_empty() {
-            println!(
-                "Sync chain relay: Enclave wants to send {} extrinsics",
-                extrinsics.len()
-            );
-            for xt in extrinsics.into_iter() {
-                api.send_extrinsic(hex_encode(xt), XtStatus::Ready).unwrap();
-            }
-            // await next block to avoid #37
-            let (events_in, events_out) = channel();
-            api.subscribe_events(events_in);
-            let _ = events_out.recv().unwrap();
-            let _ = events_out.recv().unwrap();
-            // FIXME: we should unsubscribe here or the thread will throw a SendError because the channel is destroyed
-        }
+        enclave_sync_chain_relay(eid, chunk.to_vec()).unwrap();
 
         i += chunk.len();
         println!(
@@ -586,6 +604,95 @@ pub fn sync_chain_relay(
     curr_head.block.header
 }
 
+/// executes the calls submitted to the enclave directly in new sidechain blocks and stores the
+/// blocks. The enclave hands out a block again until it has been stored. The calls the enclave
+/// wants to send to the chain are composed once the previous extrinsics have been sent
+pub fn produce_sidechain_blocks(
+    eid: sgx_enclave_id_t,
+    api: &Api<sr25519::Pair>,
+    xt_sender: &Sender<Vec<Vec<u8>>>,
+    sending: &AtomicBool,
+) {
+    let (blocks, pending_extrinsics) = match enclave_produce_sidechain_blocks(eid) {
+        Ok(res) => res,
+        Err(e) => {
+            error!("producing sidechain blocks failed: {:?}", e);
+            return;
+        }
+    };
+    let blocks: Vec<SignedSidechainBlock> = Decode::decode(&mut blocks.as_slice()).unwrap();
+    let mut stored = Vec::new();
+    for block in blocks.iter() {
+        info!(
+            "sidechain block {} of shard {} with {} calls",
+            block.block.block_number,
+            block.block.shard.encode().to_base58(),
+            block.block.call_hashes.len()
+        );
+        match sidechain::store(block) {
+            Ok(()) => stored.push((block.block.shard, block.block.block_number)),
+            Err(e) => error!("could not store sidechain block: {}", e),
+        }
+    }
+    if !stored.is_empty() {
+        if let Err(e) = enclave_confirm_sidechain_blocks(eid, stored) {
+            error!("confirming sidechain blocks failed: {:?}", e);
+        }
+    }
+
+    // the nonce is only up to date once the previous extrinsics have been included
+    if pending_extrinsics == 0 || sending.load(Ordering::SeqCst) {
+        return;
+    }
+    let tee_nonce = get_nonce(api, &enclave_account(eid));
+    let xts = match enclave_compose_extrinsics(eid, tee_nonce) {
+        Ok(xts) => xts,
+        Err(e) => {
+            error!("composing extrinsics failed: {:?}", e);
+            return;
+        }
+    };
+    let extrinsics: Vec<Vec<u8>> = Decode::decode(&mut xts.as_slice()).unwrap();
+    if !extrinsics.is_empty() {
+        println!("Enclave wants to send {} extrinsics", extrinsics.len());
+        sending.store(true, Ordering::SeqCst);
+        xt_sender.send(extrinsics).unwrap();
+    }
+}
+
+/// sends the extrinsics of the enclave on its own thread, so the main loop keeps serving
+/// requests meanwhile. `sending` is cleared once a batch has been sent
+fn spawn_extrinsic_sender(
+    api: Api<sr25519::Pair>,
+    sending: Arc<AtomicBool>,
+) -> Sender<Vec<Vec<u8>>> {
+    let (sender, receiver) = channel::<Vec<Vec<u8>>>();
+    thread::Builder::new()
+        .name("extrinsic_sender".to_owned())
+        .spawn(move || {
+            for extrinsics in receiver.iter() {
+                send_extrinsics(&api, extrinsics);
+                sending.store(false, Ordering::SeqCst);
+            }
+        })
+        .unwrap();
+    sender
+}
+
+fn send_extrinsics(api: &Api<sr25519::Pair>, extrinsics: Vec<Vec<u8>>) {
+    for xt in extrinsics.into_iter() {
+        if let Err(e) = api.send_extrinsic(hex_encode(xt), XtStatus::Ready) {
+            error!("sending extrinsic failed: {:?}", e);
+        }
+    }
+    // await next block to avoid #37
+    let (events_in, events_out) = channel();
+    api.subscribe_events(events_in);
+    let _ = events_out.recv().unwrap();
+    let _ = events_out.recv().unwrap();
+    // FIXME: we should unsubscribe here or the thread will throw a SendError because the channel is destroyed
+}
+
 fn hex_encode(data: Vec<u8>) -> String {
     let mut hex_str = hex::encode(data);
     hex_str.insert_str(0, "0x");
@@ -0,0 +1,66 @@
+/*
+    Copyright 2019 Supercomputing Systems AG
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+
+*/
+
+//! Sidechain blocks produced by the enclave. They are stored per shard at
+//! `<SIDECHAIN_PATH>/<shard>/<block number>.bin` and served through the worker api.
+
+use std::fs;
+use std::io::Result;
+use std::path::PathBuf;
+
+use base58::ToBase58;
+use codec::{Decode, Encode};
+use log::*;
+use substratee_stf::{ShardIdentifier, SignedSidechainBlock};
+
+use crate::constants::SIDECHAIN_PATH;
+
+fn shard_path(shard: &ShardIdentifier) -> PathBuf {
+    PathBuf::from(SIDECHAIN_PATH).join(shard.encode().to_base58())
+}
+
+pub fn store(block: &SignedSidechainBlock) -> Result<()> {
+    let dir = shard_path(&block.block.shard);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.bin", block.block.block_number));
+    debug!("storing sidechain block at {:?}", path);
+    fs::write(path, block.encode())
+}
+
+/// the block of `shard` with `block_number`, or its latest block if no number is given
+pub fn load(shard: &ShardIdentifier, block_number: Option<u64>) -> Option<SignedSidechainBlock> {
+    let number = match block_number {
+        Some(n) => n,
+        None => latest_block_number(shard)?,
+    };
+    let encoded = fs::read(shard_path(shard).join(format!("{}.bin", number))).ok()?;
+    SignedSidechainBlock::decode(&mut encoded.as_slice()).ok()
+}
+
+fn latest_block_number(shard: &ShardIdentifier) -> Option<u64> {
+    fs::read_dir(shard_path(shard))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            entry
+                .path()
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+        })
+        .max()
+}
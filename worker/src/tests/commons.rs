@@ -30,7 +30,9 @@ use std::{fs, str};
 use crate::enclave::api::*;
 use crate::{enclave_account, ensure_account_has_funds};
 use substrate_api_client::Api;
-use substratee_stf::{ShardIdentifier, TrustedGetter, TrustedGetterSigned};
+use substratee_stf::{
+    BalanceType, KeyPair, ShardIdentifier, TrustedCall, TrustedGetter, TrustedGetterSigned,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Message {
@@ -45,9 +47,20 @@ pub fn encrypted_set_balance(_eid: sgx_enclave_id_t, _who: AccountKeyring, _nonc
     vec![]
 }
 
-pub fn encrypted_unshield(_eid: sgx_enclave_id_t, _who: AccountKeyring, _nonce: u32) -> Vec<u8> {
-    error!("not implemented in encointer");
-    vec![]
+/// `nonce` is the trusted nonce of `who` in the default shard
+pub fn encrypted_unshield(eid: sgx_enclave_id_t, who: AccountKeyring, nonce: u32) -> Vec<u8> {
+    let shard = ShardIdentifier::default();
+    let mrenclave = enclave_mrenclave(eid).unwrap();
+    // less than what has been shielded in shield_funds_workds
+    let amount = BalanceType::from_num(40);
+    let call = TrustedCall::unshield(who.to_account_id(), who.to_account_id(), shard, amount).sign(
+        &KeyPair::from(who.pair()),
+        nonce,
+        &mrenclave,
+        &shard,
+    );
+    let rsa_pubkey: Rsa3072PubKey = enclave_shielding_key(eid).unwrap();
+    encrypt_payload(rsa_pubkey, call.encode())
 }
 
 pub fn encrypt_payload(rsa_pubkey: Rsa3072PubKey, payload: Vec<u8>) -> Vec<u8> {
@@ -58,9 +71,19 @@ pub fn encrypt_payload(rsa_pubkey: Rsa3072PubKey, payload: Vec<u8>) -> Vec<u8> {
     payload_encrypted
 }
 
-pub fn test_trusted_getter_signed(who: AccountKeyring) -> TrustedGetterSigned {
-    let getter = TrustedGetter::balance(who.public(), Default::default());
-    getter.sign(&who.pair())
+pub fn test_trusted_getter_signed(
+    who: AccountKeyring,
+    mrenclave: &[u8; 32],
+    shard: &ShardIdentifier,
+) -> TrustedGetterSigned {
+    let getter = TrustedGetter::balance(who.to_account_id(), *shard);
+    getter.sign(
+        &KeyPair::from(who.pair()),
+        mrenclave,
+        shard,
+        0,
+        u32::max_value(),
+    )
 }
 
 pub fn encrypted_alice(eid: sgx_enclave_id_t) -> Vec<u8> {
@@ -15,9 +15,11 @@
 
 */
 
-use crate::enclave::api::enclave_query_state;
+use crate::enclave::api::{
+    enclave_mrenclave, enclave_query_state, enclave_shielding_key, enclave_submit_trusted_call,
+};
 use crate::init_shard;
-use crate::tests::commons::test_trusted_getter_signed;
+use crate::tests::commons::{encrypt_payload, encrypted_unshield, test_trusted_getter_signed};
 use codec::Encode;
 use sp_keyring::AccountKeyring;
 
@@ -26,9 +28,23 @@ use sp_core::hash::H256;
 
 pub fn get_state_works(eid: sgx_enclave_id_t) {
     let alice = AccountKeyring::Alice;
-    let trusted_getter_signed = test_trusted_getter_signed(alice).encode();
     let shard = H256::default();
+    let mrenclave = enclave_mrenclave(eid).unwrap();
+    let trusted_getter_signed = test_trusted_getter_signed(alice, &mrenclave, &shard).encode();
     init_shard(&shard);
     let res = enclave_query_state(eid, trusted_getter_signed, shard.encode()).unwrap();
     println!("get_state returned {:?}", res);
 }
+
+pub fn submit_trusted_call_works(eid: sgx_enclave_id_t) {
+    let shard = H256::default();
+    init_shard(&shard);
+    // not alice, the queued call must not interfere with the integration tests
+    let call_encrypted = encrypted_unshield(eid, AccountKeyring::Bob, 0);
+    enclave_submit_trusted_call(eid, call_encrypted, shard.encode()).unwrap();
+
+    // calls which can't be decoded are rejected right away
+    let rsa_pubkey = enclave_shielding_key(eid).unwrap();
+    let garbage = encrypt_payload(rsa_pubkey, vec![1u8; 8]);
+    assert!(enclave_submit_trusted_call(eid, garbage, shard.encode()).is_err());
+}
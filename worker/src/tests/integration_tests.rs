@@ -31,6 +31,7 @@ use std::thread::sleep;
 use std::time::Duration;
 use substrate_api_client::{compose_extrinsic, extrinsic::xt_primitives::UncheckedExtrinsicV4};
 use substratee_node_primitives::{CallWorkerFn, Request, ShieldFundsFn};
+use substratee_stf::{balance_to_native, BalanceType};
 
 pub fn perform_ra_works(eid: sgx_enclave_id_t, port: &str) {
     // start the substrate-api-client to communicate with the node
@@ -80,9 +81,10 @@ pub fn forward_encrypted_unshield_works(
     port: &str,
     last_synced_head: Header,
 ) -> Header {
-    let (api, nonce, shard) = setup(eid, Some(AccountKeyring::Alice), port);
+    let (api, _nonce, shard) = setup(eid, Some(AccountKeyring::Alice), port);
     let req = Request {
-        cyphertext: encrypted_unshield(eid, AccountKeyring::Alice, nonce.unwrap()),
+        // first trusted call of alice in this shard
+        cyphertext: encrypted_unshield(eid, AccountKeyring::Alice, 0),
         shard,
     };
 
@@ -111,7 +113,7 @@ pub fn shield_funds_workds(eid: sgx_enclave_id_t, port: &str, last_synced_head:
         "SubstrateeRegistry",
         "shield_funds",
         encrypted_alice(eid),
-        444u128,
+        balance_to_native(BalanceType::from_num(444)).unwrap(),
         shard
     );
     let tx_hash = api
@@ -41,6 +41,8 @@ pub fn run_enclave_tests(matches: &ArgMatches, port: &str) {
         println!("Running ecall Tests");
         println!("  testing get_state()");
         get_state_works(eid);
+        println!("  testing submit_trusted_call()");
+        submit_trusted_call_works(eid);
         println!("[+] Ecall tests ended!");
     }
 
@@ -25,9 +25,13 @@ use log::*;
 use std::sync::mpsc::Sender as MpscSender;
 use substratee_stf::{Getter, ShardIdentifier};
 use substratee_worker_api::requests::ClientRequest;
+use substratee_worker_api::Api as WorkerApi;
 use ws::{listen, CloseCode, Handler, Message, Result, Sender};
 
-use crate::enclave::api::{enclave_query_state, enclave_shielding_key};
+use crate::enclave::api::{
+    enclave_query_state, enclave_shielding_key, enclave_submit_trusted_call,
+};
+use crate::sidechain;
 
 #[derive(Clone, Debug)]
 pub struct WsServerRequest {
@@ -85,21 +89,42 @@ pub fn start_ws_server(addr: String, worker: MpscSender<WsServerRequest>) {
     });
 }
 
+/// answers a client request. A worker which doesn't produce the sidechain has no up to date
+/// state, so it forwards everything about the state to the `producer`
 pub fn handle_request(
     req: WsServerRequest,
     eid: sgx_enclave_id_t,
     mu_ra_port: String,
+    producer: Option<&WorkerApi>,
 ) -> Result<()> {
     info!("Got message '{:?}'. ", req.request);
-    let answer = match req.request {
-        ClientRequest::PubKeyWorker => get_pubkey(eid),
-        ClientRequest::MuRaPortWorker => Message::text(mu_ra_port),
-        ClientRequest::StfState(getter, shard) => get_stf_state(eid, getter, shard),
+    let answer = match (req.request, producer) {
+        (ClientRequest::PubKeyWorker, _) => get_pubkey(eid),
+        (ClientRequest::MuRaPortWorker, _) => Message::text(mu_ra_port),
+        (request, Some(producer)) => forward(producer, request),
+        (ClientRequest::StfState(getter, shard), None) => get_stf_state(eid, getter, shard),
+        (ClientRequest::SubmitTrustedCall(shard, cyphertext), None) => {
+            submit_trusted_call(eid, shard, cyphertext)
+        }
+        (ClientRequest::SidechainBlock(shard, block_number), None) => {
+            get_sidechain_block(shard, block_number)
+        }
     };
 
     req.client.send(answer)
 }
 
+fn forward(producer: &WorkerApi, request: ClientRequest) -> Message {
+    debug!("Forward request to the sidechain producer");
+    match producer.forward(request) {
+        Ok(answer) => Message::text(answer),
+        Err(()) => {
+            error!("forwarding request to the sidechain producer failed");
+            Message::text("rejected: sidechain producer unavailable")
+        }
+    }
+}
+
 fn get_stf_state(eid: sgx_enclave_id_t, getter: Getter, shard: ShardIdentifier) -> Message {
     debug!("Query state");
     let value = match enclave_query_state(eid, getter.encode(), shard.encode()) {
@@ -114,6 +139,26 @@ fn get_stf_state(eid: sgx_enclave_id_t, getter: Getter, shard: ShardIdentifier)
     Message::text(hex::encode(value.encode()))
 }
 
+fn submit_trusted_call(
+    eid: sgx_enclave_id_t,
+    shard: ShardIdentifier,
+    cyphertext: Vec<u8>,
+) -> Message {
+    debug!("Submit trusted call");
+    match enclave_submit_trusted_call(eid, cyphertext, shard.encode()) {
+        Ok(()) => Message::text("queued"),
+        Err(e) => {
+            error!("submitting trusted call failed: {:?}", e);
+            Message::text(format!("rejected: {:?}", e))
+        }
+    }
+}
+
+fn get_sidechain_block(shard: ShardIdentifier, block_number: Option<u64>) -> Message {
+    debug!("Get sidechain block {:?}", block_number);
+    Message::text(hex::encode(sidechain::load(&shard, block_number).encode()))
+}
+
 fn get_pubkey(eid: sgx_enclave_id_t) -> Message {
     let rsa_pubkey = enclave_shielding_key(eid).unwrap();
     debug!("RSA pubkey {:?}\n", rsa_pubkey);
@@ -22,11 +22,14 @@ use sgx_crypto_helper::rsa3072::Rsa3072PubKey;
 
 use codec::Decode;
 use log::*;
+use sp_core::ed25519;
 use ws::connect;
 
 use client::WsClient;
 use requests::*;
-use substratee_stf::{Getter, ShardIdentifier};
+use substratee_stf::{
+    BlockNumber, Getter, ShardIdentifier, SignedSidechainBlock, StateProof, TrustedReturnValue,
+};
 
 pub mod client;
 pub mod requests;
@@ -54,8 +57,50 @@ impl Api {
         Ok(rsa_pubkey)
     }
 
-    pub fn get_stf_state(&self, getter: Getter, shard: &ShardIdentifier) -> Result<Vec<u8>, ()> {
-        let req = ClientRequest::StfState(getter, shard.to_owned());
+    /// query the state of `shard` and verify that the result has been signed by one of the
+    /// `enclave_signers`, which should be taken from the on-chain enclave registry.
+    pub fn get_stf_state(
+        &self,
+        getter: Getter,
+        shard: &ShardIdentifier,
+        enclave_signers: &[ed25519::Public],
+    ) -> Result<Option<Vec<u8>>, ()> {
+        // can be None if storage doesn't exist
+        self.get_return_value(getter, shard, enclave_signers)
+            .map(|return_value| return_value.value)
+    }
+
+    /// like `get_stf_state`, for a `TrustedGetter::with_proof`. Returns the block the result
+    /// has been signed at and the proof, which is checked against its state root. The caller
+    /// must compare that root to the state hash committed on chain and derive the value from
+    /// the proven entries
+    pub fn get_proven_stf_state(
+        &self,
+        getter: Getter,
+        shard: &ShardIdentifier,
+        enclave_signers: &[ed25519::Public],
+    ) -> Result<(BlockNumber, StateProof), ()> {
+        let return_value = self.get_return_value(getter, shard, enclave_signers)?;
+        match return_value.proof {
+            Some(proof) if proof.verify() => Ok((return_value.block_number, proof)),
+            Some(_) => {
+                error!("getter result has an invalid state proof");
+                Err(())
+            }
+            None => {
+                error!("getter result has no state proof");
+                Err(())
+            }
+        }
+    }
+
+    fn get_return_value(
+        &self,
+        getter: Getter,
+        shard: &ShardIdentifier,
+        enclave_signers: &[ed25519::Public],
+    ) -> Result<TrustedReturnValue, ()> {
+        let req = ClientRequest::StfState(getter.clone(), shard.to_owned());
         match Self::get(&self, req) {
             Ok(res) => {
                 let value_slice = if let Ok(v) = hex::decode(&res) {
@@ -68,15 +113,89 @@ impl Api {
                     return Err(());
                 };
                 let value: Option<Vec<u8>> = Decode::decode(&mut &value_slice[..]).unwrap();
-                match value {
-                    Some(val) => Ok(val), // val is still an encoded option! can be None.encode() if storage doesn't exist
-                    None => Err(()),      // there must've been an SgxResult::Err inside enclave
+                let val = match value {
+                    Some(val) => val,
+                    None => return Err(()), // there must've been an SgxResult::Err inside enclave
+                };
+                // val is whitespace padded. decoding strips the padding
+                let return_value = match TrustedReturnValue::decode(&mut val.as_slice()) {
+                    Ok(rv) => rv,
+                    Err(_) => {
+                        error!("worker api returned a value that can't be decoded");
+                        return Err(());
+                    }
+                };
+                if !enclave_signers
+                    .iter()
+                    .any(|signer| return_value.verify(signer, shard, &getter))
+                {
+                    error!("getter result is not signed by a registered enclave");
+                    return Err(());
                 }
+                debug!(
+                    "verified getter result at block {} ({:?})",
+                    return_value.block_number, return_value.block_hash
+                );
+                Ok(return_value)
             }
             Err(_) => Err(()), // ws error
         }
     }
 
+    /// send an encrypted `TrustedCallSigned` to the worker, bypassing the chain. The worker
+    /// executes it in a sidechain block, which is periodically committed on chain
+    pub fn submit_trusted_call(
+        &self,
+        shard: &ShardIdentifier,
+        cyphertext: Vec<u8>,
+    ) -> Result<(), ()> {
+        let req = ClientRequest::SubmitTrustedCall(shard.to_owned(), cyphertext);
+        match Self::get(&self, req)?.as_str() {
+            "queued" => Ok(()),
+            res => {
+                error!("worker did not accept the call: {}", res);
+                Err(())
+            }
+        }
+    }
+
+    /// get the sidechain block of `shard` with `block_number`, or the latest one. The block
+    /// must be signed by one of the `enclave_signers`
+    pub fn get_sidechain_block(
+        &self,
+        shard: &ShardIdentifier,
+        block_number: Option<u64>,
+        enclave_signers: &[ed25519::Public],
+    ) -> Result<Option<SignedSidechainBlock>, ()> {
+        let req = ClientRequest::SidechainBlock(shard.to_owned(), block_number);
+        let res = Self::get(&self, req)?;
+        let encoded = hex::decode(&res).map_err(|_| {
+            error!(
+                "worker api returned a block that can't be hex decoded: {}",
+                res
+            );
+        })?;
+        let block: Option<SignedSidechainBlock> = Decode::decode(&mut encoded.as_slice())
+            .map_err(|_| error!("worker api returned a block that can't be decoded"))?;
+        match block {
+            Some(b) if !enclave_signers.contains(&b.block.signer) || !b.verify_signature() => {
+                error!("sidechain block is not signed by a registered enclave");
+                Err(())
+            }
+            Some(b) if b.block.shard != *shard => {
+                error!("worker returned a block of another shard");
+                Err(())
+            }
+            block => Ok(block),
+        }
+    }
+
+    /// sends `request` as it is and returns the worker's answer, for workers relaying requests
+    /// to the sidechain producer
+    pub fn forward(&self, request: ClientRequest) -> Result<String, ()> {
+        Self::get(&self, request)
+    }
+
     fn get(&self, request: ClientRequest) -> Result<String, ()> {
         let url = self.url.clone();
         let (port_in, port_out) = channel();
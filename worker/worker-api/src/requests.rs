@@ -23,4 +23,6 @@ pub enum ClientRequest {
     PubKeyWorker,
     MuRaPortWorker,
     StfState(Getter, ShardIdentifier), // (trusted_getter_encrypted, shard)
+    SubmitTrustedCall(ShardIdentifier, Vec<u8>), // (shard, trusted_call_encrypted)
+    SidechainBlock(ShardIdentifier, Option<u64>), // (shard, block_number), latest if None
 }